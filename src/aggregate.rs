@@ -0,0 +1,279 @@
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::ast::{Affinity, FunctionArgs};
+use crate::value::{Collation, Value};
+
+//the aggregate functions, which fold the rows of a group into one value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFunction {
+    //count(*) counts rows, count(x) the rows where x isn't NULL
+    Count,
+    Sum,
+    Total,
+    Avg,
+    Min,
+    Max,
+    //group_concat(x[, separator]), also spelled string_agg(x, separator)
+    GroupConcat,
+}
+
+impl AggregateFunction {
+    //the aggregate a function call names, if it names one: min and max with more than one
+    //argument are the scalar functions instead
+    pub fn from_call(name: &str, args: &FunctionArgs) -> Result<Option<Self>> {
+        let (distinct, arg_count) = match args {
+            FunctionArgs::Star => (false, None),
+            FunctionArgs::List { distinct, args } => (*distinct, Some(args.len())),
+        };
+        let name = name.to_ascii_lowercase();
+        let (function, arity_ok) = match name.as_str() {
+            "count" => (AggregateFunction::Count, matches!(arg_count, None | Some(0 | 1))),
+            "sum" => (AggregateFunction::Sum, arg_count == Some(1)),
+            "total" => (AggregateFunction::Total, arg_count == Some(1)),
+            "avg" => (AggregateFunction::Avg, arg_count == Some(1)),
+            "min" | "max" if arg_count.is_some_and(|count| count > 1) => return Ok(None),
+            "min" => (AggregateFunction::Min, arg_count == Some(1)),
+            "max" => (AggregateFunction::Max, arg_count == Some(1)),
+            "group_concat" => (AggregateFunction::GroupConcat, matches!(arg_count, Some(1 | 2))),
+            "string_agg" => (AggregateFunction::GroupConcat, arg_count == Some(2)),
+            _ => return Ok(None),
+        };
+        if !arity_ok {
+            bail!("wrong number of arguments to function {}()", name);
+        }
+        if distinct && arg_count != Some(1) {
+            bail!("DISTINCT aggregates must have exactly one argument");
+        }
+        Ok(Some(function))
+    }
+}
+
+//the running state of one aggregate call over the rows of one group
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    function: AggregateFunction,
+    //how min and max compare text, and which arguments DISTINCT takes to be the same
+    collation: Collation,
+    //the arguments already seen, as keys of the collation, for aggregates with DISTINCT
+    seen: Option<HashSet<Value>>,
+    //the number of non-NULL arguments (or of rows, for count(*))
+    count: i64,
+    sum: Sum,
+    extreme: Option<Value>,
+    text: Option<String>,
+}
+
+impl Accumulator {
+    pub fn new(function: AggregateFunction, distinct: bool, collation: Collation) -> Self {
+        Accumulator {
+            function,
+            collation,
+            seen: distinct.then(HashSet::new),
+            count: 0,
+            sum: Sum::default(),
+            extreme: None,
+            text: None,
+        }
+    }
+
+    //add one row's arguments; true when the row is now the group's minimum or maximum, which is
+    //the row that bare columns next to a lone min() or max() take their values from
+    pub fn step(&mut self, args: &[Value]) -> bool {
+        let Some(value) = args.first() else {
+            //count(*)
+            self.count += 1;
+            return false;
+        };
+        if value.is_null() {
+            return false;
+        }
+        if let Some(seen) = &mut self.seen {
+            if !seen.insert(value.clone().collation_key(self.collation)) {
+                return false;
+            }
+        }
+        self.count += 1;
+        match self.function {
+            AggregateFunction::Count => false,
+            AggregateFunction::Sum | AggregateFunction::Total | AggregateFunction::Avg => {
+                self.sum.add(value);
+                false
+            },
+            AggregateFunction::Min | AggregateFunction::Max => {
+                let wanted = if self.function == AggregateFunction::Min { Ordering::Less } else { Ordering::Greater };
+                //ties keep the earlier row
+                let replace = self.extreme.as_ref().map_or(true, |extreme| value.compare_collated(extreme, self.collation) == wanted);
+                if replace {
+                    self.extreme = Some(value.clone());
+                }
+                replace
+            },
+            AggregateFunction::GroupConcat => {
+                let value = value.to_text();
+                match &mut self.text {
+                    Some(text) => {
+                        //each value after the first is preceded by the separator of its own row
+                        match args.get(1) {
+                            Some(Value::Null) => {},
+                            Some(separator) => text.push_str(&separator.to_text()),
+                            None => text.push(','),
+                        }
+                        text.push_str(&value);
+                    },
+                    None => self.text = Some(value),
+                }
+                false
+            },
+        }
+    }
+
+    //the aggregate's value over the rows added so far
+    pub fn finish(&self) -> Result<Value> {
+        Ok(match self.function {
+            AggregateFunction::Count => Value::Integer(self.count),
+            //sum of nothing is NULL, but total of nothing is 0.0
+            AggregateFunction::Sum if self.count == 0 => Value::Null,
+            AggregateFunction::Sum => self.sum.value()?,
+            AggregateFunction::Total => Value::Real(self.sum.real()),
+            AggregateFunction::Avg if self.count == 0 => Value::Null,
+            AggregateFunction::Avg => Value::Real(self.sum.real() / self.count as f64),
+            AggregateFunction::Min | AggregateFunction::Max => self.extreme.clone().unwrap_or(Value::Null),
+            AggregateFunction::GroupConcat => self.text.clone().map_or(Value::Null, Value::Text),
+        })
+    }
+}
+
+//a sum that stays an exact integer while every input is one, and otherwise becomes a real
+//accumulated with Kahan-Babuska-Neumaier compensation, as sqlite does
+#[derive(Debug, Clone, Default)]
+struct Sum {
+    integer: i64,
+    real: f64,
+    //the low-order bits lost from `real` so far
+    error: f64,
+    approximate: bool,
+    //an integer sum overflowed, which is an error unless a real comes along
+    overflow: bool,
+}
+
+//integers at least this big lose precision as reals, so they're added in two parts
+const PRECISE_REAL_LIMIT: i64 = 4503599627370496;
+
+impl Sum {
+    fn add(&mut self, value: &Value) {
+        //text that looks like a number counts as that number, and anything else as a real
+        let value = match value {
+            Value::Text(_) => value.clone().apply_affinity(Affinity::Numeric),
+            _ => value.clone(),
+        };
+        match value {
+            Value::Integer(n) if !self.approximate => match self.integer.checked_add(n) {
+                Some(sum) => self.integer = sum,
+                None => {
+                    self.overflow = true;
+                    self.start_approximating();
+                    self.add_integer(n);
+                },
+            },
+            Value::Integer(n) => self.add_integer(n),
+            value => {
+                if self.approximate {
+                    self.overflow = false;
+                } else {
+                    self.start_approximating();
+                }
+                self.add_real(value.to_number().as_real());
+            },
+        }
+    }
+
+    fn start_approximating(&mut self) {
+        self.approximate = true;
+        let n = self.integer;
+        if n.abs() >= PRECISE_REAL_LIMIT {
+            let big = n - n % 16384;
+            self.real = big as f64;
+            self.error = (n - big) as f64;
+        } else {
+            self.real = n as f64;
+            self.error = 0.0;
+        }
+    }
+
+    fn add_integer(&mut self, n: i64) {
+        if n.unsigned_abs() >= PRECISE_REAL_LIMIT as u64 {
+            let small = n % 16384;
+            self.add_real((n - small) as f64);
+            self.add_real(small as f64);
+        } else {
+            self.add_real(n as f64);
+        }
+    }
+
+    fn add_real(&mut self, n: f64) {
+        let sum = self.real + n;
+        if self.real.abs() > n.abs() {
+            self.error += (self.real - sum) + n;
+        } else {
+            self.error += (n - sum) + self.real;
+        }
+        self.real = sum;
+    }
+
+    //the sum as a real, for total() and avg()
+    fn real(&self) -> f64 {
+        if !self.approximate {
+            return self.integer as f64;
+        }
+        if self.error.is_finite() {
+            self.real + self.error
+        } else {
+            self.real
+        }
+    }
+
+    fn value(&self) -> Result<Value> {
+        if self.overflow {
+            bail!("integer overflow");
+        }
+        Ok(if self.approximate { Value::Real(self.real()) } else { Value::Integer(self.integer) })
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_accumulators() {
+    let run_collated = |function, distinct, collation, values: Vec<Value>| {
+        let mut accumulator = Accumulator::new(function, distinct, collation);
+        for value in values {
+            accumulator.step(&[value]);
+        }
+        accumulator.finish()
+    };
+    let run = |function, distinct, values| run_collated(function, distinct, Collation::Binary, values);
+    let values = || vec![Value::Text("5".into()), Value::Integer(2), Value::Null, Value::Real(2.0)];
+    assert!(matches!(run(AggregateFunction::Count, false, values()).unwrap(), Value::Integer(3)));
+    assert!(matches!(run(AggregateFunction::Count, true, values()).unwrap(), Value::Integer(2)));
+    assert!(matches!(run(AggregateFunction::Sum, false, values()).unwrap(), Value::Real(n) if n == 9.0));
+    assert!(matches!(run(AggregateFunction::Sum, false, vec![Value::Text("5".into()), Value::Integer(2)]).unwrap(), Value::Integer(7)));
+    assert!(matches!(run(AggregateFunction::Sum, false, vec![Value::Null]).unwrap(), Value::Null));
+    assert!(matches!(run(AggregateFunction::Total, false, vec![]).unwrap(), Value::Real(n) if n == 0.0));
+    assert!(matches!(run(AggregateFunction::Avg, false, values()).unwrap(), Value::Real(n) if n == 3.0));
+    assert!(matches!(run(AggregateFunction::Max, false, values()).unwrap(), Value::Text(text) if text == "5"));
+    assert!(matches!(run(AggregateFunction::Min, false, values()).unwrap(), Value::Integer(2)));
+    assert!(matches!(run(AggregateFunction::GroupConcat, false, values()).unwrap(), Value::Text(text) if text == "5,2,2.0"));
+
+    //compensated summation, and integer overflow unless a real is involved
+    assert!(matches!(run(AggregateFunction::Sum, false, vec![Value::Real(0.1), Value::Real(0.2), Value::Real(0.3)]).unwrap(), Value::Real(n) if n == 0.6));
+    assert!(run(AggregateFunction::Sum, false, vec![Value::Integer(i64::MAX), Value::Integer(1)]).is_err());
+    assert!(run(AggregateFunction::Sum, false, vec![Value::Integer(i64::MAX), Value::Real(1.0)]).is_ok());
+    assert!(matches!(run(AggregateFunction::Total, false, vec![Value::Integer(i64::MAX), Value::Integer(1)]).unwrap(), Value::Real(_)));
+
+    //DISTINCT takes values that are equal in the collation to be the same, keeping the first of them
+    let texts = || ["a", "A", "b", "B", "c"].iter().map(|text| Value::Text(text.to_string())).collect::<Vec<_>>();
+    assert!(matches!(run(AggregateFunction::Count, true, texts()).unwrap(), Value::Integer(5)));
+    assert!(matches!(run_collated(AggregateFunction::Count, true, Collation::NoCase, texts()).unwrap(), Value::Integer(3)));
+    assert!(matches!(run_collated(AggregateFunction::GroupConcat, true, Collation::NoCase, texts()).unwrap(), Value::Text(text) if text == "a,b,c"));
+}
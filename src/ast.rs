@@ -0,0 +1,549 @@
+//syntax tree produced by the parser in sql.rs and consumed by the executor in main.rs
+
+// ***STATEMENTS***
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(SelectStatement),
+    //EXPLAIN QUERY PLAN SELECT ..., which describes how the SELECT would read its tables instead of running it
+    ExplainQueryPlan(SelectStatement),
+}
+
+// ***SELECT***
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectStatement {
+    //WITH name AS (SELECT ...), ..., tables the statement and its subqueries can read by name
+    pub with: Vec<CommonTableExpression>,
+    //SELECT DISTINCT, which drops rows that repeat an earlier output row
+    pub distinct: bool,
+    pub columns: Vec<ResultColumn>,
+    //None for a SELECT without FROM, which outputs a single row
+    pub from: Option<FromClause>,
+    pub where_clause: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    //WINDOW name AS (...), ..., windows the window functions can name instead of defining their own
+    pub windows: Vec<(String, WindowDefinition)>,
+    //UNION, INTERSECT or EXCEPT and the SELECTs after the first; ORDER BY and LIMIT then apply to all of them
+    pub compound: Vec<CompoundSelect>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Limit>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    //SELECT *
+    Star,
+    //SELECT table.*
+    TableStar(String),
+    Expr { expr: Expr, alias: Option<String> },
+}
+
+//one of the SELECTs after the first in a compound SELECT, which has no WITH, ORDER BY or LIMIT of its own
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompoundSelect {
+    pub operator: CompoundOperator,
+    pub select: SelectStatement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompoundOperator {
+    //UNION drops the rows that repeat an earlier row, which UNION ALL keeps
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+impl CompoundOperator {
+    pub fn name(self) -> &'static str {
+        match self {
+            CompoundOperator::Union => "UNION",
+            CompoundOperator::UnionAll => "UNION ALL",
+            CompoundOperator::Intersect => "INTERSECT",
+            CompoundOperator::Except => "EXCEPT",
+        }
+    }
+}
+
+//name [(column, ...)] AS (SELECT ...) in a WITH clause. When the last SELECT of a compound SELECT reads the table
+//itself after UNION [ALL], the table is recursive: that SELECT runs on each row the table gets to add more
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommonTableExpression {
+    pub name: String,
+    //the names the columns of the SELECT are given instead of their own
+    pub columns: Vec<String>,
+    pub select: Box<SelectStatement>,
+}
+
+//FROM table [join-operator table join-constraint]...
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromClause {
+    pub table: TableOrSubquery,
+    pub joins: Vec<Join>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableOrSubquery {
+    Table(TableName),
+    //FROM (SELECT ...) [AS alias], whose result columns are the columns of the table
+    Subquery { select: Box<SelectStatement>, alias: Option<String> },
+}
+
+//a table in the FROM clause, with the alias it is referred to by
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableName {
+    pub name: String,
+    pub alias: Option<String>,
+}
+
+#[cfg(test)]
+impl TableName {
+    pub fn new(name: &str) -> Self {
+        TableName { name: name.to_string(), alias: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub operator: JoinOperator,
+    pub table: TableOrSubquery,
+    pub constraint: JoinConstraint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinOperator {
+    //JOIN, INNER JOIN, CROSS JOIN and a comma
+    Inner,
+    //LEFT [OUTER] JOIN, which keeps a row of the left side with NULLs when nothing on the right matches
+    Left,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinConstraint {
+    None,
+    On(Expr),
+    //USING (column, ...), which compares the columns of the same name on both sides
+    Using(Vec<String>),
+}
+
+//one key of an ORDER BY clause
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+    //without NULLS FIRST or NULLS LAST, NULLs sort as the smallest values
+    pub nulls_first: bool,
+}
+
+//LIMIT count [OFFSET offset], also written LIMIT offset, count
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub count: Expr,
+    pub offset: Option<Expr>,
+}
+
+// ***EXPRESSIONS***
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Text(String),
+    Integer(i64),
+    Real(f64),
+    Blob(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    Plus,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    //comparisons
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    //IS and IS NOT, which treat NULL as an ordinary value
+    Is,
+    IsNot,
+    //logic
+    And,
+    Or,
+    //arithmetic
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    //bits
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+    //string concatenation, ||
+    Concat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FunctionArgs {
+    //count(*)
+    Star,
+    List { distinct: bool, args: Vec<Expr> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    //a column, optionally qualified with its table name
+    Column { table: Option<String>, name: String },
+    Unary(UnaryOperator, Box<Expr>),
+    Binary(Box<Expr>, BinaryOperator, Box<Expr>),
+    IsNull { expr: Box<Expr>, negated: bool },
+    Function { name: String, args: FunctionArgs },
+    //function(...) OVER window, evaluated for each row over the rows of its window
+    WindowFunction { name: String, args: FunctionArgs, over: Box<Over> },
+    //CASE [operand] WHEN .. THEN .. [ELSE ..] END
+    Case { operand: Option<Box<Expr>>, when_then: Vec<(Expr, Expr)>, else_expr: Option<Box<Expr>> },
+    Cast { expr: Box<Expr>, type_name: String },
+    //expr COLLATE name, which picks the collating sequence used to compare and sort it
+    Collate { expr: Box<Expr>, collation: String },
+    //expr [NOT] IN (...)
+    In { expr: Box<Expr>, list: InList, negated: bool },
+    //(SELECT ...), the first column of the subquery's first row, or NULL when it has none
+    Subquery(Box<SelectStatement>),
+    //EXISTS (SELECT ...), which is true when the subquery has any rows
+    Exists(Box<SelectStatement>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Over {
+    //OVER name, a window of the WINDOW clause
+    Name(String),
+    Window(WindowDefinition),
+}
+
+//([base] [PARTITION BY expr, ...] [ORDER BY ...] [frame])
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowDefinition {
+    //a window of the WINDOW clause that this one adds an ORDER BY or a frame to
+    pub base: Option<String>,
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    //None for the default frame, RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW
+    pub frame: Option<Frame>,
+}
+
+//the rows of its partition a window function reads for a row, from `start` to `end`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameUnits {
+    //the offsets count rows
+    Rows,
+    //the offsets are subtracted from and added to the value of the ORDER BY expression
+    Range,
+    //the offsets count groups of rows with the same ORDER BY values
+    Groups,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(Expr),
+    CurrentRow,
+    Following(Expr),
+    UnboundedFollowing,
+}
+
+impl FrameBound {
+    //where the bound lies relative to the current row; the end of a frame can't lie before its start
+    pub fn position(&self) -> u8 {
+        match self {
+            FrameBound::UnboundedPreceding => 0,
+            FrameBound::Preceding(_) => 1,
+            FrameBound::CurrentRow => 2,
+            FrameBound::Following(_) => 3,
+            FrameBound::UnboundedFollowing => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InList {
+    //IN (1, 2, 3), which can be empty
+    Values(Vec<Expr>),
+    //IN (SELECT ...), whose rows have a single column
+    Select(Box<SelectStatement>),
+}
+
+impl Expr {
+    pub fn column(name: &str) -> Self {
+        Expr::Column { table: None, name: name.to_string() }
+    }
+
+    pub fn qualified_column(table: &str, name: &str) -> Self {
+        Expr::Column { table: Some(table.to_string()), name: name.to_string() }
+    }
+
+    //the expressions directly inside this one
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            //a subquery's expressions belong to it rather than to this one
+            Expr::Literal(_) | Expr::Column { .. } | Expr::Function { args: FunctionArgs::Star, .. } | Expr::Subquery(_) | Expr::Exists(_) => vec![],
+            Expr::Unary(_, expr) | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } | Expr::Collate { expr, .. } => vec![expr],
+            Expr::Binary(left, _, right) => vec![left, right],
+            Expr::Function { args: FunctionArgs::List { args, .. }, .. } => args.iter().collect(),
+            Expr::WindowFunction { args, over, .. } => {
+                let args = match args {
+                    FunctionArgs::List { args, .. } => args.iter().collect(),
+                    FunctionArgs::Star => vec![],
+                };
+                match over.as_ref() {
+                    Over::Name(_) => args,
+                    Over::Window(window) => args.into_iter().chain(window.expressions()).collect(),
+                }
+            },
+            Expr::In { expr, list: InList::Select(_), .. } => vec![expr],
+            Expr::In { expr, list: InList::Values(values), .. } => std::iter::once(expr.as_ref()).chain(values).collect(),
+            Expr::Case { operand, when_then, else_expr } => operand.iter().map(|operand| operand.as_ref())
+                .chain(when_then.iter().flat_map(|(when, then)| [when, then]))
+                .chain(else_expr.iter().map(|else_expr| else_expr.as_ref()))
+                .collect(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Column { .. } | Expr::Function { args: FunctionArgs::Star, .. } | Expr::Subquery(_) | Expr::Exists(_) => vec![],
+            Expr::Unary(_, expr) | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } | Expr::Collate { expr, .. } => vec![expr],
+            Expr::Binary(left, _, right) => vec![left, right],
+            Expr::Function { args: FunctionArgs::List { args, .. }, .. } => args.iter_mut().collect(),
+            Expr::WindowFunction { args, over, .. } => {
+                let args = match args {
+                    FunctionArgs::List { args, .. } => args.iter_mut().collect(),
+                    FunctionArgs::Star => vec![],
+                };
+                match over.as_mut() {
+                    Over::Name(_) => args,
+                    Over::Window(window) => args.into_iter().chain(window.expressions_mut()).collect(),
+                }
+            },
+            Expr::In { expr, list: InList::Select(_), .. } => vec![expr],
+            Expr::In { expr, list: InList::Values(values), .. } => std::iter::once(expr.as_mut()).chain(values).collect(),
+            Expr::Case { operand, when_then, else_expr } => operand.iter_mut().map(|operand| operand.as_mut())
+                .chain(when_then.iter_mut().flat_map(|(when, then)| [when, then]))
+                .chain(else_expr.iter_mut().map(|else_expr| else_expr.as_mut()))
+                .collect(),
+        }
+    }
+}
+
+impl WindowDefinition {
+    //the PARTITION BY and ORDER BY expressions and the frame's offsets
+    pub fn expressions(&self) -> Vec<&Expr> {
+        let bounds = self.frame.iter().flat_map(|frame| [&frame.start, &frame.end]);
+        let offsets = bounds.filter_map(|bound| match bound {
+            FrameBound::Preceding(offset) | FrameBound::Following(offset) => Some(offset),
+            _ => None,
+        });
+        self.partition_by.iter().chain(self.order_by.iter().map(|term| &term.expr)).chain(offsets).collect()
+    }
+
+    pub fn expressions_mut(&mut self) -> Vec<&mut Expr> {
+        let bounds = self.frame.iter_mut().flat_map(|frame| [&mut frame.start, &mut frame.end]);
+        let offsets = bounds.filter_map(|bound| match bound {
+            FrameBound::Preceding(offset) | FrameBound::Following(offset) => Some(offset),
+            _ => None,
+        });
+        self.partition_by.iter_mut().chain(self.order_by.iter_mut().map(|term| &mut term.expr)).chain(offsets).collect()
+    }
+}
+
+// ***TYPES***
+
+//the affinity a declared type gives a column (or a CAST), by the rules in section 3.1 of the datatype docs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Affinity {
+    Integer,
+    Text,
+    Blob,
+    Real,
+    Numeric,
+}
+
+impl Affinity {
+    pub fn from_type_name(type_name: Option<&str>) -> Self {
+        let Some(type_name) = type_name else {
+            return Affinity::Blob;
+        };
+        let type_name = type_name.to_ascii_uppercase();
+        if type_name.contains("INT") {
+            Affinity::Integer
+        } else if ["CHAR", "CLOB", "TEXT"].iter().any(|name| type_name.contains(name)) {
+            Affinity::Text
+        } else if type_name.contains("BLOB") || type_name.is_empty() {
+            Affinity::Blob
+        } else if ["REAL", "FLOA", "DOUB"].iter().any(|name| type_name.contains(name)) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    //a declared type that gives a column this affinity
+    pub fn type_name(self) -> &'static str {
+        match self {
+            Affinity::Integer => "INTEGER",
+            Affinity::Text => "TEXT",
+            Affinity::Blob => "BLOB",
+            Affinity::Real => "REAL",
+            Affinity::Numeric => "NUMERIC",
+        }
+    }
+}
+
+// ***CREATE TABLE***
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    pub constraints: Vec<TableConstraint>,
+    //WITHOUT ROWID tables are stored as index b-trees
+    pub without_rowid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub name: String,
+    pub type_name: Option<String>,
+    pub constraints: Vec<ColumnConstraint>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnConstraint {
+    PrimaryKey { descending: bool, autoincrement: bool },
+    NotNull,
+    Unique,
+    Check(Expr),
+    Default(Expr),
+    Collate(String),
+    References { table: String, columns: Vec<String> },
+    Generated { expr: Expr, stored: bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableConstraint {
+    PrimaryKey(Vec<IndexedColumn>),
+    Unique(Vec<IndexedColumn>),
+    Check(Expr),
+    ForeignKey { columns: Vec<String>, table: String, foreign_columns: Vec<String> },
+}
+
+impl CreateTable {
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name.eq_ignore_ascii_case(name))
+    }
+
+    //position of the INTEGER PRIMARY KEY column, which sqlite stores as an alias for the rowid
+    //(a column declared PRIMARY KEY DESC is the one exception)
+    pub fn rowid_alias(&self) -> Option<usize> {
+        if self.without_rowid {
+            return None;
+        }
+        let table_primary_key = self.constraints.iter().find_map(|constraint| match constraint {
+            TableConstraint::PrimaryKey(columns) if columns.len() == 1 => self.column_index(&columns[0].name),
+            _ => None,
+        });
+        self.columns.iter().enumerate().position(|(column_index, column)| {
+            let declared_integer = column.type_name.as_deref().is_some_and(|type_name| type_name.eq_ignore_ascii_case("integer"));
+            let primary_key = table_primary_key == Some(column_index)
+                || column.constraints.iter().any(|constraint| matches!(constraint, ColumnConstraint::PrimaryKey { descending: false, .. }));
+            declared_integer && primary_key
+        })
+    }
+
+    //the columns of the indexes sqlite makes for the PRIMARY KEY (unless it's the rowid) and UNIQUE constraints,
+    //which it names sqlite_autoindex_<table>_1, _2 and so on: those of the columns first, in the order of the columns,
+    //then those of the table. A constraint on the same columns as an earlier one shares its index. A WITHOUT ROWID
+    //table's rows are found by its PRIMARY KEY, so its indexes are left out
+    pub fn autoindexes(&self) -> Vec<Vec<IndexedColumn>> {
+        if self.without_rowid {
+            return Vec::new();
+        }
+        let rowid_alias = self.rowid_alias();
+        let column_constraints = self.columns.iter().enumerate().filter(|(column_index, _)| rowid_alias != Some(*column_index)).flat_map(|(_, column)| {
+            column.constraints.iter().filter_map(|constraint| {
+                let descending = match constraint {
+                    ColumnConstraint::PrimaryKey { descending, .. } => *descending,
+                    ColumnConstraint::Unique => false,
+                    _ => return None,
+                };
+                Some(vec![IndexedColumn { name: column.name.clone(), collation: None, descending }])
+            })
+        });
+        let table_constraints = self.constraints.iter().filter_map(|constraint| match constraint {
+            TableConstraint::PrimaryKey(columns) if rowid_alias.is_some() && columns.len() == 1 => None,
+            TableConstraint::PrimaryKey(columns) | TableConstraint::Unique(columns) => Some(columns.clone()),
+            _ => None,
+        });
+        let mut indexes: Vec<Vec<IndexedColumn>> = Vec::new();
+        for columns in column_constraints.chain(table_constraints) {
+            let same = |index: &Vec<IndexedColumn>| index.len() == columns.len() && index.iter().zip(&columns).all(|(a, b)| {
+                a.name.eq_ignore_ascii_case(&b.name) && a.collation.as_deref().map(str::to_lowercase) == b.collation.as_deref().map(str::to_lowercase)
+            });
+            if !indexes.iter().any(same) {
+                indexes.push(columns);
+            }
+        }
+        indexes
+    }
+}
+
+impl ColumnDef {
+    pub fn affinity(&self) -> Affinity {
+        Affinity::from_type_name(self.type_name.as_deref())
+    }
+
+    //the collating sequence declared with COLLATE, if any
+    pub fn collation(&self) -> Option<&str> {
+        self.constraints.iter().find_map(|constraint| match constraint {
+            ColumnConstraint::Collate(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+}
+
+// ***CREATE INDEX***
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<IndexedColumn>,
+    pub unique: bool,
+    //the WHERE clause of a partial index, which only has entries for the rows it's true for
+    pub where_clause: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedColumn {
+    pub name: String,
+    //COLLATE name, which orders the index by that collating sequence instead of the column's own
+    pub collation: Option<String>,
+    pub descending: bool,
+}
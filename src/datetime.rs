@@ -0,0 +1,589 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::scalar;
+use crate::value::{self, Value};
+
+//the julian day of the unix epoch, in milliseconds
+const UNIX_EPOCH_JD: i64 = 210866760000000;
+//the last millisecond of 9999-12-31, the latest moment the date functions work with
+const MAX_JD: i64 = 464269060799999;
+
+//a moment as the date and time functions work it out from a time value and its modifiers. Like sqlite,
+//this keeps it as a julian day (in milliseconds since noon on November 24, 4714 BC), as a date and a time
+//of day, or both, and only works out one from the other when it has to: '2024-02-30' is March 1 once it's
+//a julian day, but 'start of month' of it is still February 1
+#[derive(Debug, Default, Clone)]
+struct DateTime {
+    jd: i64,
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: f64,
+    //minutes east of UTC the date and time were given in
+    tz: i64,
+    valid_jd: bool,
+    valid_ymd: bool,
+    valid_hms: bool,
+    //the time value was a number, which the first modifier can still say is a unix time rather than a julian day
+    raw_number: Option<f64>,
+    subsec: bool,
+    error: bool,
+}
+
+impl DateTime {
+    //the moment the arguments of a date function describe: a time value and then its modifiers,
+    //or now when there are none; None when any of them makes no sense or the moment is out of range
+    fn from_args(args: &[Value]) -> Option<Self> {
+        let mut date = DateTime::default();
+        match args.first() {
+            None => date.set_now(),
+            Some(Value::Null) => return None,
+            Some(Value::Integer(n)) => date.set_number(*n as f64),
+            Some(Value::Real(n)) => date.set_number(*n),
+            Some(text) => date.parse(&text.to_text())?,
+        }
+        for (index, modifier) in args.iter().enumerate().skip(1) {
+            if modifier.is_null() {
+                return None;
+            }
+            date.apply_modifier(&modifier.to_text(), index)?;
+        }
+        date.compute_jd();
+        if date.error || !(0..=MAX_JD).contains(&date.jd) {
+            return None;
+        }
+        //a date past the end of its month, like 2023-02-31, is read as the days after it: 2023-03-03
+        if args.len() == 1 && date.valid_ymd && date.day > 28 {
+            date.valid_ymd = false;
+        }
+        Some(date)
+    }
+
+    fn set_now(&mut self) {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.jd = UNIX_EPOCH_JD + since_epoch.as_millis() as i64;
+        self.valid_jd = true;
+    }
+
+    //a number as a time value is a julian day, unless a modifier says otherwise
+    fn set_number(&mut self, n: f64) {
+        self.raw_number = Some(n);
+        if (0.0..5373484.5).contains(&n) {
+            self.jd = (n * 86400000.0 + 0.5) as i64;
+            self.valid_jd = true;
+        }
+    }
+
+    //YYYY-MM-DD with an optional time, HH:MM with optional seconds, 'now', or a number
+    fn parse(&mut self, text: &str) -> Option<()> {
+        if self.parse_date(text).is_some() || self.parse_time(text).is_some() {
+            return Some(());
+        }
+        if text.eq_ignore_ascii_case("now") {
+            self.set_now();
+        } else if let Some(n) = value::numeric_text(text) {
+            self.set_number(n.as_real());
+        } else if text.eq_ignore_ascii_case("subsec") || text.eq_ignore_ascii_case("subsecond") {
+            self.subsec = true;
+            self.set_now();
+        } else {
+            return None;
+        }
+        Some(())
+    }
+
+    //YYYY-MM-DD, then optionally a time after spaces or a T
+    fn parse_date(&mut self, text: &str) -> Option<()> {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        let year = digits(text, 0, 4, 0, 9999)?;
+        let month = separated_digits(text, 4, '-', 1, 12)?;
+        let day = separated_digits(text, 7, '-', 1, 31)?;
+        let rest = text[10..].trim_start_matches(|c: char| c.is_ascii_whitespace() || c == 'T');
+        if self.parse_time(rest).is_none() {
+            if !rest.is_empty() {
+                return None;
+            }
+            self.valid_hms = false;
+        }
+        self.valid_jd = false;
+        self.valid_ymd = true;
+        self.year = if negative { -year } else { year };
+        self.month = month;
+        self.day = day;
+        Some(())
+    }
+
+    //HH:MM, optionally with :SS and a fraction of a second, then optionally a time zone
+    fn parse_time(&mut self, text: &str) -> Option<()> {
+        let hour = digits(text, 0, 2, 0, 24)?;
+        let minute = separated_digits(text, 2, ':', 0, 59)?;
+        let mut rest = &text[5..];
+        let mut second = 0.0;
+        if let Some(seconds) = rest.strip_prefix(':') {
+            second = digits(seconds, 0, 2, 0, 59)? as f64;
+            rest = &seconds[2..];
+            if let Some(fraction) = rest.strip_prefix('.').filter(|fraction| fraction.starts_with(|c: char| c.is_ascii_digit())) {
+                let fraction_digits = fraction.len() - fraction.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                //any digits past the milliseconds are cut off rather than rounded
+                let fraction_value: f64 = format!("0.{}", &fraction[..fraction_digits]).parse().unwrap_or(0.0);
+                second += fraction_value.min(0.999);
+                rest = &fraction[fraction_digits..];
+            }
+        }
+        self.valid_jd = false;
+        self.raw_number = None;
+        self.valid_hms = true;
+        self.hour = hour;
+        self.minute = minute;
+        self.second = second;
+        self.parse_time_zone(rest)
+    }
+
+    //nothing, Z, or +HH:MM / -HH:MM, with spaces around it
+    fn parse_time_zone(&mut self, text: &str) -> Option<()> {
+        let text = text.trim_start_matches(|c: char| c.is_ascii_whitespace());
+        self.tz = 0;
+        let rest = match text.chars().next() {
+            None => return Some(()),
+            Some('Z' | 'z') => &text[1..],
+            Some(sign @ ('+' | '-')) => {
+                let hours = digits(text, 1, 2, 0, 14)?;
+                let minutes = separated_digits(text, 3, ':', 0, 59)?;
+                self.tz = (hours * 60 + minutes) * if sign == '-' { -1 } else { 1 };
+                &text[6..]
+            },
+            Some(_) => return None,
+        };
+        rest.trim_start_matches(|c: char| c.is_ascii_whitespace()).is_empty().then_some(())
+    }
+
+    //apply the `index`th argument, which is one of sqlite's modifiers
+    fn apply_modifier(&mut self, modifier: &str, index: usize) -> Option<()> {
+        let lower = modifier.to_ascii_lowercase();
+        match lower.as_str() {
+            //a number time value is a julian day, which it was read as already
+            "julianday" => {
+                if index > 1 || !self.valid_jd || self.raw_number.is_none() {
+                    return None;
+                }
+                self.raw_number = None;
+            },
+            //a number time value is seconds since 1970
+            "unixepoch" => {
+                let n = self.raw_number.filter(|_| index == 1)?;
+                self.set_unix_time(n)?;
+            },
+            //a number time value is a julian day if it can be one, and a unix time otherwise
+            "auto" => {
+                if index > 1 {
+                    return None;
+                }
+                match self.raw_number {
+                    Some(n) if !self.valid_jd => {
+                        if !(-210866760000.0..=253402300799.0).contains(&n) {
+                            return None;
+                        }
+                        self.set_unix_time(n)?;
+                    },
+                    _ => self.raw_number = None,
+                }
+            },
+            //without a time zone database local time is UTC, so these only settle the moment
+            "localtime" | "utc" => {
+                self.compute_jd();
+                self.clear_ymd_hms_tz();
+                self.raw_number = None;
+            },
+            "subsec" | "subsecond" => self.subsec = true,
+            _ if lower.starts_with("start of ") => {
+                if !self.valid_jd && !self.valid_ymd && !self.valid_hms {
+                    return None;
+                }
+                self.compute_ymd();
+                self.valid_hms = true;
+                self.hour = 0;
+                self.minute = 0;
+                self.second = 0.0;
+                self.raw_number = None;
+                self.tz = 0;
+                self.valid_jd = false;
+                match &lower[9..] {
+                    "month" => self.day = 1,
+                    "year" => {
+                        self.month = 1;
+                        self.day = 1;
+                    },
+                    "day" => {},
+                    _ => return None,
+                }
+            },
+            //the next day (or this one) that is day N of the week, Sunday being 0
+            _ if lower.starts_with("weekday ") => {
+                let weekday = value::numeric_text(&lower[8..]).map(|n| n.as_real()).filter(|n| (0.0..7.0).contains(n) && n.fract() == 0.0)? as i64;
+                self.compute_ymd_hms();
+                self.tz = 0;
+                self.valid_jd = false;
+                self.compute_jd();
+                let mut day = ((self.jd + 129600000) / 86400000) % 7;
+                if day > weekday {
+                    day -= 7;
+                }
+                self.jd += (weekday - day) * 86400000;
+                self.clear_ymd_hms_tz();
+            },
+            _ if lower.starts_with(|c: char| c == '+' || c == '-' || c.is_ascii_digit()) => self.add(&lower)?,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn set_unix_time(&mut self, seconds: f64) -> Option<()> {
+        let jd = seconds * 1000.0 + UNIX_EPOCH_JD as f64;
+        if !(0.0..MAX_JD as f64 + 1.0).contains(&jd) {
+            return None;
+        }
+        self.clear_ymd_hms_tz();
+        self.jd = (jd + 0.5) as i64;
+        self.valid_jd = true;
+        self.raw_number = None;
+        Some(())
+    }
+
+    //'±N unit', where the unit is days, hours, minutes, seconds, months or years (with or without the s),
+    //or '±HH:MM[:SS[.SSS]]'
+    fn add(&mut self, modifier: &str) -> Option<()> {
+        let number_end = modifier[1..].find(|c: char| c == ':' || c.is_ascii_whitespace()).map_or(modifier.len(), |end| end + 1);
+        let n = value::numeric_text(&modifier[..number_end])?.as_real();
+        if modifier[number_end..].starts_with(':') {
+            let time = modifier.strip_prefix(['+', '-']).unwrap_or(modifier);
+            let mut offset = DateTime::default();
+            offset.parse_time(time)?;
+            offset.compute_jd();
+            let millis = (offset.jd - 43200000).rem_euclid(86400000);
+            self.compute_jd();
+            self.clear_ymd_hms_tz();
+            self.jd += if modifier.starts_with('-') { -millis } else { millis };
+            return Some(());
+        }
+        let unit = modifier[number_end..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+        if !(3..=10).contains(&unit.len()) {
+            return None;
+        }
+        let unit = unit.strip_suffix('s').unwrap_or(unit);
+        //the seconds in each unit, and how many of it can be added without leaving the range of julian days
+        let (seconds, limit) = match unit {
+            "second" => (1.0, 4.6427e14),
+            "minute" => (60.0, 7.7379e12),
+            "hour" => (3600.0, 1.2897e11),
+            "day" => (86400.0, 5373485.0),
+            "month" => (2592000.0, 176546.0),
+            "year" => (31536000.0, 14713.0),
+            _ => return None,
+        };
+        if n <= -limit || n >= limit {
+            return None;
+        }
+        self.compute_jd();
+        let mut n = n;
+        //whole months and years move the date and keep the day of the month, which may then overflow into the next
+        if unit == "month" || unit == "year" {
+            self.compute_ymd_hms();
+            let whole = n.trunc() as i64;
+            if unit == "month" {
+                self.month += whole;
+                let years = if self.month > 0 { (self.month - 1) / 12 } else { (self.month - 12) / 12 };
+                self.year += years;
+                self.month -= years * 12;
+            } else {
+                self.year += whole;
+            }
+            self.valid_jd = false;
+            n -= whole as f64;
+        }
+        self.compute_jd();
+        let rounder = if n < 0.0 { -0.5 } else { 0.5 };
+        self.jd += (n * 1000.0 * seconds + rounder) as i64;
+        self.clear_ymd_hms_tz();
+        Some(())
+    }
+
+    fn compute_jd(&mut self) {
+        if self.valid_jd {
+            return;
+        }
+        let (mut year, mut month, day) = if self.valid_ymd { (self.year, self.month, self.day) } else { (2000, 1, 1) };
+        if !(-4713..=9999).contains(&year) || self.raw_number.is_some() {
+            self.error = true;
+            return;
+        }
+        if month <= 2 {
+            year -= 1;
+            month += 12;
+        }
+        let a = (year + 4800) / 100;
+        let b = 38 - a + a / 4;
+        let x1 = 36525 * (year + 4716) / 100;
+        let x2 = 306001 * (month + 1) / 10000;
+        self.jd = (((x1 + x2 + day + b) as f64 - 1524.5) * 86400000.0) as i64;
+        self.valid_jd = true;
+        if self.valid_hms {
+            self.jd += self.hour * 3600000 + self.minute * 60000 + (self.second * 1000.0 + 0.5) as i64;
+            if self.tz != 0 {
+                self.jd -= self.tz * 60000;
+                self.valid_ymd = false;
+                self.valid_hms = false;
+                self.tz = 0;
+            }
+        }
+    }
+
+    fn compute_ymd(&mut self) {
+        if self.valid_ymd {
+            return;
+        }
+        if !self.valid_jd {
+            self.year = 2000;
+            self.month = 1;
+            self.day = 1;
+        } else if !(0..=MAX_JD).contains(&self.jd) {
+            self.error = true;
+            return;
+        } else {
+            let z = (self.jd + 43200000) / 86400000;
+            let alpha = ((z as f64 + 32044.75) / 36524.25) as i64 - 52;
+            let a = z + 1 + alpha - ((alpha + 100) / 4) + 25;
+            let b = a + 1524;
+            let c = ((b as f64 - 122.1) / 365.25) as i64;
+            let d = (36525 * (c & 32767)) / 100;
+            let e = ((b - d) as f64 / 30.6001) as i64;
+            let x1 = (30.6001 * e as f64) as i64;
+            self.day = b - d - x1;
+            self.month = if e < 14 { e - 1 } else { e - 13 };
+            self.year = if self.month > 2 { c - 4716 } else { c - 4715 };
+        }
+        self.valid_ymd = true;
+    }
+
+    fn compute_hms(&mut self) {
+        if self.valid_hms {
+            return;
+        }
+        self.compute_jd();
+        let day_millis = (self.jd + 43200000) % 86400000;
+        self.second = (day_millis % 60000) as f64 / 1000.0;
+        let day_minutes = day_millis / 60000;
+        self.minute = day_minutes % 60;
+        self.hour = day_minutes / 60;
+        self.raw_number = None;
+        self.valid_hms = true;
+    }
+
+    fn compute_ymd_hms(&mut self) {
+        self.compute_ymd();
+        self.compute_hms();
+    }
+
+    fn clear_ymd_hms_tz(&mut self) {
+        self.valid_ymd = false;
+        self.valid_hms = false;
+        self.tz = 0;
+    }
+
+    fn date(&self) -> String {
+        match self.year < 0 {
+            true => format!("-{:04}-{:02}-{:02}", -self.year, self.month, self.day),
+            false => format!("{:04}-{:02}-{:02}", self.year, self.month, self.day),
+        }
+    }
+
+    //HH:MM:SS, with milliseconds when subsec asked for them
+    fn time(&self) -> String {
+        match self.subsec {
+            true => format!("{:02}:{:02}:{:06.3}", self.hour, self.minute, self.second),
+            false => format!("{:02}:{:02}:{:02}", self.hour, self.minute, self.second as i64),
+        }
+    }
+
+    fn julian_day(&self) -> f64 {
+        self.jd as f64 / 86400000.0
+    }
+
+    //whole days since January 1 of the same year
+    fn days_after_jan01(&self) -> i64 {
+        let mut jan01 = self.clone();
+        jan01.month = 1;
+        jan01.day = 1;
+        jan01.valid_jd = false;
+        jan01.compute_jd();
+        (self.jd - jan01.jd + 43200000) / 86400000
+    }
+
+    fn days_after_monday(&self) -> i64 {
+        ((self.jd + 43200000) / 86400000) % 7
+    }
+
+    fn days_after_sunday(&self) -> i64 {
+        ((self.jd + 129600000) / 86400000) % 7
+    }
+
+    //the Thursday of the same ISO week, whose year is the week's year
+    fn iso_week_thursday(&self) -> DateTime {
+        let mut thursday = self.clone();
+        thursday.jd += (3 - self.days_after_monday()) * 86400000;
+        thursday.valid_ymd = false;
+        thursday.compute_ymd();
+        thursday
+    }
+
+    //the moment formatted with strftime's substitutions; None for a % it doesn't know
+    fn strftime(&self, format: &str) -> Option<String> {
+        let mut output = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                output.push(c);
+                continue;
+            }
+            let twelve_hour = match self.hour % 12 {
+                0 => 12,
+                hour => hour,
+            };
+            let text = match chars.next()? {
+                'd' => format!("{:02}", self.day),
+                'e' => format!("{:2}", self.day),
+                'f' => format!("{:06.3}", self.second.min(59.999)),
+                'F' => self.date(),
+                'H' => format!("{:02}", self.hour),
+                'k' => format!("{:2}", self.hour),
+                'I' => format!("{:02}", twelve_hour),
+                'l' => format!("{:2}", twelve_hour),
+                'j' => format!("{:03}", self.days_after_jan01() + 1),
+                'J' => scalar::printf("%.16g", &[Value::Real(self.julian_day())]),
+                'm' => format!("{:02}", self.month),
+                'M' => format!("{:02}", self.minute),
+                'p' => if self.hour >= 12 { "PM" } else { "AM" }.to_string(),
+                'P' => if self.hour >= 12 { "pm" } else { "am" }.to_string(),
+                'R' => format!("{:02}:{:02}", self.hour, self.minute),
+                's' => match self.subsec {
+                    true => format!("{:.3}", (self.jd - UNIX_EPOCH_JD) as f64 / 1000.0),
+                    false => (self.jd / 1000 - UNIX_EPOCH_JD / 1000).to_string(),
+                },
+                'S' => format!("{:02}", self.second as i64),
+                'T' => format!("{:02}:{:02}:{:02}", self.hour, self.minute, self.second as i64),
+                'u' => (self.days_after_monday() + 1).to_string(),
+                'w' => self.days_after_sunday().to_string(),
+                'U' => format!("{:02}", (self.days_after_jan01() - self.days_after_sunday() + 7) / 7),
+                'W' => format!("{:02}", (self.days_after_jan01() - self.days_after_monday() + 7) / 7),
+                'V' => format!("{:02}", self.iso_week_thursday().days_after_jan01() / 7 + 1),
+                'G' => format!("{:04}", self.iso_week_thursday().year),
+                'g' => format!("{:02}", self.iso_week_thursday().year % 100),
+                'Y' => format!("{:04}", self.year),
+                '%' => "%".to_string(),
+                _ => return None,
+            };
+            output.push_str(&text);
+        }
+        Some(output)
+    }
+}
+
+//`count` digits at `start` of `text` making a number between `min` and `max`
+fn digits(text: &str, start: usize, count: usize, min: i64, max: i64) -> Option<i64> {
+    let digits = text.get(start..start + count).filter(|digits| digits.bytes().all(|byte| byte.is_ascii_digit()))?;
+    digits.parse().ok().filter(|n| (min..=max).contains(n))
+}
+
+//two digits after a separator at `start`
+fn separated_digits(text: &str, start: usize, separator: char, min: i64, max: i64) -> Option<i64> {
+    text.get(start..)?.starts_with(separator).then_some(())?;
+    digits(text, start + 1, 2, min, max)
+}
+
+//date(time, modifiers...): YYYY-MM-DD
+pub fn date(args: &[Value]) -> Value {
+    match DateTime::from_args(args) {
+        Some(mut date) => {
+            date.compute_ymd();
+            Value::Text(date.date())
+        },
+        None => Value::Null,
+    }
+}
+
+//time(time, modifiers...): HH:MM:SS
+pub fn time(args: &[Value]) -> Value {
+    match DateTime::from_args(args) {
+        Some(mut date) => {
+            date.compute_hms();
+            Value::Text(date.time())
+        },
+        None => Value::Null,
+    }
+}
+
+//datetime(time, modifiers...): YYYY-MM-DD HH:MM:SS
+pub fn datetime(args: &[Value]) -> Value {
+    match DateTime::from_args(args) {
+        Some(mut date) => {
+            date.compute_ymd_hms();
+            Value::Text(format!("{} {}", date.date(), date.time()))
+        },
+        None => Value::Null,
+    }
+}
+
+//julianday(time, modifiers...): the days since noon on November 24, 4714 BC
+pub fn julian_day(args: &[Value]) -> Value {
+    DateTime::from_args(args).map_or(Value::Null, |date| Value::Real(date.julian_day()))
+}
+
+//unixepoch(time, modifiers...): the seconds since 1970, with a fraction only when subsec asks for it
+pub fn unix_epoch(args: &[Value]) -> Value {
+    match DateTime::from_args(args) {
+        Some(date) if date.subsec => Value::Real((date.jd - UNIX_EPOCH_JD) as f64 / 1000.0),
+        Some(date) => Value::Integer(date.jd / 1000 - UNIX_EPOCH_JD / 1000),
+        None => Value::Null,
+    }
+}
+
+//strftime(format, time, modifiers...)
+pub fn strftime(args: &[Value]) -> Value {
+    if args[0].is_null() {
+        return Value::Null;
+    }
+    let Some(mut date) = DateTime::from_args(&args[1..]) else { return Value::Null };
+    date.compute_ymd_hms();
+    date.strftime(&args[0].to_text()).map_or(Value::Null, Value::Text)
+}
+
+#[cfg(test)]
+#[test]
+fn test_date_functions() {
+    let text = |text: &str| Value::Text(text.to_string());
+    let call = |function: fn(&[Value]) -> Value, args: &[&str]| function(&args.iter().map(|arg| text(arg)).collect::<Vec<_>>()).to_string();
+    assert_eq!(call(date, &["2024-02-30"]), "2024-03-01");
+    assert_eq!(call(date, &["2024-02-30", "start of month"]), "2024-02-01");
+    assert_eq!(call(date, &["2024-01-31", "+1 month"]), "2024-03-02");
+    assert_eq!(call(date, &["2024-05-15", "weekday 0"]), "2024-05-19");
+    assert_eq!(call(datetime, &["2024-03-10 12:00:00-05:00"]), "2024-03-10 17:00:00");
+    assert_eq!(call(datetime, &["2024-01-01", "+1.5 days"]), "2024-01-02 12:00:00");
+    assert_eq!(call(datetime, &["2024-01-01 10:00", "-01:30"]), "2024-01-01 08:30:00");
+    assert_eq!(call(datetime, &["2024-01-01T10:00Z", "start of year", "localtime"]), "2024-01-01 00:00:00");
+    assert_eq!(call(time, &["12:34:56.789", "subsec"]), "12:34:56.789");
+    assert_eq!(call(julian_day, &["2000-01-01"]), "2451544.5");
+    assert_eq!(call(unix_epoch, &["2024-01-01 00:00:00.5", "subsec"]), "1704067200.5");
+    assert_eq!(call(strftime, &["%j %W %U %V %G %u %w %J %s %f %e %k %l %p", "2024-01-07 15:04:05.25"]),
+        "007 01 01 01 2024 7 0 2460317.127838542 1704639845 05.250  7 15  3 PM");
+    assert_eq!(datetime(&[Value::Integer(1700000000), text("unixepoch")]).to_string(), "2023-11-14 22:13:20");
+    assert_eq!(datetime(&[Value::Integer(1700000000), text("auto")]).to_string(), "2023-11-14 22:13:20");
+    assert!(datetime(&[Value::Integer(1700000000)]).is_null());
+    assert!(date(&[text("2024-13-01")]).is_null());
+    assert!(date(&[text(" 2024-01-01")]).is_null());
+    assert!(date(&[text("2024-01-01"), text("+1 fortnight")]).is_null());
+    assert!(strftime(&[text("%Q"), text("2024-01-01")]).is_null());
+}
@@ -1,12 +1,28 @@
-#![allow(dead_code)]
-
+mod aggregate;
+mod ast;
+mod datetime;
+mod scalar;
+mod sort;
 mod sql;
+mod value;
+mod window;
 
+use aggregate::{Accumulator, AggregateFunction};
 use anyhow::{bail, Ok, Result};
+use ast::{Affinity, BinaryOperator, ColumnConstraint, ColumnDef, CommonTableExpression, CompoundOperator, CompoundSelect, CreateIndex, CreateTable, Expr, FrameBound, FrameUnits, FromClause, FunctionArgs, InList, JoinConstraint, JoinOperator, Limit, Literal, OrderingTerm, Over, ResultColumn, SelectStatement, Statement, TableName, TableOrSubquery, UnaryOperator, WindowDefinition};
+use scalar::ScalarFunction;
+use sort::{SortKey, SortQueue, SortRow, Sorter};
+use value::{Collation, Number, Value};
+use window::{Window, WindowFunction};
 // use std::env::VarError;
 // use core::num;
 // use std::collections::btree_map::Range;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::File;
+use std::ops::{Bound, ControlFlow};
+use std::rc::Rc;
 use std::io::prelude::*;
 use std::vec;
 
@@ -14,213 +30,799 @@ use std::vec;
 
 #[derive(Debug)]
 struct Database {
-    page_size: u16,
-    num_pages: u16,
-    file: File
+    page_size: u32,
+    //page size minus the reserved space at the end of each page
+    usable_size: u32,
+    //every read seeks first, so reading through a shared reference is enough, and the subqueries
+    //of a query can read the file while the query's own cursors are part way through it
+    file: File,
+    //how many b-tree pages have been read, for the tests to check what a query reads
+    pages_read: Cell<usize>,
 }
 
 impl Database {
     fn new(file_name: &str) -> Result<Self> {
         let mut file = File::open(file_name)?;
         let mut header = [0; 100];
-        file.read_exact(&mut header).unwrap(); 
-        let page_size = u16::from_be_bytes([header[16], header[17]]);
-        let mut b_tree_header = [0;12];
-        file.read_exact(&mut b_tree_header).unwrap();
-        let num_pages = u16::from_be_bytes([b_tree_header[3],b_tree_header[4]]);
+        file.read_exact(&mut header)?;
+        //every database file starts with the same 16 bytes
+        if !header.starts_with(b"SQLite format 3\0") {
+            bail!("file is not a database");
+        }
+        //a page size of 1 represents 65536, which doesn't fit in two bytes
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65536,
+            n => n as u32,
+        };
+        if !page_size.is_power_of_two() || page_size < 512 {
+            bail!("invalid page size {}", page_size);
+        }
+        let usable_size = page_size - header[20] as u32;
         Ok(Self {
             page_size,
-            num_pages,
-            file
+            usable_size,
+            file,
+            pages_read: Cell::new(0),
         }) 
     }
 
-    fn read_page(&mut self, page_index:u16) -> Result<Page> {
-        //pages are one-indexed and so need to be rolled back by 1
-        let page_offset = (page_index-1) * self.page_size;
-        //for schema table
-        if page_index == 1 {
-            self.file.seek(std::io::SeekFrom::Start(page_offset as u64 + 100))?;
-        }
-        //all other tables
-        else {
-            self.file.seek(std::io::SeekFrom::Start(page_offset as u64))?;
-        }
-        //The b-tree page header is 8 bytes in size for leaf pages and 12 bytes for interior pages. 
-        //TODO: fix this for interior pages later
-        // let page_header_size = 8;
-
-        let mut page_header = [0u8;8];
-        self.file.read_exact(&mut page_header)?;
-        let page_type = u8::from_be_bytes([page_header[0]]);
-        let num_cells = u16::from_be_bytes([page_header[3],page_header[4]]);
-        // 2 (0x02) means the page is an interior index b-tree page, 5 (0x05): interior table b-tree page, 10 (0x0a): leaf index b-tree page, 13 (0x0d): leaf table b-tree page. 
-        match page_type {
-            0x0d => {
-                //get the cell pointer array
-                // let cpa_start = 8;
-                let cpa_size = 2*num_cells;
-                // let mut page_1 = vec![100;page_size];
-                // file.read_exact(&mut page_1).unwrap();
-                //chain these later
-                let mut cell_pointer_span = vec![0u8;cpa_size as usize];
-                self.file.read_exact(&mut cell_pointer_span)?;
-                //using chunks_exact(2) because these are 2-byte values
-                let cell_pointers = cell_pointer_span.chunks_exact(2);
-                // get usize from be_bytes
-                let cell_pointers_bytes = cell_pointers.map(|i| u16::from_be_bytes([i[0],i[1]]) as usize);
-                //collect into array
-                let cell_pointer_array: Vec<usize> = cell_pointers_bytes.collect();
-                let mut cells: Vec<TableLeafCell> = Vec::with_capacity(num_cells as usize);
-                for cell_pointer in cell_pointer_array {
-                    let cell = self.read_table_leaf_cell(page_index as u32, cell_pointer as u16)?;
-                    cells.push(cell);
-                }
-                return Ok(Page::TableLeaf { cells });
+    //read a b-tree page whole; its cells are decoded as they're asked for
+    fn read_page(&self, page_index:u32) -> Result<Page> {
+        self.pages_read.set(self.pages_read.get() + 1);
+        let mut data = vec![0u8; self.page_size as usize];
+        (&self.file).seek(std::io::SeekFrom::Start(self.page_offset(page_index)))?;
+        (&self.file).read_exact(&mut data)?;
+        //the schema table's page comes after the 100-byte database header
+        let header_start = if page_index == 1 { 100 } else { 0 };
+        let page_type = data[header_start];
+        let num_cells = u16::from_be_bytes([data[header_start + 3], data[header_start + 4]]) as usize;
+        // 2 (0x02) means the page is an interior index b-tree page, 5 (0x05): interior table b-tree page, 10 (0x0a): leaf index b-tree page, 13 (0x0d): leaf table b-tree page.
+        //the b-tree page header is 8 bytes in size for leaf pages and 12 bytes for interior pages,
+        //which carry the right-most pointer in its last 4 bytes
+        let (header_size, right_most_pointer) = match page_type {
+            0x02 | 0x05 => (12, Some(u32::from_be_bytes(data[header_start + 8..header_start + 12].try_into()?))),
+            0x0a | 0x0d => (8, None),
+            _ => bail!("unknown b-tree page type {:#04x} on page {}", page_type, page_index),
+        };
+        //the cell pointer array comes straight after the header
+        let cell_pointers = header_start + header_size;
+        if cell_pointers + 2 * num_cells > data.len() {
+            bail!("the cell pointer array of page {} runs past the end of the page", page_index);
+        }
+        Ok(Page { page_index, page_type, data, cell_pointers, num_cells, right_most_pointer })
+    }
+
+    //byte offset of the start of a page in the file
+    fn page_offset(&self, page_index:u32) -> u64 {
+        (page_index as u64 - 1) * self.page_size as u64
+    }
+
+    //number of payload bytes stored on the b-tree page itself, from the file format spec:
+    //U is the usable size, P the payload size and X the most that can be kept locally
+    fn local_payload_size(&self, payload_size: u64, max_local: u32) -> u64 {
+        let usable_size = self.usable_size as u64;
+        let max_local = max_local as u64;
+        if payload_size <= max_local {
+            return payload_size;
+        }
+        //M is the least that has to be kept locally once the payload spills
+        let min_local = ((usable_size - 12) * 32 / 255) - 23;
+        //K makes the overflow fill whole overflow pages where possible
+        let k = min_local + ((payload_size - min_local) % (usable_size - 4));
+        if k <= max_local {
+            k
+        } else {
+            min_local
+        }
+    }
+
+    //the full payload of a cell, from the cell's bytes starting at the payload, following the overflow chain if it spills
+    fn read_payload(&self, cell: &[u8], payload_size: u64, max_local: u32) -> Result<Vec<u8>> {
+        let local_size = self.local_payload_size(payload_size, max_local) as usize;
+        let Some(local) = cell.get(..local_size) else {
+            bail!("cell payload runs past the end of the page");
+        };
+        let mut payload = local.to_vec();
+        if local_size as u64 == payload_size {
+            return Ok(payload);
+        }
+
+        //a 4-byte page number for the first overflow page follows the local payload
+        let Some(pointer_bytes) = cell.get(local_size..local_size + 4) else {
+            bail!("cell overflow pointer runs past the end of the page");
+        };
+        let mut overflow_page = u32::from_be_bytes(pointer_bytes.try_into()?);
+        payload.reserve(payload_size as usize - local_size);
+        //each overflow page starts with the next page number (0 on the last page) followed by content
+        while payload.len() < payload_size as usize {
+            if overflow_page == 0 {
+                bail!("overflow chain ended before the end of the payload");
+            }
+            let content_size = (payload_size as usize - payload.len()).min(self.usable_size as usize - 4);
+            let mut page = vec![0u8; 4 + content_size];
+            (&self.file).seek(std::io::SeekFrom::Start(self.page_offset(overflow_page)))?;
+            (&self.file).read_exact(&mut page)?;
+            payload.extend_from_slice(&page[4..]);
+            overflow_page = u32::from_be_bytes(page[..4].try_into()?);
+        }
+        Ok(payload)
+    }
+
+    //find a row of the table b-tree rooted at `root_page` by descending through the interior cell keys
+    fn read_row(&self, root_page: u32, row_id: i64) -> Result<Option<TableLeafCell>> {
+        let mut page_index = root_page;
+        loop {
+            let page = self.read_page(page_index)?;
+            match page.page_type {
+                0x05 => {
+                    //the first cell whose key is >= the rowid has it in its left subtree
+                    let cell_index = page.partition_point(|cell_index| Ok(page.table_interior_key(cell_index)? < row_id))?;
+                    page_index = page.child(cell_index)?;
+                },
+                0x0d => {
+                    //only the record of the row found is decoded
+                    let cell_index = page.partition_point(|cell_index| Ok(page.table_leaf_row_id(cell_index)? < row_id))?;
+                    if cell_index < page.num_cells && page.table_leaf_row_id(cell_index)? == row_id {
+                        return Ok(Some(page.table_leaf_cell(self, cell_index)?));
+                    }
+                    return Ok(None);
+                },
+                _ => bail!("page {} is not a table b-tree page", page_index)
             }
-            _ => {bail!("Haven't implemented this page type yet")}
         }
     }
-    fn read_table_leaf_cell(&mut self, page_index:u32, cell_pointer:u16) -> Result<TableLeafCell> {
-        let page_offset = (page_index-1) * self.page_size as u32;
-        let offset = page_offset + cell_pointer as u32;
-        self.file.seek(std::io::SeekFrom::Start(offset as u64))?;
 
-        //TODO: reorganize this varint reading + cursor adjustment into a method
-        //get payload size
-        // always pass in 9 bytes
-        let mut possible_bytes = [0u8;9];
-        self.file.read_exact(&mut possible_bytes)?;
-        let (_payload_size, ps_len) = handle_varint(&possible_bytes).unwrap();
-        //need to adjust cursor based on length of varint
-        let mut new_offset = offset + ps_len as u32;
-        self.file.seek(std::io::SeekFrom::Start(new_offset as u64))?;
+    fn get_schema_table(&self) -> Result<Vec<Schema>> {
+        let mut db_tables = Vec::new();
+        //the schema table is rooted at page 1 and can span several pages itself
+        let mut cursor = TableCursor::new(1);
+        while let Some(cell) = cursor.next(self)? {
+            db_tables.push(Schema::from_cell(&cell)?);
+        }
+        Ok(db_tables)
+    }
+}
 
-        //get row id
-        self.file.read_exact(&mut possible_bytes)?;
-        let (row_id, row_id_len) = handle_varint(&possible_bytes).unwrap();
-        new_offset += row_id_len as u32;
-        self.file.seek(std::io::SeekFrom::Start(new_offset as u64))?;  
+//the tests build their databases page by page, with 512-byte pages: these are pages 2 on, after a first page
+//with the header and an empty schema table, written to a file of the temporary directory
+#[cfg(test)]
+const TEST_PAGE_SIZE: usize = 512;
 
-        //get payload header size (varint)
-        self.file.read_exact(&mut possible_bytes)?;
-        let (payload_header_size, phs_len) = handle_varint(&possible_bytes).unwrap();
-        new_offset += phs_len as u32;
-        self.file.seek(std::io::SeekFrom::Start(new_offset as u64))?;  
+//a database built by a test, in a file that's deleted when it's dropped
+#[cfg(test)]
+struct TestDatabase {
+    path: std::path::PathBuf,
+    database: Database,
+}
 
-        //collect serial types for the columns
-        //payload_header_size - phs_len = len of serial type span
-        let serial_types_len = payload_header_size - phs_len as u64;
-        let mut serial_types: Vec<u64> = Vec::new();
-        let mut serial_type_buffer = [0u8;9];
-        //keep track of bytes taken so far
-        let mut byte_tally = 0;
-        while byte_tally < serial_types_len {
-            self.file.read_exact(&mut serial_type_buffer)?;
-            let (stype, stype_len) = handle_varint(&serial_type_buffer).unwrap();
-            serial_types.push(stype);
-            byte_tally += stype_len as u64;
-            new_offset += stype_len as u32;
-            self.file.seek(std::io::SeekFrom::Start(new_offset as u64))?;
+#[cfg(test)]
+impl std::ops::Deref for TestDatabase {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        &self.database
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+fn test_database(name: &str, pages: &[Vec<u8>]) -> TestDatabase {
+    let mut contents = b"SQLite format 3\0".to_vec();
+    contents.extend((TEST_PAGE_SIZE as u16).to_be_bytes());
+    contents.resize(100, 0);
+    contents.extend(test_page(0x0d, None, &[]));
+    contents.truncate(TEST_PAGE_SIZE);
+    for page in pages {
+        let mut page = page.clone();
+        page.resize(TEST_PAGE_SIZE, 0);
+        contents.extend(page);
+    }
+    let path = std::env::temp_dir().join(format!("codecrafters-sqlite-{}-{}.db", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    let database = Database::new(path.to_str().unwrap()).unwrap();
+    TestDatabase { path, database }
+}
+
+//a b-tree page with its cells packed at the end, in the order given
+#[cfg(test)]
+fn test_page(page_type: u8, right_most_pointer: Option<u32>, cells: &[Vec<u8>]) -> Vec<u8> {
+    let mut page = vec![0u8; TEST_PAGE_SIZE];
+    page[0] = page_type;
+    page[3..5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    let header_size = match right_most_pointer {
+        Some(pointer) => {
+            page[8..12].copy_from_slice(&pointer.to_be_bytes());
+            12
+        },
+        None => 8,
+    };
+    let mut content_start = TEST_PAGE_SIZE;
+    for (cell_index, cell) in cells.iter().enumerate() {
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        let pointer = header_size + 2 * cell_index;
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+    }
+    page[5..7].copy_from_slice(&(content_start as u16).to_be_bytes());
+    page
+}
+
+#[cfg(test)]
+fn test_varint(value: u64) -> Vec<u8> {
+    //the ninth byte of the longest varints has all 8 bits of the value's last byte
+    if value >> 56 != 0 {
+        let mut bytes: Vec<u8> = (0..8).map(|byte| (value >> (57 - 7 * byte)) as u8 | 0x80).collect();
+        bytes.push(value as u8);
+        return bytes;
+    }
+    let mut bytes = vec![value as u8 & 0x7f];
+    let mut rest = value >> 7;
+    while rest != 0 {
+        bytes.push(rest as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+//a record of the values, with every integer and real stored in 8 bytes
+#[cfg(test)]
+fn test_record(values: &[Value]) -> Vec<u8> {
+    let (mut serial_types, mut body) = (Vec::new(), Vec::new());
+    for value in values {
+        let serial_type = match value {
+            Value::Null => 0,
+            Value::Integer(value) => {
+                body.extend(value.to_be_bytes());
+                6
+            },
+            Value::Real(value) => {
+                body.extend(value.to_be_bytes());
+                7
+            },
+            Value::Text(text) => {
+                body.extend(text.as_bytes());
+                13 + 2 * text.len() as u64
+            },
+            Value::Blob(blob) => {
+                body.extend(blob);
+                12 + 2 * blob.len() as u64
+            },
+        };
+        serial_types.extend(test_varint(serial_type));
+    }
+    let mut record = test_varint(serial_types.len() as u64 + 1);
+    record.extend(serial_types);
+    record.extend(body);
+    record
+}
+
+//a database with a table for each CREATE TABLE statement given, whose rows (rowids from 1) fit on a single leaf,
+//and the schema that describes them
+#[cfg(test)]
+fn test_tables(name: &str, tables: &[(&str, Vec<Vec<Value>>)]) -> (TestDatabase, Vec<Schema>) {
+    let mut pages = Vec::new();
+    let mut schema_tables = Vec::new();
+    for (sql, rows) in tables {
+        let cells: Vec<Vec<u8>> = rows.iter().enumerate().map(|(i, values)| test_table_leaf_cell(i as i64 + 1, &test_record(values))).collect();
+        pages.push(test_page(0x0d, None, &cells));
+        let table_name = sql::parse_create_table(sql).unwrap().name;
+        schema_tables.push(Schema {
+            schema_type: "table".to_string(),
+            name: table_name.clone(),
+            tbl_name: table_name,
+            root_page: pages.len() as u32 + 1,
+            sql: sql.to_string(),
+        });
+    }
+    (test_database(name, &pages), schema_tables)
+}
+
+//the rows a query outputs, with the values of each joined by |
+#[cfg(test)]
+fn test_query(database: &Database, schema_tables: &[Schema], sql: &str) -> Vec<String> {
+    let statement = sql::parse_select(sql).unwrap();
+    let subqueries = Subqueries::new(database, schema_tables, 0, named_tables(&[], &statement));
+    let rows = Query::new(&subqueries, &statement, None).unwrap().collect(&subqueries).unwrap();
+    rows.iter().map(|row| row.payload.values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join("|")).collect()
+}
+
+#[cfg(test)]
+#[test]
+fn test_database_header() {
+    let open = |contents: &[u8]| {
+        let path = std::env::temp_dir().join(format!("codecrafters-sqlite-header-{}.db", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let database = Database::new(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        database.map(|database| database.page_size).map_err(|err| err.to_string())
+    };
+    let mut contents = b"SQLite format 3\0".to_vec();
+    contents.extend(4096u16.to_be_bytes());
+    contents.resize(112, 0);
+    contents[100] = 0x0d;
+    assert_eq!(open(&contents), std::result::Result::Ok(4096));
+    let mut wrong_format = contents.clone();
+    wrong_format[14] = b'2';
+    assert_eq!(open(&wrong_format), Err("file is not a database".to_string()));
+    let mut wrong_page_size = contents.clone();
+    wrong_page_size[16..18].copy_from_slice(&1000u16.to_be_bytes());
+    assert_eq!(open(&wrong_page_size), Err("invalid page size 1000".to_string()));
+    //a file too short to have the header is an error rather than a panic
+    assert!(open(&contents[..50]).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_local_payload_size() {
+    let database = test_database("local-payload-size", &[]);
+    let table_max_local = database.usable_size - 35;
+    let index_max_local = ((database.usable_size - 12) * 64 / 255) - 23;
+    assert_eq!((table_max_local, index_max_local), (477, 102));
+    //a payload that fits is kept whole, and one that spills keeps what makes the overflow fill whole pages...
+    assert_eq!(database.local_payload_size(477, table_max_local), 477);
+    assert_eq!(database.local_payload_size(1203, table_max_local), 187);
+    //...unless that's more than can be kept, when only the least is
+    assert_eq!(database.local_payload_size(997, table_max_local), 39);
+    assert_eq!(database.local_payload_size(1203, index_max_local), 39);
+}
+
+#[cfg(test)]
+#[test]
+fn test_overflow_payload() {
+    //a 1200-byte text makes a 1203-byte record: 187 bytes stay on the leaf and two overflow pages hold 508 bytes each
+    let text: String = (0..1200).map(|position| (b'a' + (position % 26) as u8) as char).collect();
+    let record = test_record(&[Value::Text(text.clone())]);
+    assert_eq!(record.len(), 1203);
+    let mut cell = test_varint(record.len() as u64);
+    cell.extend(test_varint(1));
+    cell.extend(&record[..187]);
+    cell.extend(3u32.to_be_bytes());
+    let overflow = |next: u32, content: &[u8]| [next.to_be_bytes().as_slice(), content].concat();
+    let pages = [
+        test_page(0x0d, None, &[cell]),
+        overflow(4, &record[187..695]),
+        overflow(0, &record[695..]),
+    ];
+    let database = test_database("overflow-payload", &pages);
+    let mut cursor = TableCursor::new(2);
+    let cell = cursor.next(&database).unwrap().unwrap();
+    assert_eq!(cell.row_id, 1);
+    assert!(matches!(&cell.payload.values[..], [Value::Text(value)] if *value == text));
+    assert!(cursor.next(&database).unwrap().is_none());
+
+    //a chain that ends early is an error rather than a short payload
+    let database = test_database("overflow-payload-short", &[pages[0].clone(), overflow(0, &record[187..695])]);
+    assert!(TableCursor::new(2).next(&database).is_err());
+}
+
+//walks the leaves of a table b-tree from left to right, i.e. in rowid order, optionally only over a range of rowids
+//the cursor doesn't hold on to the database so several can be open at once
+struct TableCursor {
+    //pages still to visit, the next one to visit on top
+    pending_pages: Vec<u32>,
+    //the current leaf page and the position of the next of its cells to return
+    leaf: Option<(Page, usize)>,
+    lower: Bound<i64>,
+    upper: Bound<i64>,
+}
+
+impl TableCursor {
+    fn new(root_page: u32) -> Self {
+        Self::range(root_page, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    //only visit rows whose rowid is within the bounds; subtrees entirely outside them are never read
+    fn range(root_page: u32, lower: Bound<i64>, upper: Bound<i64>) -> Self {
+        Self {
+            pending_pages: vec![root_page],
+            leaf: None,
+            lower,
+            upper,
         }
-        //collect values of each column
-        let mut values: Vec<RecordValue> = Vec::new();
-        for stype in serial_types {
-            let value = self.read_record_value(stype)?;
-            values.push(value);
-        }
-
-        Ok(TableLeafCell{row_id, payload: Record {values}})
-      
-
-    }
-
-    fn read_record_value(&mut self, serial_type: u64) -> Result<RecordValue> {
-        match serial_type {
-            //string
-            0 => Ok(RecordValue::Null),
-            1 => { 
-                let mut record_buffer = [0u8;1];
-                self.file.read_exact(&mut record_buffer)?;
-                let value = u8::from_be_bytes(record_buffer);
-                Ok(RecordValue::Int8 { val: value })
-            },
-            2 => { 
-                let mut record_buffer = [0u8;2];
-                self.file.read_exact(&mut record_buffer)?;
-                let value = u16::from_be_bytes(record_buffer);
-                Ok(RecordValue::Int16 { val: value })
-            },
-            3 => todo!(), //i24
-            4 => { 
-                let mut record_buffer = [0u8;4];
-                self.file.read_exact(&mut record_buffer)?;
-                let value = u32::from_be_bytes(record_buffer);
-                Ok(RecordValue::Int32 { val: value })
-            },
-            5 => todo!(), //i48
-            6 => { 
-                let mut record_buffer = [0u8;8];
-                self.file.read_exact(&mut record_buffer)?;
-                let value = u64::from_be_bytes(record_buffer);
-                Ok(RecordValue::Int64 { val: value })
-            },
-            7 => { 
-                let mut record_buffer = [0u8;8];
-                self.file.read_exact(&mut record_buffer)?;
-                let value = f64::from_be_bytes(record_buffer);
-                Ok(RecordValue::Double { val: value })
-            }, //this may be wrong
-            8 => Ok(RecordValue::Fake0),
-            9 => Ok(RecordValue::Fake1),
-            10 | 11 => Ok(RecordValue::Null),
-            x if x >= 12 && x % 2 == 0 => {
-                let size = (serial_type - 12)/2;
-                let mut record_buffer = vec![0u8;size as usize];
-                //todo: make sure the cursor is at the right place here
-                //or this gets done in the calling function?
-                self.file.read_exact(&mut record_buffer)?;
-                let mut value = Vec::new();
-                for item in record_buffer {
-                    value.push(item);
+    }
+
+    fn next(&mut self, database: &Database) -> Result<Option<TableLeafCell>> {
+        loop {
+            if let Some((page, cell_index)) = self.leaf.take() {
+                if cell_index < page.num_cells {
+                    if !self.before_upper(page.table_leaf_row_id(cell_index)?) {
+                        //everything after this is past the range too
+                        self.pending_pages.clear();
+                        return Ok(None);
+                    }
+                    let cell = page.table_leaf_cell(database, cell_index)?;
+                    self.leaf = Some((page, cell_index + 1));
+                    return Ok(Some(cell));
                 }
-                Ok(RecordValue::Blob { val:value })
-            },
-            x if x >= 13 && x % 2 == 1 => {
-                let size = (serial_type - 13)/2;
-                let mut record_buffer = vec![0u8;size as usize];
-                //todo: make sure the cursor is at the right place here
-                //or this gets done in the calling function?
-                self.file.read_exact(&mut record_buffer)?;
-                let value = String::from_utf8(record_buffer)?;
-                Ok(RecordValue::VarChar { val:value })
+            }
+            let Some(page_index) = self.pending_pages.pop() else {
+                return Ok(None);
+            };
+            let page = database.read_page(page_index)?;
+            match page.page_type {
+                0x05 => {
+                    //a child holds the rowids above the previous cell's key, up to its own cell's key, so the
+                    //children in range run from the first whose key isn't below the range to the first whose key
+                    //reaches the last rowid that can be in it
+                    let first = page.partition_point(|cell_index| Ok(!self.after_lower(page.table_interior_key(cell_index)?)))?;
+                    let last = page.partition_point(|cell_index| Ok(page.table_interior_key(cell_index)? < self.last_possible()))?;
+                    //push in reverse so the left-most child is visited first
+                    for cell_index in (first..=last).rev() {
+                        self.pending_pages.push(page.child(cell_index)?);
+                    }
+                },
+                0x0d => {
+                    let first = page.partition_point(|cell_index| Ok(!self.after_lower(page.table_leaf_row_id(cell_index)?)))?;
+                    self.leaf = Some((page, first));
+                },
+                _ => bail!("page {} is not a table b-tree page", page_index)
+            }
+        }
+    }
+
+    fn after_lower(&self, row_id: i64) -> bool {
+        match self.lower {
+            Bound::Included(lower) => row_id >= lower,
+            Bound::Excluded(lower) => row_id > lower,
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn before_upper(&self, row_id: i64) -> bool {
+        match self.upper {
+            Bound::Included(upper) => row_id <= upper,
+            Bound::Excluded(upper) => row_id < upper,
+            Bound::Unbounded => true,
+        }
+    }
+
+    //the largest rowid that can still be in range
+    fn last_possible(&self) -> i64 {
+        match self.upper {
+            Bound::Included(upper) => upper,
+            Bound::Excluded(upper) => upper.saturating_sub(1),
+            Bound::Unbounded => i64::MAX,
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_table_leaf_cell(row_id: i64, record: &[u8]) -> Vec<u8> {
+    let mut cell = test_varint(record.len() as u64);
+    cell.extend(test_varint(row_id as u64));
+    cell.extend(record);
+    cell
+}
+
+#[cfg(test)]
+fn test_table_interior_cell(left_child: u32, row_id: i64) -> Vec<u8> {
+    [left_child.to_be_bytes().as_slice(), &test_varint(row_id as u64)].concat()
+}
+
+#[cfg(test)]
+#[test]
+fn test_table_cursor_levels() {
+    //a root interior page over two interior pages, each over two leaves of two rows
+    let row = |row_id: i64| test_table_leaf_cell(row_id, &test_record(&[Value::Text(format!("row {}", row_id))]));
+    let pages = [
+        test_page(0x05, Some(4), &[test_table_interior_cell(3, 4)]),
+        test_page(0x05, Some(6), &[test_table_interior_cell(5, 2)]),
+        test_page(0x05, Some(8), &[test_table_interior_cell(7, 6)]),
+        test_page(0x0d, None, &[row(1), row(2)]),
+        test_page(0x0d, None, &[row(3), row(4)]),
+        test_page(0x0d, None, &[row(5), row(6)]),
+        test_page(0x0d, None, &[row(7), row(8)]),
+    ];
+    let database = test_database("table-cursor-levels", &pages);
+    let rows = |lower: Bound<i64>, upper: Bound<i64>| {
+        let mut cursor = TableCursor::range(2, lower, upper);
+        let mut rows = Vec::new();
+        while let Some(cell) = cursor.next(&database).unwrap() {
+            let [Value::Text(text)] = &cell.payload.values[..] else { panic!("row {} isn't a single text", cell.row_id) };
+            assert_eq!(*text, format!("row {}", cell.row_id));
+            rows.push(cell.row_id);
+        }
+        rows
+    };
+    assert_eq!(rows(Bound::Unbounded, Bound::Unbounded), [1, 2, 3, 4, 5, 6, 7, 8]);
+    //ranges that start and end part way through leaves under different interior pages
+    assert_eq!(rows(Bound::Included(2), Bound::Excluded(7)), [2, 3, 4, 5, 6]);
+    assert_eq!(rows(Bound::Excluded(4), Bound::Included(5)), [5]);
+    assert_eq!(rows(Bound::Included(8), Bound::Unbounded), [8]);
+    assert_eq!(rows(Bound::Excluded(8), Bound::Unbounded), []);
+    //a rowid lookup reads one page on each level
+    for row_id in 1..=8 {
+        database.pages_read.set(0);
+        assert_eq!(database.read_row(2, row_id).unwrap().map(|cell| cell.row_id), Some(row_id));
+        assert_eq!(database.pages_read.get(), 3);
+    }
+    assert!(database.read_row(2, 9).unwrap().is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn test_read_row() {
+    //every record but row 3's claims a header longer than the record, so decoding it is an error
+    let broken = |row_id: i64| test_table_leaf_cell(row_id, &[0x7f, 0x01]);
+    let row_3 = test_table_leaf_cell(3, &test_record(&[Value::Integer(30)]));
+    let database = test_database("read-row", &[test_page(0x0d, None, &[broken(1), broken(2), row_3, broken(4), broken(5)])]);
+    //finding a row only decodes its own record
+    let cell = database.read_row(2, 3).unwrap().unwrap();
+    assert!(cell.row_id == 3 && matches!(cell.payload.values[..], [Value::Integer(30)]));
+    assert!(database.read_row(2, 2).is_err());
+    assert!(database.read_row(2, 6).unwrap().is_none());
+    assert!(database.read_row(2, 0).unwrap().is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn test_negative_rowids() {
+    //rowids are ordered as signed integers, so the negative ones come first, in the leftmost leaf
+    let row = |row_id: i64| test_table_leaf_cell(row_id, &test_record(&[Value::Integer(row_id * 10)]));
+    let pages = [
+        test_page(0x05, Some(4), &[test_table_interior_cell(3, -1)]),
+        test_page(0x0d, None, &[row(-5), row(-1)]),
+        test_page(0x0d, None, &[row(3), row(10)]),
+    ];
+    let database = test_database("negative-rowids", &pages);
+    for row_id in [-5, -1, 3, 10] {
+        let cell = database.read_row(2, row_id).unwrap().unwrap();
+        assert!(cell.row_id == row_id && matches!(cell.payload.values[..], [Value::Integer(value)] if value == row_id * 10));
+    }
+    assert!(database.read_row(2, 0).unwrap().is_none());
+    assert!(database.read_row(2, i64::MIN).unwrap().is_none());
+
+    let row_ids = |lower: Bound<i64>, upper: Bound<i64>| {
+        let mut cursor = TableCursor::range(2, lower, upper);
+        let mut row_ids = Vec::new();
+        while let Some(cell) = cursor.next(&database).unwrap() {
+            row_ids.push(cell.row_id);
+        }
+        row_ids
+    };
+    assert_eq!(row_ids(Bound::Unbounded, Bound::Unbounded), [-5, -1, 3, 10]);
+    assert_eq!(row_ids(Bound::Unbounded, Bound::Excluded(0)), [-5, -1]);
+    assert_eq!(row_ids(Bound::Included(-10), Bound::Included(5)), [-5, -1, 3]);
+    assert_eq!(row_ids(Bound::Excluded(-1), Bound::Unbounded), [3, 10]);
+    assert_eq!(row_ids(Bound::Included(-4), Bound::Excluded(-1)), []);
+}
+
+//an entry read from an index: the indexed column values followed by the rowid of the table row
+struct IndexEntry {
+    key: Vec<Value>,
+    row_id: i64,
+}
+
+impl IndexEntry {
+    fn from_record(mut record: Record) -> Result<Self> {
+        let row_id = match record.values.pop() {
+            Some(value) => match value.as_integer() {
+                Some(row_id) => row_id,
+                None => bail!("index record doesn't end in a rowid"),
             },
-            _ => bail!("Invalid serial type")
+            None => bail!("empty index record"),
+        };
+        Ok(IndexEntry { key: record.values, row_id })
+    }
+}
+
+//one level of the path from the root of an index b-tree down to the current entry
+//on an interior page, `next` is the child being walked; once it is exhausted cell `next` is returned and
+//the walk continues into the following child (the right-most pointer after the last cell)
+struct IndexFrame {
+    page: Page,
+    next: usize,
+}
+
+//walks an index b-tree in key order, optionally restricted to a range of keys
+//keys in the bounds can be shorter than the index key, in which case only that prefix is compared
+struct IndexCursor {
+    root_page: u32,
+    stack: Vec<IndexFrame>,
+    //the cursor is positioned lazily, on the first call to next
+    started: bool,
+    lower: Bound<Vec<Value>>,
+    upper: Bound<Vec<Value>>,
+    //the collations the index's columns are ordered by, which the bounds are compared in
+    collations: Vec<Collation>,
+}
+
+impl IndexCursor {
+    fn range(root_page: u32, collations: Vec<Collation>, lower: Bound<Vec<Value>>, upper: Bound<Vec<Value>>) -> Self {
+        Self {
+            root_page,
+            stack: Vec::new(),
+            started: false,
+            lower,
+            upper,
+            collations,
         }
     }
 
-    fn get_schema_table(&mut self) -> Result<Vec<Schema>> {
-        let mut db_tables = Vec::new();
-        let db_first_page = self.read_page(1)?;
-        match db_first_page {
-            Page::TableLeaf {cells} => {
-                for cell in cells {
-                    db_tables.push(Schema::from_cell(&cell)?);             
+    fn next(&mut self, database: &Database) -> Result<Option<IndexEntry>> {
+        if !self.started {
+            self.started = true;
+            self.descend(database, self.root_page)?;
+        }
+        let Some(record) = self.step(database)? else {
+            return Ok(None);
+        };
+        //the bounds can go on past the indexed columns to the rowid, so they're compared with the whole record
+        let past_upper = match &self.upper {
+            Bound::Included(key) => compare_key_prefix(&record.values, key, &self.collations) == Ordering::Greater,
+            Bound::Excluded(key) => compare_key_prefix(&record.values, key, &self.collations) != Ordering::Less,
+            Bound::Unbounded => false,
+        };
+        if past_upper {
+            //nothing after this can be in range either
+            self.stack.clear();
+            return Ok(None);
+        }
+        Ok(Some(IndexEntry::from_record(record)?))
+    }
+
+    //whether a cell's key comes at or after the lower bound, i.e. where the walk should start
+    fn at_or_after_lower(&self, key: &[Value]) -> bool {
+        match &self.lower {
+            Bound::Included(lower) => compare_key_prefix(key, lower, &self.collations) != Ordering::Less,
+            Bound::Excluded(lower) => compare_key_prefix(key, lower, &self.collations) == Ordering::Greater,
+            Bound::Unbounded => true,
+        }
+    }
+
+    //go down from `page_index` to the first entry at or after the lower bound, pushing the path
+    fn descend(&mut self, database: &Database, page_index: u32) -> Result<()> {
+        let mut page_index = page_index;
+        loop {
+            let page = database.read_page(page_index)?;
+            if !matches!(page.page_type, 0x02 | 0x0a) {
+                bail!("page {} is not an index b-tree page", page_index);
+            }
+            //on an interior page, every key in the child left of the first cell at or after the bound
+            //is no bigger than that cell, and every key before it is out of range
+            let next = page.partition_point(|cell_index| Ok(!self.at_or_after_lower(&page.index_record(database, cell_index)?.values)))?;
+            if page.page_type == 0x0a {
+                self.stack.push(IndexFrame { page, next });
+                return Ok(());
             }
+            page_index = page.child(next)?;
+            self.stack.push(IndexFrame { page, next });
         }
-        _ => bail!("something wrong with first page")
+    }
+
+    //return the record at the current position and move past it
+    fn step(&mut self, database: &Database) -> Result<Option<Record>> {
+        loop {
+            let Some(frame) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+            if frame.next >= frame.page.num_cells {
+                //the leaf, or the interior page's right-most child, is done
+                self.stack.pop();
+                continue;
+            }
+            let record = frame.page.index_record(database, frame.next)?;
+            frame.next += 1;
+            if frame.page.page_type == 0x02 {
+                //everything below this cell is after the lower bound already
+                let child = frame.page.child(frame.next)?;
+                self.descend_leftmost(database, child)?;
+            }
+            return Ok(Some(record));
+        }
+    }
+
+    //go down the left-most path from `page_index`
+    fn descend_leftmost(&mut self, database: &Database, page_index: u32) -> Result<()> {
+        let mut page_index = page_index;
+        loop {
+            let page = database.read_page(page_index)?;
+            match page.page_type {
+                0x02 => {
+                    page_index = page.child(0)?;
+                    self.stack.push(IndexFrame { page, next: 0 });
+                },
+                0x0a => {
+                    self.stack.push(IndexFrame { page, next: 0 });
+                    return Ok(());
+                },
+                _ => bail!("page {} is not an index b-tree page", page_index)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_index_cell(left_child: Option<u32>, record: &[u8]) -> Vec<u8> {
+    let mut cell = left_child.map(|left_child| left_child.to_be_bytes().to_vec()).unwrap_or_default();
+    cell.extend(test_varint(record.len() as u64));
+    cell.extend(record);
+    cell
+}
+
+#[cfg(test)]
+#[test]
+fn test_index_cursor() {
+    //an index on a text column: a leaf on each side of an interior cell that's an entry itself
+    let entry = |key: &str, row_id: i64| test_record(&[Value::Text(key.to_string()), Value::Integer(row_id)]);
+    let pages = [
+        test_page(0x02, Some(4), &[test_index_cell(Some(3), &entry("c", 4))]),
+        test_page(0x0a, None, &[test_index_cell(None, &entry("a", 1)), test_index_cell(None, &entry("b", 2)), test_index_cell(None, &entry("b", 3))]),
+        test_page(0x0a, None, &[test_index_cell(None, &entry("d", 5)), test_index_cell(None, &entry("e", 6))]),
+    ];
+    let database = test_database("index-cursor", &pages);
+    let key = |key: &str| vec![Value::Text(key.to_string())];
+    let row_ids = |lower: Bound<Vec<Value>>, upper: Bound<Vec<Value>>| {
+        let mut cursor = IndexCursor::range(2, vec![Collation::Binary], lower, upper);
+        let mut row_ids = Vec::new();
+        while let Some(entry) = cursor.next(&database).unwrap() {
+            row_ids.push(entry.row_id);
+        }
+        row_ids
+    };
+    assert_eq!(row_ids(Bound::Unbounded, Bound::Unbounded), [1, 2, 3, 4, 5, 6]);
+    assert_eq!(row_ids(Bound::Included(key("b")), Bound::Unbounded), [2, 3, 4, 5, 6]);
+    assert_eq!(row_ids(Bound::Included(key("b")), Bound::Included(key("b"))), [2, 3]);
+    assert_eq!(row_ids(Bound::Excluded(key("b")), Bound::Included(key("d"))), [4, 5]);
+    assert_eq!(row_ids(Bound::Included(key("c")), Bound::Excluded(key("e"))), [4, 5]);
+    assert_eq!(row_ids(Bound::Included(key("bb")), Bound::Unbounded), [4, 5, 6]);
+    assert_eq!(row_ids(Bound::Excluded(key("e")), Bound::Unbounded), []);
+
+    //after the indexed columns, the bounds can compare the rowid
+    let key_and_row_id = |key: &str, row_id: i64| vec![Value::Text(key.to_string()), Value::Integer(row_id)];
+    assert_eq!(row_ids(Bound::Excluded(key("b")), Bound::Excluded(key_and_row_id("b", 3))), []);
+    assert_eq!(row_ids(Bound::Included(key("b")), Bound::Excluded(key_and_row_id("b", 3))), [2]);
+    assert_eq!(row_ids(Bound::Excluded(key_and_row_id("b", 2)), Bound::Included(key_and_row_id("b", 3))), [3]);
+    assert_eq!(row_ids(Bound::Included(key("b")), Bound::Excluded(key_and_row_id("b", 100))), [2, 3]);
+    assert_eq!(row_ids(Bound::Included(key("c")), Bound::Excluded(key_and_row_id("c", 4))), []);
+
+    //an index on a NOCASE column is in NOCASE order, and its bounds are compared in NOCASE
+    let entries = [("a", 1), ("B", 2), ("b", 3), ("c", 4), ("D", 5)].map(|(key, row_id)| test_index_cell(None, &entry(key, row_id)));
+    let database = test_database("index-cursor-nocase", &[test_page(0x0a, None, &entries)]);
+    let row_ids = |lower: Bound<Vec<Value>>, upper: Bound<Vec<Value>>| {
+        let mut cursor = IndexCursor::range(2, vec![Collation::NoCase], lower, upper);
+        let mut row_ids = Vec::new();
+        while let Some(entry) = cursor.next(&database).unwrap() {
+            row_ids.push(entry.row_id);
+        }
+        row_ids
+    };
+    assert_eq!(row_ids(Bound::Included(key("b")), Bound::Included(key("b"))), [2, 3]);
+    assert_eq!(row_ids(Bound::Excluded(key("A")), Bound::Excluded(key("d"))), [2, 3, 4]);
+    assert_eq!(row_ids(Bound::Included(key("C")), Bound::Unbounded), [4, 5]);
+}
+
+//compare an index key against a (possibly shorter) search key, column by column, each in its collation
+//(the rowid after the columns has none but BINARY)
+fn compare_key_prefix(key: &[Value], search_key: &[Value], collations: &[Collation]) -> Ordering {
+    for (position, (value, search_value)) in key.iter().zip(search_key).enumerate() {
+        match value.compare_collated(search_value, collations.get(position).copied().unwrap_or(Collation::Binary)) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
         }
-        Ok(db_tables)
     }
+    Ordering::Equal
 }
 
 
 fn handle_varint(bytes:&[u8]) -> Result<(u64,usize)> {
+    //a varint can end anywhere in the first 9 bytes, so only the bytes it actually uses have to be present
+    let byte_at = |i: usize| match bytes.get(i) {
+        Some(byte) => Ok(*byte),
+        None => Err(anyhow::anyhow!("varint runs past the end of the buffer")),
+    };
     //initialize incrementor to count size of varint
     let mut i: usize = 1;
     //we know we always need the first byte
     //bitwise AND here gets rid of the initial flag bit to store into the value
-    let mut val: u64 = (bytes[0] & 0x7f).into();
+    let mut val: u64 = (byte_at(0)? & 0x7f).into();
 
     //looping through bytes as long as the previous byte value is >= 128 
     // (because if it's greater than 128, the first bit is 1, which means that more bytes are coming)
@@ -230,7 +832,7 @@ fn handle_varint(bytes:&[u8]) -> Result<(u64,usize)> {
         val <<= 7;
         // assign the 7 bits of the current byte
         //bitwise OR assign here to combine the existing content of the value and the new value
-        val |= (bytes[i] & 0x7f) as u64;
+        val |= (byte_at(i)? & 0x7f) as u64;
         i += 1;
     }
 
@@ -241,7 +843,7 @@ fn handle_varint(bytes:&[u8]) -> Result<(u64,usize)> {
         //(don't need to remove first bit of the 9th byte)
         val <<= 8;
         //add all 8 bits
-        val |= bytes[i] as u64;
+        val |= byte_at(i)? as u64;
         i += 1;
     }
 
@@ -250,7 +852,8 @@ fn handle_varint(bytes:&[u8]) -> Result<(u64,usize)> {
 }
 
 struct Schema {
-    // schema_type: String,
+    //"table", "index", "view" or "trigger"
+    schema_type: String,
     name: String,
     tbl_name: String,
     root_page: u32,
@@ -260,221 +863,3630 @@ struct Schema {
 impl Schema {
     fn from_cell(cell: &TableLeafCell) -> Result<Self> {
         let values = &cell.payload.values;
-        // let schema_type = values[0]
+        let schema_type = match values[0] {
+            Value::Text(ref val) => Ok(val.clone()),
+            _ => bail!("something wrong with schema type")
+        }?;
         let name = match values[1] {
-            RecordValue::VarChar { ref val } => Ok(val.clone()),
+            Value::Text(ref val) => Ok(val.clone()),
             _ => bail!("something wrong with schema name")
         }?;
         let tbl_name = match values[2] {
-            RecordValue::VarChar { ref val } => Ok (val.clone()),
+            Value::Text(ref val) => Ok (val.clone()),
             _ => bail!("something wrong with schema table name")
         }?;
         let root_page = match values[3] {
-            RecordValue::Int8 { val } => Ok(val as u32),
-            RecordValue::Int16 { val } => Ok(val as u32),
-            RecordValue::Int32 { val } => Ok (val as u32),
-            RecordValue::Int64 {val} => Ok(val as u32),
+            //views and triggers have no b-tree and store a root page of 0
+            Value::Integer(val) => Ok(val as u32),
             _ => bail!("something wrong with schema root page")
         }?;
         let sql = match values[4] {
-            RecordValue::VarChar { ref val } => Ok(val.clone()),
+            Value::Text(ref val) => Ok(val.clone()),
+            //indexes sqlite creates for UNIQUE and PRIMARY KEY constraints have no sql
+            Value::Null => Ok(String::new()),
             _ => bail!("something wrong with schema sql")
         }?;
-        Ok(Schema {name, tbl_name,root_page, sql})
+        Ok(Schema {schema_type, name, tbl_name,root_page, sql})
     }
 }
 
-//ignore the other types of pages for now
-enum Page {
-    TableInterior {},
-    TableLeaf {cells: Vec<TableLeafCell>},
-    IndexInterior {},
-    IndexLeaf{}
+//a b-tree page as read from the file; the cells stay encoded until they're asked for
+struct Page {
+    page_index: u32,
+    page_type: u8,
+    data: Vec<u8>,
+    //where the cell pointer array starts, after the page header (and the database header on page 1)
+    cell_pointers: usize,
+    num_cells: usize,
+    //interior pages have a child for the keys after their last cell's
+    right_most_pointer: Option<u32>,
+}
+
+impl Page {
+    //the bytes of a cell, from its start to the end of the page
+    fn cell(&self, cell_index: usize) -> Result<&[u8]> {
+        if cell_index >= self.num_cells {
+            bail!("page {} has no cell {}", self.page_index, cell_index);
+        }
+        let pointer = self.cell_pointers + 2 * cell_index;
+        let offset = u16::from_be_bytes([self.data[pointer], self.data[pointer + 1]]) as usize;
+        match self.data.get(offset..) {
+            Some(cell) => Ok(cell),
+            None => bail!("cell {} of page {} starts past the end of the page", cell_index, self.page_index),
+        }
+    }
+
+    //the child page left of a cell of an interior page, or the right-most pointer past its last cell
+    //an interior cell starts with the 4-byte big-endian left child page number
+    fn child(&self, cell_index: usize) -> Result<u32> {
+        if cell_index == self.num_cells {
+            if let Some(right_most_pointer) = self.right_most_pointer {
+                return Ok(right_most_pointer);
+            }
+        }
+        match self.cell(cell_index)?.get(..4) {
+            Some(bytes) => Ok(u32::from_be_bytes(bytes.try_into()?)),
+            None => bail!("cell {} of page {} is out of bounds", cell_index, self.page_index),
+        }
+    }
+
+    //a table interior cell's key (varint), the largest rowid in its left child's subtree
+    fn table_interior_key(&self, cell_index: usize) -> Result<i64> {
+        //rowids are signed, stored as the varint of their two's complement
+        let (row_id, _) = handle_varint(self.cell(cell_index)?.get(4..).unwrap_or_default())?;
+        Ok(row_id as i64)
+    }
+
+    //a table leaf cell's rowid, which comes after the payload size, without decoding its record
+    fn table_leaf_row_id(&self, cell_index: usize) -> Result<i64> {
+        let cell = self.cell(cell_index)?;
+        let (_, ps_len) = handle_varint(cell)?;
+        let (row_id, _) = handle_varint(&cell[ps_len..])?;
+        Ok(row_id as i64)
+    }
+
+    fn table_leaf_cell(&self, database: &Database, cell_index: usize) -> Result<TableLeafCell> {
+        let cell = self.cell(cell_index)?;
+        let (payload_size, ps_len) = handle_varint(cell)?;
+        let (row_id, row_id_len) = handle_varint(&cell[ps_len..])?;
+        //table leaf cells can keep up to U-35 bytes of payload on the page
+        let max_local = database.usable_size - 35;
+        let payload = database.read_payload(&cell[ps_len + row_id_len..], payload_size, max_local)?;
+        Ok(TableLeafCell { row_id: row_id as i64, payload: Record::from_payload(&payload)? })
+    }
+
+    //index cells are a payload size varint followed by a record of the key columns and the rowid, with
+    //the left child page number first on interior pages (index interior cells hold a full key of their own)
+    fn index_record(&self, database: &Database, cell_index: usize) -> Result<Record> {
+        let mut cell = self.cell(cell_index)?;
+        if self.right_most_pointer.is_some() {
+            cell = cell.get(4..).unwrap_or_default();
+        }
+        let (payload_size, ps_len) = handle_varint(cell)?;
+        //index cells keep less on the page than table leaves so that each page fits at least four cells
+        let max_local = ((database.usable_size - 12) * 64 / 255) - 23;
+        let payload = database.read_payload(&cell[ps_len..], payload_size, max_local)?;
+        Record::from_payload(&payload)
+    }
+
+    //the index of the first cell that `before` is false for, with the cells it's true for all coming first,
+    //found with a binary search so only the cells it lands on are decoded
+    fn partition_point(&self, mut before: impl FnMut(usize) -> Result<bool>) -> Result<usize> {
+        let (mut low, mut high) = (0, self.num_cells);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if before(middle)? {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        Ok(low)
+    }
 }
 
+#[derive(Clone)]
 struct TableLeafCell {
-    row_id: u64,
+    row_id: i64,
     payload:Record
 }
 
+impl TableLeafCell {
+    //value of the column at `column_index`
+    //the INTEGER PRIMARY KEY column is stored as NULL since its value is the rowid, and rows written
+    //before an ALTER TABLE ADD COLUMN have no value for the new columns
+    fn column_value(&self, column_index: usize, rowid_alias: Option<usize>) -> Value {
+        if rowid_alias == Some(column_index) {
+            return Value::Integer(self.row_id);
+        }
+        self.payload.values.get(column_index).cloned().unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Clone)]
 struct Record {
-    values: Vec<RecordValue>
+    values: Vec<Value>
 }
 
-//add the rest of the value types later
-enum RecordValue {
-    Null,
-    Int8 { val: u8 },
-    Int16 { val: u16 },
-    Int24 { val: u32 },
-    Int32 { val: u32 },
-    Int48 { val: u64 },
-    Int64 { val: u64 },
-    Double { val: f64 },
-    Blob {val: Vec<u8>},
-    Fake0,
-    Fake1,
-    VarChar {val:String}, 
+impl Record {
+    //decode a complete record: a header of serial types followed by the column values
+    fn from_payload(payload: &[u8]) -> Result<Self> {
+        //get payload header size (varint)
+        let (payload_header_size, phs_len) = handle_varint(payload)?;
+        if payload_header_size as usize > payload.len() {
+            bail!("record header is larger than the payload");
+        }
+        //collect serial types for the columns
+        let mut serial_types: Vec<u64> = Vec::new();
+        let mut header_offset = phs_len;
+        while header_offset < payload_header_size as usize {
+            let (stype, stype_len) = handle_varint(&payload[header_offset..])?;
+            serial_types.push(stype);
+            header_offset += stype_len;
+        }
+        //collect values of each column, reading from the body after the header
+        let mut body = &payload[payload_header_size as usize..];
+        let mut values: Vec<Value> = Vec::with_capacity(serial_types.len());
+        for stype in serial_types {
+            values.push(read_record_value(&mut body, stype)?);
+        }
+        Ok(Record { values })
+    }
+}
 
+fn read_record_value(reader: &mut impl Read, serial_type: u64) -> Result<Value> {
+    match serial_type {
+        //string
+        0 => Ok(Value::Null),
+        //big-endian two's complement integers of 1, 2, 3, 4, 6 and 8 bytes
+        1..=6 => {
+            let size = [1, 2, 3, 4, 6, 8][serial_type as usize - 1];
+            let mut record_buffer = [0u8;8];
+            reader.read_exact(&mut record_buffer[8 - size..])?;
+            //shift the value up to the top of the i64 and back down again to extend its sign
+            let unused_bits = 64 - 8 * size as u32;
+            let value = i64::from_be_bytes(record_buffer).wrapping_shl(unused_bits).wrapping_shr(unused_bits);
+            Ok(Value::Integer(value))
+        },
+        7 => { 
+            let mut record_buffer = [0u8;8];
+            reader.read_exact(&mut record_buffer)?;
+            let value = f64::from_be_bytes(record_buffer);
+            Ok(Value::Real(value))
+        }, //this may be wrong
+        //the constants 0 and 1, which take no space in the body
+        8 => Ok(Value::Integer(0)),
+        9 => Ok(Value::Integer(1)),
+        10 | 11 => Ok(Value::Null),
+        x if x >= 12 && x % 2 == 0 => {
+            let size = (serial_type - 12)/2;
+            let mut record_buffer = vec![0u8;size as usize];
+            reader.read_exact(&mut record_buffer)?;
+            let mut value = Vec::new();
+            for item in record_buffer {
+                value.push(item);
+            }
+            Ok(Value::Blob(value))
+        },
+        x if x >= 13 && x % 2 == 1 => {
+            let size = (serial_type - 13)/2;
+            let mut record_buffer = vec![0u8;size as usize];
+            reader.read_exact(&mut record_buffer)?;
+            let value = String::from_utf8(record_buffer)?;
+            Ok(Value::Text(value))
+        },
+        _ => bail!("Invalid serial type")
+    }
 }
 
-impl ToString for RecordValue {
-    fn to_string(&self) -> String {
-        match self {
-            RecordValue::Null => "null".into(),
-            RecordValue::Int8 { val: n} => n.to_string(),
-            RecordValue::Int16 {val: n} => n.to_string(),
-            RecordValue::Int24 {val: n} => n.to_string(),
-            RecordValue::Int32 {val: n} => n.to_string(),
-            RecordValue::Int48 {val: n} => n.to_string(),
-            RecordValue::Int64 {val: n} => n.to_string(),
-            RecordValue::Double {val: n} => n.to_string(),
-            RecordValue::Blob {val: n} => format!("{:?}",n),
-            RecordValue::Fake0 => 0.to_string(),
-            RecordValue::Fake1 => 1.to_string(),
-            RecordValue::VarChar {val: n} => n.to_owned(),
+#[cfg(test)]
+#[test]
+fn test_read_record_value() {
+    let integer = |serial_type: u64, bytes: &[u8]| match read_record_value(&mut &bytes[..], serial_type).unwrap() {
+        Value::Integer(value) => value,
+        value => panic!("{:?} is not an integer", value),
+    };
+    //each size of integer is two's complement, so a set top bit makes it negative
+    assert_eq!(integer(1, &[0x7f]), 127);
+    assert_eq!(integer(1, &[0x80]), -128);
+    assert_eq!(integer(1, &[0xff]), -1);
+    assert_eq!(integer(2, &[0x7f, 0xff]), 32767);
+    assert_eq!(integer(2, &[0x80, 0x00]), -32768);
+    assert_eq!(integer(2, &[0xff, 0x38]), -200);
+    assert_eq!(integer(3, &[0x7f, 0xff, 0xff]), 8388607);
+    assert_eq!(integer(3, &[0x80, 0x00, 0x00]), -8388608);
+    assert_eq!(integer(3, &[0xff, 0xff, 0xfe]), -2);
+    assert_eq!(integer(4, &[0x7f, 0xff, 0xff, 0xff]), i32::MAX as i64);
+    assert_eq!(integer(4, &[0x80, 0x00, 0x00, 0x00]), i32::MIN as i64);
+    assert_eq!(integer(5, &[0x7f, 0xff, 0xff, 0xff, 0xff, 0xff]), 140737488355327);
+    assert_eq!(integer(5, &[0x80, 0x00, 0x00, 0x00, 0x00, 0x00]), -140737488355328);
+    assert_eq!(integer(5, &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), -1);
+    assert_eq!(integer(6, &i64::MAX.to_be_bytes()), i64::MAX);
+    assert_eq!(integer(6, &i64::MIN.to_be_bytes()), i64::MIN);
+    assert_eq!(integer(6, &(-1000i64).to_be_bytes()), -1000);
+    assert_eq!((integer(8, &[]), integer(9, &[])), (0, 1));
 
-        }
-    }
+    //only the value's own bytes are read
+    let mut reader = &[0xfe, 0x01][..];
+    assert!(matches!(read_record_value(&mut reader, 1).unwrap(), Value::Integer(-2)));
+    assert_eq!(reader, [0x01]);
+    assert!(read_record_value(&mut &[0x01][..], 2).is_err());
 }
 
-fn parse_sql(args: Vec<String>) -> () {
+
+fn parse_sql(args: &[String]) -> Result<()> {
     //queries supported: 
     // "SELECT COUNT(*) FROM apples"
+    // "SELECT max(id), avg(length(name)) FROM apples"
+    // "SELECT color, count(*) FROM apples GROUP BY color HAVING count(*) > 1 ORDER BY 2 DESC LIMIT 3"
     // "SELECT name FROM apples"
     // "SELECT name, color FROM apples"
+    // "SELECT name, color FROM apples WHERE color = 'Yellow'"
+    // "SELECT a.name, b.name FROM apples a JOIN bananas b ON a.color = b.color"
+    // "SELECT name, id * 2 AS double_id FROM apples WHERE NOT (color = 'Yellow' OR id > 3)"
+    // "SELECT name FROM apples a WHERE EXISTS (SELECT 1 FROM bananas b WHERE b.color = a.color)"
+    // "SELECT avg(n) FROM (SELECT color, count(*) AS n FROM apples GROUP BY color)"
+    // "SELECT name FROM apples UNION SELECT name FROM bananas ORDER BY 1"
+    // "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 10) SELECT i FROM n"
+    // "SELECT name, row_number() OVER (PARTITION BY color ORDER BY name) FROM apples"
+    // "EXPLAIN QUERY PLAN SELECT a.name FROM apples a JOIN bananas b ON b.id = a.id WHERE a.color = 'Yellow'"
 
     let sql_query = &args[2];
-    let (_, (target_table, selected)) = sql::select(&sql_query).unwrap();
+    let statement = sql::parse_statement(sql_query)?;
 
     //initialize database
-    let mut database = Database::new(&args[1]).unwrap();
+    let database = Database::new(&args[1])?;
 
-    match selected[0].to_ascii_lowercase().as_str() {
-        "count(*)" => num_rows_in_table(&mut database, target_table),
-        _ => data_from_columns(&mut database, selected, target_table),
+    match statement {
+        Statement::Select(statement) => data_from_columns(&database, &statement),
+        Statement::ExplainQueryPlan(statement) => explain_query_plan(&database, &statement),
     }
 }
 
-fn num_rows_in_table(database: &mut Database, target_table: &str) -> () {
-    //initialize database
-    //maybe this can be done in parse_sql or somewhere else? 
-    let schema_tables = database.get_schema_table().unwrap();
-    for table in schema_tables {
-        if table.tbl_name == target_table {
-            let table_rootpage = table.root_page;
-            //navigate to table
-            let page = database.read_page(table_rootpage as u16).unwrap();
-            match page {
-                Page::TableLeaf {cells} => {
-                    let row_count = cells.iter().count();
-                    println!("{}",row_count);
-                    break
-                },
-                _ => panic!("page should be a table leaf page")
-        }
-
-    };
-        println!("couldn't find table {}",target_table)
-    }
-}
-
-fn data_from_columns(database: &mut Database, selection:Vec<&str>,target_table: &str) -> () {
-    let schema_tables = database.get_schema_table().unwrap();
-// CREATE TABLE apples
-// (
-// 	id integer primary key autoincrement,
-// 	name text,
-// 	color text
-// )
-    // get CREATE TABLE statement from schema
-    for table in schema_tables {
-        if table.tbl_name == target_table {
-            let table_rootpage = table.root_page;
-            let table_sql = table.sql;
-            
-            //get the column names from the CREATE TABLE query
-            let (_, (_, table_columns)) = sql::create_table(&table_sql).unwrap();
-            //get index of desired column
-            let mut column_indices:Vec<usize> = Vec::new();
-            //handle (*) vs. column names
-            match selection[0] {
-                "(*)" => {
-                    column_indices = (0..table_columns.len()).collect()
-                },
-                _ => {
-                    for column in &selection {
-                        let column_index = table_columns.iter().position(|x| x[0] == *column).unwrap();
-                        column_indices.push(column_index);
-                    }
-                }
-            }
-            //navigate to table
-            let page = database.read_page(table_rootpage as u16).unwrap();
-            let mut column_data:Vec<Vec<String>> = Vec::new();
-            match page {
-                Page::TableLeaf {cells} => {
-                    // iterate through rows on page, collect data in vec
-                    for cell in cells {
-                        let mut row_data = Vec::new();
-                        for index in &column_indices {
-                        row_data.push(cell.payload.values[*index].to_string());
-                        }
-                        column_data.push(row_data);
-                    }
-                    // print data
-                    for item in column_data {
-                        println!("{}",item.join("|"));
-                    }
-                },
-                _ => panic!("page should be a table leaf page")
+//find the table called `target_table` and parse its CREATE TABLE statement
+fn find_table<'a>(schema_tables: &'a [Schema], target_table: &str) -> Result<(&'a Schema, CreateTable)> {
+    let Some(table) = schema_tables.iter().find(|table| table.schema_type == "table" && table.tbl_name.eq_ignore_ascii_case(target_table)) else {
+        bail!("no such table: {}", target_table);
+    };
+    let table_def = match sql::parse_create_table(&table.sql) {
+        std::result::Result::Ok(table_def) => table_def,
+        Err(err) => bail!("couldn't read the schema of table {}: {}", table.tbl_name, err),
+    };
+    Ok((table, table_def))
+}
+
+//a table of the FROM clause, as the expressions of the query see it
+#[derive(Clone)]
+struct Source {
+    //the name its columns are qualified with: its alias, or else the table's own name
+    name: String,
+    table_name: String,
+    root_page: u32,
+    table: CreateTable,
+    rowid_alias: Option<usize>,
+    //the columns a USING clause shares with a table to the left, which unqualified names and * leave to that table
+    using_columns: Vec<String>,
+    //the comparisons of those columns with the left table's, which join the two like ON terms
+    using_terms: Vec<Expr>,
+    //the right side of a LEFT JOIN, whose columns are NULL when none of its rows match
+    outer: bool,
+    //the rows of a subquery in FROM, which are read instead of a b-tree
+    rows: Option<Rc<Vec<TableLeafCell>>>,
+    //how many queries the query it belongs to is a subquery of
+    depth: usize,
+}
+
+impl Source {
+    fn new(schema_tables: &[Schema], table_name: &TableName, outer: bool, depth: usize) -> Result<Self> {
+        let (schema, table) = find_table(schema_tables, &table_name.name)?;
+        Ok(Source {
+            name: table_name.alias.clone().unwrap_or_else(|| table_name.name.clone()),
+            table_name: schema.tbl_name.clone(),
+            root_page: schema.root_page,
+            rowid_alias: table.rowid_alias(),
+            table,
+            using_columns: Vec::new(),
+            using_terms: Vec::new(),
+            outer,
+            rows: None,
+            depth,
+        })
+    }
+
+    //a subquery in FROM, whose rows have already been read; it has no rowid
+    fn subquery(name: &str, columns: Vec<ColumnDef>, rows: Rc<Vec<TableLeafCell>>, outer: bool, depth: usize) -> Self {
+        Source {
+            name: name.to_string(),
+            table_name: name.to_string(),
+            root_page: 0,
+            table: CreateTable { name: name.to_string(), columns, constraints: Vec::new(), without_rowid: true },
+            rowid_alias: None,
+            using_columns: Vec::new(),
+            using_terms: Vec::new(),
+            outer,
+            rows: Some(rows),
+            depth,
         }
+    }
+
+    fn value(&self, cell: &TableLeafCell, column: ColumnRef) -> Value {
+        match column {
+            ColumnRef::RowId => Value::Integer(cell.row_id),
+            ColumnRef::Column(column_index) => {
+                let value = cell.column_value(column_index, self.rowid_alias);
+                //REAL columns store whole numbers as integers on disk
+                match self.table.columns[column_index].affinity() {
+                    Affinity::Real => value.apply_affinity(Affinity::Real),
+                    _ => value,
+                }
+            },
         }
+    }
 
+    fn affinity(&self, column: ColumnRef) -> Affinity {
+        match column {
+            ColumnRef::RowId => Affinity::Integer,
+            ColumnRef::Column(column_index) => self.table.columns[column_index].affinity(),
+        }
     }
 
+    fn collation(&self, column: ColumnRef) -> Option<&str> {
+        match column {
+            ColumnRef::RowId => None,
+            ColumnRef::Column(column_index) => self.table.columns[column_index].collation(),
+        }
+    }
 }
 
-fn main() -> Result<()> {
-    // Parse arguments
-    let args = std::env::args().collect::<Vec<_>>();
-    // debug args
-    // let args = vec!["".to_string(),"sample.db".to_string(), ".dbinfo".to_string()];
-    // let args = vec!["".to_string(),"sample.db".to_string(), ".tables".to_string()];
-    // let args = vec!["".to_string(),"sample.db".to_string(), "SELECT COUNT(*) FROM apples".to_string()];
-    // let args = vec!["".to_string(),"sample.db".to_string(), "SELECT name FROM apples".to_string()];
-    // let args = vec!["".to_string(),"sample.db".to_string(), "SELECT name,color FROM apples".to_string()];
-    match args.len() {
-        0 | 1 => bail!("Missing <database path> and <command>"),
-        2 => bail!("Missing <command>"),
-        _ => {}
+//the tables of the FROM clause of a query run by `subqueries`, whose subqueries in FROM see `enclosing`,
+//the row of the queries around it. The query reads no more than `needed` rows of its first table, when that's known
+fn from_sources(subqueries: &Subqueries, from: &FromClause, enclosing: Option<&RowContext>, needed: Option<u64>) -> Result<Vec<Source>> {
+    let mut sources = vec![from_source(subqueries, &from.table, false, enclosing, needed)?];
+    for join in &from.joins {
+        let mut source = from_source(subqueries, &join.table, join.operator == JoinOperator::Left, enclosing, None)?;
+        if let JoinConstraint::Using(columns) = &join.constraint {
+            for column in columns {
+                //the column on the left is the one an unqualified name would pick out of the tables so far
+                let left = match resolve_source_column(&sources, None, column) {
+                    std::result::Result::Ok((left, _)) if source.table.column_index(column).is_some() => left,
+                    _ => bail!("cannot join using column {} - column not present in both tables", column),
+                };
+                let term = Expr::Binary(
+                    Box::new(Expr::qualified_column(&sources[left].name, column)),
+                    BinaryOperator::Equal,
+                    Box::new(Expr::qualified_column(&source.name, column)),
+                );
+                source.using_terms.push(term);
+                source.using_columns.push(column.clone());
+            }
+        }
+        sources.push(source);
     }
+    Ok(sources)
+}
 
-    // Parse command and act accordingly
-    let command = &args[2];
-    match command.as_str() {
-        ".dbinfo" => {
-            // dbinfo(args);
-            let mut database = Database::new(&args[1])?;
+fn from_source(subqueries: &Subqueries, table: &TableOrSubquery, outer: bool, enclosing: Option<&RowContext>, needed: Option<u64>) -> Result<Source> {
+    let (select, alias) = match table {
+        TableOrSubquery::Table(table_name) => {
+            let name = table_name.alias.as_deref().unwrap_or(&table_name.name);
+            let (columns, rows) = match subqueries.named_table(&table_name.name) {
+                Some(NamedTable::With { cte, scope, rows }) => match rows.get() {
+                    Some(rows) => rows.clone(),
+                    None => {
+                        let (columns, table_rows, reusable) = with_rows(subqueries, cte, *scope, enclosing, needed)?;
+                        if reusable {
+                            let _ = rows.set((columns.clone(), table_rows.clone()));
+                        }
+                        (columns, table_rows)
+                    },
+                },
+                Some(NamedTable::Rows { columns, rows, .. }) => (columns.clone(), rows.clone()),
+                None => return Source::new(subqueries.schema_tables, table_name, outer, subqueries.depth),
+            };
+            return Ok(Source::subquery(name, columns, rows, outer, subqueries.depth));
+        },
+        TableOrSubquery::Subquery { select, alias } => (select, alias),
+    };
+    //a subquery in FROM can't see the other tables of the FROM clause, so it runs once, before the query does
+    let inner = subqueries.nested(subqueries.depth, named_tables(&subqueries.tables, select));
+    let query = Query::new(&inner, select, enclosing)?;
+    let rows = match subqueries.explaining {
+        true => Vec::new(),
+        false => query.collect(&inner)?,
+    };
+    subqueries.read_all(&inner);
+    Ok(Source::subquery(alias.as_deref().unwrap_or_default(), query.column_defs(), Rc::new(rows), outer, subqueries.depth))
+}
+
+//the columns and rows of a common table expression read by a query run by `subqueries`, which reads no more than
+//`needed` of them when that's known: it sees the first `scope` of the named tables around the query (those before it
+//in its WITH clause and those of the queries enclosing it) and the row of the query as a subquery in FROM would.
+//Also whether the rows are all of them and read no row of the queries around it, so they can be kept for wherever
+//else it's read
+fn with_rows(subqueries: &Subqueries, cte: &CommonTableExpression, scope: usize, enclosing: Option<&RowContext>, needed: Option<u64>) -> Result<(Vec<ColumnDef>, Rc<Vec<TableLeafCell>>, bool)> {
+    let tables = &subqueries.tables[..scope];
+    let parts = recursive_parts(cte);
+    let (select, recursive) = match &parts {
+        Some((initial, recursive)) => (initial, Some(*recursive)),
+        None => (cte.select.as_ref(), None),
+    };
+    let inner = subqueries.nested(subqueries.depth, named_tables(tables, select));
+    let query = Query::new(&inner, select, enclosing)?;
+    let result_columns = query.column_defs();
+    let mut columns = result_columns.clone();
+    if !cte.columns.is_empty() {
+        if cte.columns.len() != columns.len() {
+            bail!("table {} has {} values for {} columns", cte.name, columns.len(), cte.columns.len());
+        }
+        for (column, name) in columns.iter_mut().zip(&cte.columns) {
+            column.name = name.clone();
+        }
+    }
+    //whether the SELECTs have read a row of the queries around it
+    let reads_enclosing = |inner: &Subqueries| {
+        subqueries.read_all(inner);
+        inner.enclosing_reads.iter().any(Cell::get)
+    };
+    let Some(recursive) = recursive else {
+        if subqueries.explaining {
+            return Ok((columns, Rc::new(Vec::new()), false));
+        }
+        let rows = query.collect(&inner)?;
+        return Ok((columns, Rc::new(rows), !reads_enclosing(&inner)));
+    };
+
+    if table_references(&recursive.select, &cte.name) > 1 {
+        bail!("multiple references to recursive table: {}", cte.name);
+    }
+    //the recursive SELECT reads the table in its FROM clause only, where its row is swapped for each run
+    if subqueries_read(&recursive.select, &cte.name) {
+        bail!("recursive reference in a subquery: {}", cte.name);
+    }
+
+    //the rows wait in a queue, and each one taken out of it is added to the table, then read by the recursive SELECT
+    //as the whole table, which puts the rows it outputs at the back of the queue. The ORDER BY and LIMIT of
+    //the compound SELECT apply to the queue: the row taken out is the first in that order, and the recursion
+    //stops once the table has LIMIT rows. UNION drops the rows that repeat one the queue has already had
+    let step = &recursive.select;
+    let mut step_tables = tables.to_vec();
+    step_tables.push(NamedTable::Rows { name: cte.name.clone(), columns: columns.clone(), rows: Rc::new(Vec::new()) });
+    let step_subqueries = subqueries.nested(subqueries.depth, named_tables(&step_tables, step));
+    //it's prepared once, reading no rows, and then run for each row
+    let mut step_query = Query::new(&step_subqueries, step, enclosing)?;
+    let mut from_tables = step.from.iter().flat_map(|from| std::iter::once(&from.table).chain(from.joins.iter().map(|join| &join.table)));
+    let Some(position) = from_tables.position(|table| matches!(table, TableOrSubquery::Table(table) if table.name.eq_ignore_ascii_case(&cte.name))) else {
+        unreachable!("the recursive SELECT reads the table")
+    };
+    let step_source = step_query.first_source() + position;
+    if step_query.outputs.len() != columns.len() {
+        bail!("SELECTs to the left and right of {} do not have the same number of result columns", recursive.operator.name());
+    }
+    let mut aggregate_calls = Vec::new();
+    for (output, _) in &step_query.outputs {
+        find_aggregates(output, &mut aggregate_calls)?;
+    }
+    if !aggregate_calls.is_empty() || !step.group_by.is_empty() {
+        bail!("recursive aggregate queries not supported");
+    }
+    if subqueries.explaining {
+        return Ok((columns, Rc::new(Vec::new()), false));
+    }
+
+    //as in any compound SELECT, the ORDER BY terms name the result columns of the first SELECT
+    let (order_by, sort_keys): (Vec<usize>, Vec<SortKey>) = compound_order_by(&cte.select.order_by, &[result_columns])?.into_iter().unzip();
+    let collations = columns.iter().map(|column| column.collation().map_or(Ok(Collation::Binary), Collation::from_name)).collect::<Result<Vec<_>>>()?;
+    let mut seen = (recursive.operator == CompoundOperator::Union).then(HashSet::new);
+    let mut queue = SortQueue::new(sort_keys);
+    let mut enqueue = |values: Vec<Value>, queue: &mut SortQueue| {
+        if let Some(seen) = &mut seen {
+            let key: Vec<Value> = values.iter().zip(&collations).map(|(value, collation)| value.collation_key(*collation)).collect();
+            if !seen.insert(key) {
+                return;
+            }
+        }
+        let keys = order_by.iter().map(|column_index| values[*column_index].clone()).collect();
+        queue.push(SortRow { keys, values });
+    };
+    for values in query.collect(&inner)? {
+        enqueue(values.payload.values, &mut queue);
+    }
+    let mut reusable = !reads_enclosing(&inner);
+
+    //the recursion stops early once the query has all the rows it reads
+    let mut limit = RowLimit::new(cte.select.limit.as_ref())?;
+    let mut rows = Vec::new();
+    while !limit.is_exhausted() {
+        if needed.is_some_and(|needed| rows.len() as u64 >= needed) {
+            reusable = false;
+            break;
+        }
+        let Some(SortRow { values, .. }) = queue.pop() else {
+            break;
+        };
+        match limit.next() {
+            Some(true) => rows.push(TableLeafCell { row_id: rows.len() as i64 + 1, payload: Record { values: values.clone() } }),
+            Some(false) => {},
+            None => break,
+        }
+        step_query.sources[step_source].rows = Some(Rc::new(vec![TableLeafCell { row_id: 1, payload: Record { values } }]));
+        for cell in step_query.collect(&step_subqueries)? {
+            enqueue(cell.payload.values, &mut queue);
+        }
+        reusable &= !reads_enclosing(&step_subqueries);
+    }
+    Ok((columns, Rc::new(rows), reusable))
+}
+
+//a common table expression is recursive when the last SELECT of a compound SELECT reads it after UNION [ALL]:
+//the SELECTs before that one, without the ORDER BY and LIMIT that apply to the recursion, make up its initial SELECT
+fn recursive_parts(cte: &CommonTableExpression) -> Option<(SelectStatement, &CompoundSelect)> {
+    let last = cte.select.compound.last()?;
+    if !matches!(last.operator, CompoundOperator::Union | CompoundOperator::UnionAll) || table_references(&last.select, &cte.name) == 0 {
+        return None;
+    }
+    let compound = cte.select.compound[..cte.select.compound.len() - 1].to_vec();
+    Some((SelectStatement { compound, order_by: Vec::new(), limit: None, ..cte.select.as_ref().clone() }, last))
+}
+
+//how many of the tables of a SELECT's FROM clause are the table called `name`
+fn table_references(select: &SelectStatement, name: &str) -> usize {
+    let from_tables = select.from.iter().flat_map(|from| std::iter::once(&from.table).chain(from.joins.iter().map(|join| &join.table)));
+    from_tables.filter(|table| matches!(table, TableOrSubquery::Table(table) if table.name.eq_ignore_ascii_case(name))).count()
+}
+
+//whether a SELECT's subqueries read the table called `name`, those in FROM and in its expressions and the SELECTs
+//of a compound SELECT, and theirs in turn (unless a WITH table of the same name hides it)
+fn subqueries_read(select: &SelectStatement, name: &str) -> bool {
+    fn reads(select: &SelectStatement, name: &str) -> bool {
+        table_references(select, name) > 0 || subqueries_read(select, name)
+    }
+    fn expr_reads(expr: &Expr, name: &str) -> bool {
+        let subquery = match expr {
+            Expr::Subquery(select) | Expr::Exists(select) | Expr::In { list: InList::Select(select), .. } => reads(select, name),
+            _ => false,
+        };
+        subquery || expr.children().into_iter().any(|child| expr_reads(child, name))
+    }
+    for cte in &select.with {
+        if cte.name.eq_ignore_ascii_case(name) {
+            return false;
+        }
+        if reads(&cte.select, name) {
+            return true;
+        }
+    }
+    let mut from = select.from.iter().flat_map(|from| std::iter::once(&from.table).chain(from.joins.iter().map(|join| &join.table)));
+    let mut exprs: Vec<&Expr> = select.columns.iter().filter_map(|column| match column {
+        ResultColumn::Expr { expr, .. } => Some(expr),
+        _ => None,
+    }).collect();
+    exprs.extend(select.from.iter().flat_map(|from| &from.joins).filter_map(|join| match &join.constraint {
+        JoinConstraint::On(condition) => Some(condition),
+        _ => None,
+    }));
+    exprs.extend(select.where_clause.iter().chain(&select.group_by).chain(&select.having).chain(select.order_by.iter().map(|term| &term.expr)));
+    exprs.extend(select.windows.iter().flat_map(|(_, window)| window.expressions()));
+    from.any(|table| matches!(table, TableOrSubquery::Subquery { select, .. } if reads(select, name)))
+        || exprs.into_iter().any(|expr| expr_reads(expr, name))
+        || select.compound.iter().any(|compound| reads(&compound.select, name))
+}
+
+//how many rows of the table in its FROM clause a query reads at most, when it outputs them as they come until its
+//LIMIT is reached, as a query that only picks columns of a table does
+fn rows_needed(statement: &SelectStatement) -> Option<u64> {
+    let from = statement.from.as_ref()?;
+    let plain = from.joins.is_empty() && statement.where_clause.is_none() && statement.group_by.is_empty() && statement.having.is_none()
+        && !statement.distinct && statement.order_by.is_empty() && statement.compound.is_empty();
+    let computed = statement.columns.iter().any(|column| {
+        let ResultColumn::Expr { expr, .. } = column else { return false };
+        let mut windows = Vec::new();
+        find_aggregates(expr, &mut Vec::new()).map_or(true, |calls| !calls.is_empty()) || find_windows(expr, &mut windows).is_err() || !windows.is_empty()
+    });
+    if !plain || computed {
+        return None;
+    }
+    RowLimit::new(statement.limit.as_ref()).ok()?.rows_needed()
+}
+
+fn data_from_columns(database: &Database, statement: &SelectStatement) -> Result<()> {
+    let schema_tables = database.get_schema_table()?;
+    let subqueries = Subqueries::new(database, &schema_tables, 0, named_tables(&[], statement));
+    let query = Query::new(&subqueries, statement, None)?;
+    query.run(&subqueries, &mut |values| {
+        let row_data: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        println!("{}",row_data.join("|"));
+        Ok(ControlFlow::Continue(()))
+    })
+}
+
+//the expressions a query outputs for every row and their aliases, with * standing for all the columns of the tables
+//(but a column shared by USING only once) and table.* for all the columns of that table
+fn result_outputs(statement: &SelectStatement, sources: &[Source]) -> Result<Vec<(Expr, Option<String>)>> {
+    let mut outputs: Vec<(Expr, Option<String>)> = Vec::new();
+    for column in &statement.columns {
+        match column {
+            ResultColumn::Star if sources.is_empty() => bail!("no tables specified"),
+            ResultColumn::Star => for source in sources {
+                let columns = source.table.columns.iter().filter(|column| !source.using_columns.iter().any(|using| using.eq_ignore_ascii_case(&column.name)));
+                outputs.extend(columns.map(|column| (Expr::qualified_column(&source.name, &column.name), None)));
+            },
+            ResultColumn::TableStar(name) => {
+                let Some(source) = sources.iter().find(|source| source.name.eq_ignore_ascii_case(name)) else {
+                    bail!("no such table: {}", name);
+                };
+                outputs.extend(source.table.columns.iter().map(|column| (Expr::qualified_column(&source.name, &column.name), None)));
+            },
+            ResultColumn::Expr { expr, alias } => outputs.push((expr.clone(), alias.clone())),
+        }
+    }
+    Ok(outputs)
+}
+
+//a SELECT whose tables have been looked up, ready to run. A subquery's tables come after those of the queries
+//around it, whose columns its expressions can read as well: they hold the row those queries are on while it runs
+struct Query<'a> {
+    statement: Rc<SelectStatement>,
+    sources: Vec<Source>,
+    //the row of each of the enclosing queries' tables, which is None for a table that has no row yet
+    enclosing_cells: RefCell<Vec<Option<TableLeafCell>>>,
+    outputs: Vec<(Expr, Option<String>)>,
+    //the SELECTs after this one in a compound SELECT, each with the subqueries of its own expressions
+    compound: Vec<(CompoundOperator, Subqueries<'a>, Query<'a>)>,
+    //how it runs, worked out the first time it does
+    compiled: OnceCell<Compiled<'a>>,
+}
+
+//what running a SELECT takes that stays the same each time it runs: how it reads its tables, its expressions
+//prepared, and how it sorts, groups and computes the window functions of its rows
+struct Compiled<'a> {
+    plan: JoinPlan<'a>,
+    outputs: Vec<Prepared>,
+    //the collations DISTINCT compares the values of the result columns in
+    output_collations: Vec<Collation>,
+    //the terms the rows are sorted by, none when the join reads them in that order already
+    order_by: Vec<Prepared>,
+    sort_keys: Vec<SortKey>,
+    having: Option<Prepared>,
+    //the groups of an aggregate query
+    grouping: Option<Grouping>,
+    window_calls: Vec<Expr>,
+    windows: Vec<WindowDefinition>,
+}
+
+impl<'a> Query<'a> {
+    //prepare `statement` to be run by `subqueries`, as a subquery of the query whose row is `enclosing`
+    fn new(subqueries: &Subqueries<'a>, statement: &SelectStatement, enclosing: Option<&RowContext>) -> Result<Self> {
+        let (mut sources, mut enclosing_cells) = match enclosing {
+            Some(row) => (row.sources.to_vec(), row.cells.to_vec()),
+            None => (Vec::new(), Vec::new()),
+        };
+        enclosing_cells.resize(sources.len(), None);
+        for (position, cte) in statement.with.iter().enumerate() {
+            if statement.with[..position].iter().any(|earlier| earlier.name.eq_ignore_ascii_case(&cte.name)) {
+                bail!("duplicate WITH table name: {}", cte.name);
+            }
+        }
+        if let Some(from) = &statement.from {
+            sources.extend(from_sources(subqueries, from, enclosing, rows_needed(statement))?);
+        }
+        let outputs = result_outputs(statement, &sources[enclosing_cells.len()..])?;
+        let mut compound = Vec::new();
+        for select in &statement.compound {
+            let select_subqueries = subqueries.nested(subqueries.depth, subqueries.tables.clone());
+            let query = Query::new(&select_subqueries, &select.select, enclosing)?;
+            subqueries.read_all(&select_subqueries);
+            if query.outputs.len() != outputs.len() {
+                bail!("SELECTs to the left and right of {} do not have the same number of result columns", select.operator.name());
+            }
+            compound.push((select.operator, select_subqueries, query));
+        }
+        Ok(Query {
+            statement: Rc::new(statement.clone()),
+            sources,
+            enclosing_cells: RefCell::new(enclosing_cells),
+            outputs,
+            compound,
+            compiled: OnceCell::new(),
+        })
+    }
+
+    //run the query again as a subquery of the query whose row is now `enclosing`
+    fn bind(&self, subqueries: &Subqueries, enclosing: &RowContext) {
+        subqueries.restart();
+        let mut enclosing_cells = enclosing.cells.to_vec();
+        enclosing_cells.resize(enclosing.sources.len(), None);
+        *self.enclosing_cells.borrow_mut() = enclosing_cells;
+        for (_, select_subqueries, query) in &self.compound {
+            query.bind(select_subqueries, enclosing);
+        }
+    }
+
+    //how many of the tables are the enclosing queries'
+    fn first_source(&self) -> usize {
+        self.enclosing_cells.borrow().len()
+    }
+
+    //the ORDER BY of this SELECT alone, which a compound SELECT applies to all of its SELECTs instead
+    fn ordering_terms(&self) -> &[OrderingTerm] {
+        match self.compound.is_empty() {
+            true => &self.statement.order_by,
+            false => &[],
+        }
+    }
+
+    //the one result column of a subquery that's compared with a value
+    fn single_column(&self) -> Result<&Expr> {
+        match self.outputs.as_slice() {
+            [(column, _)] => Ok(column),
+            outputs => bail!("sub-select returns {} columns - expected 1", outputs.len()),
+        }
+    }
+
+    //the result columns as the columns of a table: named by their aliases or after the columns they are
+    //(sqlite names the others after their expressions' text), with the affinity and collation of their expressions.
+    //A compound SELECT's columns are those of its first SELECT, but a result column there that's neither a column
+    //(which has its declared collation, or else BINARY) nor given a COLLATE takes the collation of the same result
+    //column in the first SELECT after it that has one
+    fn column_defs(&self) -> Vec<ColumnDef> {
+        self.outputs.iter().enumerate().map(|(output_index, (expr, alias))| {
+            let name = match (alias, expr) {
+                (Some(alias), _) => alias.clone(),
+                (None, Expr::Column { name, .. }) => name.clone(),
+                (None, _) => format!("column{}", output_index + 1),
+            };
+            let collation = std::iter::once(self).chain(self.compound.iter().map(|(_, _, query)| query)).find_map(|query| {
+                let expr = &query.outputs[output_index].0;
+                explicit_collation(expr).or_else(|| match expr {
+                    Expr::Column { .. } => column_collation(expr, &query.sources),
+                    _ => None,
+                })
+            });
+            ColumnDef {
+                name,
+                type_name: expression_affinity(expr, &self.sources).map(|affinity| affinity.type_name().to_string()),
+                constraints: collation.map(|collation| ColumnConstraint::Collate(collation.to_string())).into_iter().collect(),
+            }
+        }).collect()
+    }
+
+    //run the query, collecting its result rows as the rows of a table
+    fn collect(&self, subqueries: &Subqueries<'a>) -> Result<Vec<TableLeafCell>> {
+        let mut rows = Vec::new();
+        self.run(subqueries, &mut |values| {
+            rows.push(TableLeafCell { row_id: rows.len() as i64 + 1, payload: Record { values } });
+            Ok(ControlFlow::Continue(()))
+        })?;
+        Ok(rows)
+    }
+
+    //run the query, handing each of its result rows to `output` until it asks to stop
+    fn run(&self, subqueries: &Subqueries<'a>, output: &mut dyn FnMut(Vec<Value>) -> Result<ControlFlow<()>>) -> Result<()> {
+        if self.compound.is_empty() {
+            return self.run_select(subqueries, self.statement.limit.as_ref(), output);
+        }
+        let columns = self.column_defs();
+        let mut select_columns = vec![columns.clone()];
+        select_columns.extend(self.compound.iter().map(|(_, _, query)| query.column_defs()));
+        let order_by = compound_order_by(&self.statement.order_by, &select_columns)?;
+        let mut limit = RowLimit::new(self.statement.limit.as_ref())?;
+        if limit.is_exhausted() {
+            return Ok(());
+        }
+        let rows_needed = limit.rows_needed();
+        let mut emit = |values| match limit.next() {
+            Some(true) => Ok(if output(values)?.is_break() || limit.is_exhausted() { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }),
+            Some(false) => Ok(ControlFlow::Continue(())),
+            None => Ok(ControlFlow::Break(())),
+        };
+        //with nothing but UNION ALL and no ORDER BY, the rows of each SELECT can be output as they come
+        if order_by.is_empty() && self.compound.iter().all(|(operator, _, _)| *operator == CompoundOperator::UnionAll) {
+            let mut stopped = false;
+            let selects = std::iter::once((subqueries, self)).chain(self.compound.iter().map(|(_, select_subqueries, query)| (select_subqueries, query)));
+            for (select_subqueries, query) in selects {
+                query.run_select(select_subqueries, None, &mut |values| {
+                    let flow = emit(values)?;
+                    stopped = flow.is_break();
+                    Ok(flow)
+                })?;
+                subqueries.read_all(select_subqueries);
+                if stopped {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        //otherwise the SELECTs are combined from left to right. The rows of UNION, INTERSECT and EXCEPT are
+        //distinct and, as sqlite reads them out of the temporary index it builds, in order; of the rows that
+        //are the same, the last one is kept, as it replaces the others in that index
+        let collations = columns.iter().map(|column| column.collation().map_or(Ok(Collation::Binary), Collation::from_name)).collect::<Result<Vec<_>>>()?;
+        let key = |values: &[Value]| -> Vec<Value> { values.iter().zip(&collations).map(|(value, collation)| value.collation_key(*collation)).collect() };
+        let distinct_in_order = |rows: Vec<Vec<Value>>| {
+            let distinct: HashMap<Vec<Value>, Vec<Value>> = rows.into_iter().map(|values| (key(&values), values)).collect();
+            let mut rows: Vec<SortRow> = distinct.into_values().map(|values| SortRow { keys: values.clone(), values }).collect();
+            let sort_keys: Vec<SortKey> = collations.iter().map(|collation| SortKey { descending: false, nulls_first: true, collation: *collation }).collect();
+            rows.sort_by(|a, b| sort::compare_rows(&sort_keys, a, b));
+            rows.into_iter().map(|row| row.values).collect::<Vec<_>>()
+        };
+        let mut rows = self.collect_select(subqueries)?;
+        for (operator, select_subqueries, query) in &self.compound {
+            let right = query.collect_select(select_subqueries)?;
+            subqueries.read_all(select_subqueries);
+            rows = match operator {
+                CompoundOperator::UnionAll => {
+                    rows.extend(right);
+                    rows
+                },
+                CompoundOperator::Union => distinct_in_order(rows.into_iter().chain(right).collect()),
+                CompoundOperator::Intersect | CompoundOperator::Except => {
+                    let right: HashSet<Vec<Value>> = right.iter().map(|values| key(values)).collect();
+                    let keep = *operator == CompoundOperator::Intersect;
+                    distinct_in_order(rows.into_iter().filter(|values| right.contains(&key(values)) == keep).collect())
+                },
+            };
+        }
+
+        if order_by.is_empty() {
+            for values in rows {
+                if emit(values)?.is_break() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        let (order_by, keys): (Vec<usize>, Vec<SortKey>) = order_by.into_iter().unzip();
+        let mut sorter = Sorter::new(keys, sort::SORT_MEMORY_BUDGET).keep_first(rows_needed);
+        for values in rows {
+            sorter.push(SortRow { keys: order_by.iter().map(|column_index| values[*column_index].clone()).collect(), values })?;
+        }
+        for row in sorter.finish()? {
+            if emit(row?.values)?.is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    //the rows of this SELECT alone, without the rest of a compound SELECT
+    fn collect_select(&self, subqueries: &Subqueries<'a>) -> Result<Vec<Vec<Value>>> {
+        let mut rows = Vec::new();
+        self.run_select(subqueries, None, &mut |values| {
+            rows.push(values);
+            Ok(ControlFlow::Continue(()))
+        })?;
+        Ok(rows)
+    }
+
+    //how the SELECT runs, worked out the first time it's asked for
+    fn compiled(&self, subqueries: &Subqueries<'a>) -> Result<&Compiled<'a>> {
+        if let Some(compiled) = self.compiled.get() {
+            return Ok(compiled);
+        }
+        let compiled = self.compile(subqueries)?;
+        Ok(self.compiled.get_or_init(|| compiled))
+    }
+
+    fn compile(&self, subqueries: &Subqueries<'a>) -> Result<Compiled<'a>> {
+        let statement = self.statement.as_ref();
+        let sources = &self.sources;
+        let outputs = &self.outputs;
+        let plan = plan_joins(subqueries.schema_tables, sources, statement, outputs, self.first_source())?;
+
+        let ordering_terms = self.ordering_terms();
+        let order_by_terms: Vec<&Expr> = ordering_terms.iter().map(|term| &term.expr).collect();
+        let order_by = result_column_references(&order_by_terms, "ORDER", outputs, sources)?;
+        let sort_keys = ordering_terms.iter().zip(&order_by).map(|(term, expr)| Ok(SortKey {
+            descending: term.descending,
+            nulls_first: term.nulls_first,
+            collation: expression_collation(expr, sources)?,
+        })).collect::<Result<Vec<_>>>()?;
+        //DISTINCT compares the output rows with the collations of their columns
+        //(Value equality makes 1 and 1.0 the same, and NULLs equal each other)
+        let output_collations = outputs.iter().map(|(output, _)| expression_collation(output, sources)).collect::<Result<Vec<_>>>()?;
+        let prepared_outputs = prepare_all(outputs.iter().map(|(output, _)| output), sources)?;
+        let mut prepared_order_by = prepare_all(order_by.iter().copied(), sources)?;
+
+        //HAVING can name result columns by their aliases
+        let having = statement.having.clone().map(|mut having| {
+            replace_aliases(&mut having, outputs, sources);
+            having
+        });
+        let prepared_having = having.as_ref().map(|having| prepare(having, sources)).transpose()?;
+        let group_by_terms: Vec<&Expr> = statement.group_by.iter().collect();
+        let group_by = result_column_references(&group_by_terms, "GROUP", outputs, sources)?;
+        for expr in &group_by {
+            if !find_aggregates(expr, &mut Vec::new())?.is_empty() {
+                bail!("aggregate functions are not allowed in the GROUP BY clause");
+            }
+        }
+        let (window_calls, windows) = query_windows(statement, outputs, &order_by)?;
+
+        //GROUP BY, HAVING or aggregate calls in the result columns, HAVING, ORDER BY or the windows make this an aggregate
+        //query, which outputs a row per group of rows (and with no GROUP BY, a single row for all of them)
+        let mut aggregate_calls = Vec::new();
+        let aggregated_exprs = || outputs.iter().map(|(output, _)| output)
+            .chain(having.iter())
+            .chain(order_by.iter().copied())
+            .chain(windows.iter().flat_map(WindowDefinition::expressions));
+        for expr in aggregated_exprs() {
+            find_aggregates(expr, &mut aggregate_calls)?;
+        }
+        let grouping = match aggregate_calls.is_empty() && group_by.is_empty() && having.is_none() {
+            true => None,
+            false => {
+                let keeps_row = aggregated_exprs().any(has_bare_column);
+                Some(Grouping::new(aggregate_calls, &group_by, sources, keeps_row)?)
+            },
+        };
+        //rows that aren't grouped or held back for window functions come out in the order the join reads them
+        if grouping.is_none() && window_calls.is_empty() && reads_in_order(&plan, sources, &order_by, &sort_keys) {
+            prepared_order_by.clear();
+        }
+        Ok(Compiled {
+            plan,
+            outputs: prepared_outputs,
+            output_collations,
+            order_by: prepared_order_by,
+            sort_keys,
+            having: prepared_having,
+            grouping,
+            window_calls,
+            windows,
+        })
+    }
+
+    //run this SELECT alone with the given LIMIT, which a compound SELECT applies to all of its SELECTs instead
+    fn run_select(&self, subqueries: &Subqueries<'a>, limit: Option<&Limit>, output: &mut dyn FnMut(Vec<Value>) -> Result<ControlFlow<()>>) -> Result<()> {
+        let compiled = self.compiled(subqueries)?;
+        let sources = &self.sources;
+        let enclosing_cells = self.enclosing_cells.borrow().clone();
+
+        let mut limit = RowLimit::new(limit)?;
+        if limit.is_exhausted() {
+            return Ok(());
+        }
+        //with ORDER BY the rows are collected in a sorter, which spills to disk if there are too many
+        //(or with a LIMIT, only keeps the first of them)
+        let mut sorter = (!compiled.order_by.is_empty()).then(|| Sorter::new(compiled.sort_keys.clone(), sort::SORT_MEMORY_BUDGET).keep_first(limit.rows_needed()));
+        //with DISTINCT, the output rows already produced
+        let mut distinct_rows = self.statement.distinct.then(HashSet::new);
+
+        //hand a finished row to the sorter, or print it straight away and ask to stop once LIMIT is reached
+        let mut emit = |row: &RowContext| -> Result<ControlFlow<()>> {
+            let values = compiled.outputs.iter().map(|output| evaluate(output, row)).collect::<Result<Vec<_>>>()?;
+            if let Some(distinct_rows) = &mut distinct_rows {
+                let key: Vec<Value> = values.iter().zip(&compiled.output_collations).map(|(value, collation)| value.collation_key(*collation)).collect();
+                if !distinct_rows.insert(key) {
+                    return Ok(ControlFlow::Continue(()));
+                }
+            }
+            let Some(sorter) = &mut sorter else {
+                if limit.next() == Some(true) && output(values)?.is_break() {
+                    return Ok(ControlFlow::Break(()));
+                }
+                return Ok(if limit.is_exhausted() { ControlFlow::Break(()) } else { ControlFlow::Continue(()) });
+            };
+            let keys = compiled.order_by.iter().map(|expr| evaluate(expr, row)).collect::<Result<Vec<_>>>()?;
+            sorter.push(SortRow { keys, values })?;
+            Ok(ControlFlow::Continue(()))
+        };
+
+        //rows wait here until the window functions have seen all of them
+        let mut window_rows: Vec<WindowRow> = Vec::new();
+        let mut visit = |row: &RowContext| -> Result<ControlFlow<()>> {
+            if compiled.window_calls.is_empty() {
+                return emit(row);
+            }
+            window_rows.push((row.cells.to_vec(), row.aggregates.to_vec()));
+            Ok(ControlFlow::Continue(()))
+        };
+
+        match &compiled.grouping {
+            None => scan_join(sources, subqueries, &compiled.plan, enclosing_cells.clone(), |cells| {
+                visit(&RowContext { sources, subqueries, cells, aggregates: &[] })
+            })?,
+            Some(grouping) => {
+                let mut aggregation = HashAggregation::new(grouping, GROUP_MEMORY_BUDGET);
+                scan_join(sources, subqueries, &compiled.plan, enclosing_cells.clone(), |cells| {
+                    aggregation.add(&RowContext { sources, subqueries, cells, aggregates: &[] })?;
+                    Ok(ControlFlow::Continue(()))
+                })?;
+                aggregation.finish(sources, subqueries, |group| {
+                    let aggregates = group.finish(&grouping.calls)?;
+                    //a group with no rows still has the row of the enclosing queries
+                    let cells = group.row.as_deref().unwrap_or(&enclosing_cells);
+                    let row = RowContext { sources, subqueries, cells, aggregates: &aggregates };
+                    if let Some(having) = &compiled.having {
+                        if evaluate(having, &row)?.truth() != Some(true) {
+                            return Ok(ControlFlow::Continue(()));
+                        }
+                    }
+                    visit(&row)
+                })?;
+            },
+        }
+
+        //each window function is computed in turn, from the last to the first, by sorting the rows into its window's
+        //partitions and order; the rows are left in the order of the first window's
+        if !compiled.window_calls.is_empty() {
+            for (call, window) in compiled.window_calls.iter().zip(&compiled.windows).rev() {
+                let enclosing = RowContext { sources, subqueries, cells: &enclosing_cells, aggregates: &[] };
+                window_rows = compute_window(call, window, window_rows, &enclosing)?;
+            }
+            for (cells, aggregates) in &window_rows {
+                if emit(&RowContext { sources, subqueries, cells, aggregates })?.is_break() {
+                    break;
+                }
+            }
+        }
+
+        if let Some(sorter) = sorter {
+            for row in sorter.finish()? {
+                let row = row?;
+                match limit.next() {
+                    Some(true) => if output(row.values)?.is_break() {
+                        break;
+                    },
+                    Some(false) => {},
+                    None => break,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+//collect the distinct aggregate calls in an expression
+fn find_aggregates<'a>(expr: &Expr, calls: &'a mut Vec<Expr>) -> Result<&'a [Expr]> {
+    if let Expr::Function { name, args } = expr {
+        if AggregateFunction::from_call(name, args)?.is_some() {
+            let mut nested = Vec::new();
+            let mut windows = Vec::new();
+            for arg in expr.children() {
+                find_aggregates(arg, &mut nested)?;
+                find_windows(arg, &mut windows)?;
+            }
+            if let Some(Expr::WindowFunction { name, .. }) = windows.first() {
+                bail!("misuse of window function {}()", name);
+            }
+            if let Some(Expr::Function { name, .. }) = nested.first() {
+                bail!("misuse of aggregate function {}()", name);
+            }
+            if !calls.contains(expr) {
+                calls.push(expr.clone());
+            }
+            return Ok(calls);
+        }
+    }
+    for child in expr.children() {
+        find_aggregates(child, calls)?;
+    }
+    Ok(calls)
+}
+
+//collect the distinct window function calls in an expression, which can't have others inside them
+fn find_windows(expr: &Expr, calls: &mut Vec<Expr>) -> Result<()> {
+    if let Expr::WindowFunction { .. } = expr {
+        let mut nested = Vec::new();
+        for child in expr.children() {
+            find_windows(child, &mut nested)?;
+        }
+        if let Some(Expr::WindowFunction { name, .. }) = nested.first() {
+            bail!("misuse of window function {}()", name);
+        }
+        if !calls.contains(expr) {
+            calls.push(expr.clone());
+        }
+        return Ok(());
+    }
+    for child in expr.children() {
+        find_windows(child, calls)?;
+    }
+    Ok(())
+}
+
+//the window function calls of a query's result columns and ORDER BY, and the window of each spelled out
+fn query_windows(statement: &SelectStatement, outputs: &[(Expr, Option<String>)], order_by: &[&Expr]) -> Result<(Vec<Expr>, Vec<WindowDefinition>)> {
+    let mut calls = Vec::new();
+    for expr in outputs.iter().map(|(output, _)| output).chain(order_by.iter().copied()) {
+        find_windows(expr, &mut calls)?;
+    }
+    let windows = calls.iter().map(|call| {
+        let Expr::WindowFunction { over, .. } = call else { unreachable!("window calls are window function calls") };
+        resolve_window(&statement.windows, over, 0)
+    }).collect::<Result<Vec<_>>>()?;
+    Ok((calls, windows))
+}
+
+//the window an OVER clause names or defines, with the window of the WINDOW clause it's based on merged in
+fn resolve_window(windows: &[(String, WindowDefinition)], over: &Over, depth: usize) -> Result<WindowDefinition> {
+    let named = |name: &str| match windows.iter().find(|(window_name, _)| window_name.eq_ignore_ascii_case(name)) {
+        //windows based on each other in a loop are missing one
+        Some((_, window)) if depth <= windows.len() => resolve_window(windows, &Over::Window(window.clone()), depth + 1),
+        _ => bail!("no such window: {}", name),
+    };
+    let window = match over {
+        Over::Name(name) => named(name)?,
+        Over::Window(window) => match &window.base {
+            None => window.clone(),
+            Some(base_name) => {
+                let base = named(base_name)?;
+                if !window.partition_by.is_empty() {
+                    bail!("cannot override PARTITION clause of window: {}", base_name);
+                }
+                if !window.order_by.is_empty() && !base.order_by.is_empty() {
+                    bail!("cannot override ORDER BY clause of window: {}", base_name);
+                }
+                if base.frame.is_some() {
+                    bail!("cannot override frame specification of window: {}", base_name);
+                }
+                WindowDefinition {
+                    base: None,
+                    partition_by: base.partition_by,
+                    order_by: if window.order_by.is_empty() { base.order_by } else { window.order_by.clone() },
+                    frame: window.frame.clone(),
+                }
+            },
+        },
+    };
+    if let Some(frame) = &window.frame {
+        if frame.end.position() < frame.start.position() {
+            bail!("unsupported frame specification");
+        }
+        let offset = matches!(frame.start, FrameBound::Preceding(_) | FrameBound::Following(_)) || matches!(frame.end, FrameBound::Preceding(_) | FrameBound::Following(_));
+        if frame.units == FrameUnits::Range && offset && window.order_by.len() != 1 {
+            bail!("RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression");
+        }
+    }
+    Ok(window)
+}
+
+//a row of a query with window functions: its row of each table, and the values of its aggregate calls and
+//of the window function calls computed so far
+type WindowRow = (Vec<Option<TableLeafCell>>, Vec<(Expr, Value)>);
+
+//compute a window function over the rows of a query, adding its value to each row's aggregates and
+//returning the rows sorted by the window's PARTITION BY and ORDER BY (rows that sort the same keep their order)
+fn compute_window(
+    call: &Expr,
+    window: &WindowDefinition,
+    rows: Vec<WindowRow>,
+    enclosing: &RowContext,
+) -> Result<Vec<WindowRow>> {
+    let Expr::WindowFunction { name, args, .. } = call else { unreachable!("window calls are window function calls") };
+    let sources = enclosing.sources;
+    let function = match WindowFunction::from_call(name, args)? {
+        Some(function) => function,
+        None => match AggregateFunction::from_call(name, args)? {
+            Some(_) if matches!(args, FunctionArgs::List { distinct: true, .. }) => bail!("DISTINCT is not supported for window functions"),
+            Some(function) => {
+                let collation = match args {
+                    FunctionArgs::List { args, .. } => args.first().map_or(Ok(Collation::Binary), |arg| expression_collation(arg, sources))?,
+                    FunctionArgs::Star => Collation::Binary,
+                };
+                WindowFunction::Aggregate(Accumulator::new(function, false, collation))
+            },
+            None if ScalarFunction::from_call(name, args)?.is_some() => bail!("{}() may not be used as a window function", name),
+            None => bail!("no such function: {}", name),
+        },
+    };
+    let frame = window.frame.clone().unwrap_or(ast::Frame { units: FrameUnits::Range, start: FrameBound::UnboundedPreceding, end: FrameBound::CurrentRow });
+    let bound = |bound: &FrameBound| -> Result<window::Bound> {
+        Ok(match bound {
+            FrameBound::UnboundedPreceding => window::Bound::UnboundedPreceding,
+            FrameBound::Preceding(offset) => window::Bound::Preceding(evaluate(&prepare(offset, sources)?, enclosing)?),
+            FrameBound::CurrentRow => window::Bound::CurrentRow,
+            FrameBound::Following(offset) => window::Bound::Following(evaluate(&prepare(offset, sources)?, enclosing)?),
+            FrameBound::UnboundedFollowing => window::Bound::UnboundedFollowing,
+        })
+    };
+    let order_keys = window.order_by.iter().map(|term| Ok(SortKey {
+        descending: term.descending,
+        nulls_first: term.nulls_first,
+        collation: expression_collation(&term.expr, sources)?,
+    })).collect::<Result<Vec<_>>>()?;
+    let partition_keys = window.partition_by.iter().map(|expr| Ok(SortKey {
+        descending: false,
+        nulls_first: true,
+        collation: expression_collation(expr, sources)?,
+    })).collect::<Result<Vec<_>>>()?;
+    let spec = Window::new(order_keys.clone(), frame.units, bound(&frame.start)?, bound(&frame.end)?)?;
+
+    //each row's partition and order values, then its arguments
+    let arg_exprs: &[Expr] = match args {
+        FunctionArgs::List { args, .. } => args,
+        FunctionArgs::Star => &[],
+    };
+    let key_exprs = prepare_all(window.partition_by.iter().chain(window.order_by.iter().map(|term| &term.expr)), sources)?;
+    let arg_exprs = prepare_all(arg_exprs, sources)?;
+    let mut keyed = Vec::with_capacity(rows.len());
+    for (cells, aggregates) in rows {
+        let row = RowContext { sources, subqueries: enclosing.subqueries, cells: &cells, aggregates: &aggregates };
+        let keys = key_exprs.iter().map(|expr| evaluate(expr, &row)).collect::<Result<Vec<_>>>()?;
+        let args = arg_exprs.iter().map(|arg| evaluate(arg, &row)).collect::<Result<Vec<_>>>()?;
+        keyed.push((keys, args, cells, aggregates));
+    }
+    let sort_keys: Vec<SortKey> = partition_keys.iter().chain(&order_keys).cloned().collect();
+    keyed.sort_by(|a, b| sort::compare_keys(&sort_keys, &a.0, &b.0));
+
+    let partition_count = partition_keys.len();
+    let mut remaining = keyed.as_mut_slice();
+    while !remaining.is_empty() {
+        let size = remaining.iter().position(|row| sort::compare_keys(&partition_keys, &row.0, &remaining[0].0) != Ordering::Equal).unwrap_or(remaining.len());
+        let (partition, rest) = remaining.split_at_mut(size);
+        let order_values: Vec<Vec<Value>> = partition.iter().map(|row| row.0[partition_count..].to_vec()).collect();
+        let args: Vec<Vec<Value>> = partition.iter().map(|row| row.1.clone()).collect();
+        for (row, value) in partition.iter_mut().zip(function.partition_values(&spec, &order_values, &args)?) {
+            row.3.push((call.clone(), value));
+        }
+        remaining = rest;
+    }
+    Ok(keyed.into_iter().map(|(_, _, cells, aggregates)| (cells, aggregates)).collect())
+}
+
+//whether an expression reads a column outside of any aggregate call (a subquery might)
+fn has_bare_column(expr: &Expr) -> bool {
+    match expr {
+        Expr::Column { .. } | Expr::Subquery(_) | Expr::Exists(_) | Expr::In { list: InList::Select(_), .. } => true,
+        Expr::Function { name, args } if matches!(AggregateFunction::from_call(name, args), std::result::Result::Ok(Some(_))) => false,
+        _ => expr.children().into_iter().any(has_bare_column),
+    }
+}
+
+//the aggregates of a group of rows, and the row its bare columns are read from: the row that gave
+//a lone min() or max() its value (or the group's last row, while it has only seen NULLs), and otherwise the group's first row
+#[derive(Clone)]
+struct AggregateGroup {
+    accumulators: Vec<Accumulator>,
+    //a row of each table of the FROM clause
+    row: Option<Vec<Option<TableLeafCell>>>,
+    keeps_row: bool,
+    lone_extreme: bool,
+    extreme_found: bool,
+}
+
+impl AggregateGroup {
+    fn new(calls: &[Expr], sources: &[Source], keeps_row: bool) -> Result<Self> {
+        let mut accumulators = Vec::with_capacity(calls.len());
+        let mut extremes = 0;
+        for call in calls {
+            let Expr::Function { name, args } = call else { unreachable!("aggregate calls are function calls") };
+            let Some(function) = AggregateFunction::from_call(name, args)? else { unreachable!("{} is an aggregate", name) };
+            let (distinct, collation) = match args {
+                FunctionArgs::List { distinct, args } => (*distinct, match args.first() {
+                    Some(arg) => expression_collation(arg, sources)?,
+                    None => Collation::Binary,
+                }),
+                FunctionArgs::Star => (false, Collation::Binary),
+            };
+            if matches!(function, AggregateFunction::Min | AggregateFunction::Max) {
+                extremes += 1;
+            }
+            accumulators.push(Accumulator::new(function, distinct, collation));
+        }
+        Ok(AggregateGroup { accumulators, row: None, keeps_row, lone_extreme: extremes == 1, extreme_found: false })
+    }
+
+    //fold a row into the accumulators, given the arguments of each aggregate call
+    fn step(&mut self, call_args: &[Vec<Prepared>], row: &RowContext) -> Result<()> {
+        let mut new_extreme = false;
+        for (args, accumulator) in call_args.iter().zip(&mut self.accumulators) {
+            let args = args.iter().map(|arg| evaluate(arg, row)).collect::<Result<Vec<_>>>()?;
+            new_extreme |= accumulator.step(&args);
+        }
+        self.extreme_found |= new_extreme;
+        let keep = match self.lone_extreme {
+            true => new_extreme || !self.extreme_found,
+            false => self.row.is_none(),
+        };
+        if self.keeps_row && keep {
+            self.row = Some(row.cells.to_vec());
+        }
+        Ok(())
+    }
+
+    //rough size in memory
+    fn memory_size(&self) -> usize {
+        let row_size = self.row.iter().flatten().flatten().map(|cell| cell.payload.values.iter().map(Value::memory_size).sum::<usize>()).sum::<usize>();
+        std::mem::size_of::<Self>() + self.accumulators.len() * std::mem::size_of::<Accumulator>() + row_size
+    }
+
+    //each aggregate call with its value
+    fn finish(&self, calls: &[Expr]) -> Result<Vec<(Expr, Value)>> {
+        calls.iter().zip(&self.accumulators).map(|(call, accumulator)| Ok((call.clone(), accumulator.finish()?))).collect()
+    }
+}
+
+//how much a GROUP BY keeps in its hash table before it starts sorting the rows of new groups instead
+const GROUP_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+//the parts of an aggregate query's grouping worked out before it runs: the aggregate calls and their arguments,
+//and the GROUP BY expressions with the collations their values are compared with, so 'a' and 'A' share a NOCASE group
+struct Grouping {
+    calls: Vec<Expr>,
+    //the arguments of each aggregate call
+    call_args: Vec<Vec<Prepared>>,
+    group_by: Vec<Prepared>,
+    collations: Vec<Collation>,
+    //a group no row has been added to yet
+    empty_group: AggregateGroup,
+}
+
+impl Grouping {
+    fn new(calls: Vec<Expr>, group_by: &[&Expr], sources: &[Source], keeps_row: bool) -> Result<Self> {
+        let collations = group_by.iter().map(|expr| expression_collation(expr, sources)).collect::<Result<Vec<_>>>()?;
+        let call_args = calls.iter().map(|call| match call {
+            Expr::Function { args: FunctionArgs::List { args, .. }, .. } => prepare_all(args, sources),
+            _ => Ok(Vec::new()),
+        }).collect::<Result<Vec<_>>>()?;
+        Ok(Grouping {
+            empty_group: AggregateGroup::new(&calls, sources, keeps_row)?,
+            calls,
+            call_args,
+            group_by: prepare_all(group_by.iter().copied(), sources)?,
+            collations,
+        })
+    }
+}
+
+//hash aggregation: every row is folded into the accumulators of its group, found in a hash table by the
+//values of the GROUP BY expressions. Once the table outgrows its memory budget, rows of groups that aren't
+//in it yet go to a sorter instead (which spills to disk), and those groups are aggregated afterwards from
+//the sorted rows, one group at a time. Groups come out in the order of their GROUP BY values
+struct HashAggregation<'a> {
+    grouping: &'a Grouping,
+    groups: HashMap<Vec<Value>, AggregateGroup>,
+    memory_used: usize,
+    memory_budget: usize,
+    //the overflowing rows, keyed by their GROUP BY values, with the rows of the tables as values
+    overflow: Option<Sorter>,
+}
+
+impl<'a> HashAggregation<'a> {
+    fn new(grouping: &'a Grouping, memory_budget: usize) -> Self {
+        HashAggregation { grouping, groups: HashMap::new(), memory_used: 0, memory_budget, overflow: None }
+    }
+
+    fn add(&mut self, row: &RowContext) -> Result<()> {
+        let key = self.grouping.group_by.iter().zip(&self.grouping.collations)
+            .map(|(expr, collation)| Ok(evaluate(expr, row)?.collation_key(*collation)))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(group) = self.groups.get_mut(&key) {
+            return group.step(&self.grouping.call_args, row);
+        }
+        if self.memory_used < self.memory_budget {
+            let mut group = self.grouping.empty_group.clone();
+            group.step(&self.grouping.call_args, row)?;
+            self.memory_used += key.iter().map(Value::memory_size).sum::<usize>() + group.memory_size();
+            self.groups.insert(key, group);
+            return Ok(());
+        }
+        let sort_keys = key.iter().map(|_| SortKey { descending: false, nulls_first: true, collation: Collation::Binary }).collect();
+        let overflow = self.overflow.get_or_insert_with(|| Sorter::new(sort_keys, sort::SORT_MEMORY_BUDGET));
+        overflow.push(SortRow { keys: key, values: cells_to_values(row.cells) })
+    }
+
+    //call `visit` with every group in order, until it asks to stop
+    fn finish(self, sources: &[Source], subqueries: &Subqueries, mut visit: impl FnMut(&AggregateGroup) -> Result<ControlFlow<()>>) -> Result<()> {
+        let mut groups: Vec<(Vec<Value>, AggregateGroup)> = self.groups.into_iter().collect();
+        groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+        //without GROUP BY there is always exactly one group, even when no rows matched
+        if self.grouping.group_by.is_empty() && groups.is_empty() {
+            groups.push((Vec::new(), self.grouping.empty_group.clone()));
+        }
+        let mut groups = groups.into_iter().peekable();
+
+        //the overflow rows come out of the sorter one group after another
+        let mut overflow_rows = match self.overflow {
+            Some(overflow) => Some(overflow.finish()?),
+            None => None,
+        }.into_iter().flatten().peekable();
+        let mut next_overflow_group = || -> Result<Option<(Vec<Value>, AggregateGroup)>> {
+            let Some(first) = overflow_rows.next() else { return Ok(None) };
+            let SortRow { keys, values } = first?;
+            let mut group = self.grouping.empty_group.clone();
+            let mut pending = Some(values);
+            while let Some(values) = pending.take() {
+                let cells = values_to_cells(values);
+                group.step(&self.grouping.call_args, &RowContext { sources, subqueries, cells: &cells, aggregates: &[] })?;
+                if let Some(next) = overflow_rows.next_if(|row| row.as_ref().map_or(true, |row| row.keys == keys)) {
+                    pending = Some(next?.values);
+                }
+            }
+            Ok(Some((keys, group)))
+        };
+
+        //merge them with the groups from the hash table, whose keys they never share
+        let mut overflow_group = next_overflow_group()?;
+        loop {
+            let from_hash_table = match (groups.peek(), &overflow_group) {
+                (Some((hashed_key, _)), Some((overflow_key, _))) => hashed_key < overflow_key,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return Ok(()),
+            };
+            let group = match from_hash_table {
+                true => groups.next().map(|(_, group)| group),
+                false => std::mem::replace(&mut overflow_group, next_overflow_group()?).map(|(_, group)| group),
+            };
+            if let Some(group) = group {
+                if visit(&group)?.is_break() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_hash_aggregation_overflow() {
+    let database = test_database("hash-aggregation-overflow", &[]);
+    let subqueries = Subqueries::new(&database, &[], 0, Vec::new());
+    let table = sql::parse_create_table("CREATE TABLE t (k TEXT COLLATE NOCASE, v INTEGER)").unwrap();
+    let rows: Vec<TableLeafCell> = [("b", 1), ("a", 2), ("c", 3), ("A", 4), ("b", 5), ("d", 6), ("c", 7), ("B", 8)].iter().enumerate()
+        .map(|(position, (k, v))| TableLeafCell { row_id: position as i64 + 1, payload: Record { values: vec![Value::Text(k.to_string()), Value::Integer(*v)] } })
+        .collect();
+    let sources = [Source::subquery("t", table.columns, Rc::new(rows.clone()), false, 0)];
+    let statement = sql::parse_select("SELECT count(*), sum(v) FROM t GROUP BY k").unwrap();
+    let calls: Vec<Expr> = statement.columns.iter().map(|column| match column {
+        ResultColumn::Expr { expr, .. } => expr.clone(),
+        _ => unreachable!("the result columns are expressions"),
+    }).collect();
+    let group_by: Vec<&Expr> = statement.group_by.iter().collect();
+    let grouping = Grouping::new(calls.clone(), &group_by, &sources, true).unwrap();
+
+    //each group's k (from the row kept for bare columns), count(*) and sum(v)
+    let groups = |memory_budget: usize| {
+        let mut aggregation = HashAggregation::new(&grouping, memory_budget);
+        for row in &rows {
+            aggregation.add(&RowContext { sources: &sources, subqueries: &subqueries, cells: &[Some(row.clone())], aggregates: &[] }).unwrap();
+        }
+        let mut groups = Vec::new();
+        aggregation.finish(&sources, &subqueries, |group| {
+            let values = group.finish(&calls)?.into_iter().map(|(_, value)| value.to_string());
+            let row = RowContext { sources: &sources, subqueries: &subqueries, cells: group.row.as_deref().unwrap_or_default(), aggregates: &[] };
+            groups.push(std::iter::once(evaluate(&prepare(&Expr::column("k"), &sources)?, &row)?.to_string()).chain(values).collect::<Vec<_>>().join("|"));
+            Ok(ControlFlow::Continue(()))
+        }).unwrap();
+        groups
+    };
+    //with a budget of a single group, the rows of the groups after "b" go through the sorter, and the groups
+    //of both come out merged in order, the same as when they all fit in the hash table
+    let expected = ["a|2|6", "b|3|14", "c|2|10", "d|1|6"];
+    assert_eq!(groups(1), expected);
+    assert_eq!(groups(GROUP_MEMORY_BUDGET), expected);
+}
+
+//flatten a row of each table into values for the sorter: a table's row is its rowid, the number of values
+//in its record and the values, and a missing row (of a LEFT JOIN that matched nothing) is a NULL
+fn cells_to_values(cells: &[Option<TableLeafCell>]) -> Vec<Value> {
+    let mut values = Vec::new();
+    for cell in cells {
+        match cell {
+            Some(cell) => {
+                values.push(Value::Integer(cell.row_id));
+                values.push(Value::Integer(cell.payload.values.len() as i64));
+                values.extend(cell.payload.values.iter().cloned());
+            },
+            None => values.push(Value::Null),
+        }
+    }
+    values
+}
+
+fn values_to_cells(values: Vec<Value>) -> Vec<Option<TableLeafCell>> {
+    let mut values = values.into_iter();
+    let mut cells = Vec::new();
+    while let Some(row_id) = values.next() {
+        cells.push(row_id.as_integer().map(|row_id| {
+            let count = values.next().and_then(|count| count.as_integer()).unwrap_or(0) as usize;
+            TableLeafCell { row_id, payload: Record { values: values.by_ref().take(count).collect() } }
+        }));
+    }
+    cells
+}
+
+//counts rows off against LIMIT and OFFSET
+struct RowLimit {
+    offset: u64,
+    //None when there's no limit
+    remaining: Option<u64>,
+}
+
+impl RowLimit {
+    fn new(limit: Option<&Limit>) -> Result<Self> {
+        let Some(limit) = limit else {
+            return Ok(RowLimit { offset: 0, remaining: None });
+        };
+        //a negative LIMIT means no limit, and a negative OFFSET means none
+        let count = limit_value(&limit.count)?;
+        let offset = match &limit.offset {
+            Some(offset) => limit_value(offset)?.max(0) as u64,
+            None => 0,
+        };
+        Ok(RowLimit { offset, remaining: u64::try_from(count).ok() })
+    }
+
+    //whether the next row produced should be output, or None once the limit has been reached
+    fn next(&mut self) -> Option<bool> {
+        if self.is_exhausted() {
+            return None;
+        }
+        if self.offset > 0 {
+            self.offset -= 1;
+            return Some(false);
+        }
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= 1;
+        }
+        Some(true)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    //how many rows are produced before the limit is reached, or None when there's no limit
+    fn rows_needed(&self) -> Option<u64> {
+        self.remaining.map(|remaining| remaining.saturating_add(self.offset))
+    }
+}
+
+//the value of a LIMIT or OFFSET expression, which has to be a constant integer
+fn limit_value(expr: &Expr) -> Result<i64> {
+    let value = match expr {
+        Expr::Literal(literal) => Value::from_literal(literal),
+        Expr::Unary(operator @ (UnaryOperator::Negate | UnaryOperator::Plus), inner) => {
+            let n = limit_value(inner)?;
+            return Ok(if *operator == UnaryOperator::Negate { n.wrapping_neg() } else { n });
+        },
+        _ => bail!("LIMIT and OFFSET must be constant integers"),
+    };
+    match value.apply_affinity(Affinity::Integer).as_integer() {
+        Some(n) => Ok(n),
+        None => bail!("datatype mismatch"),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_limit_reads() {
+    //t has 70 rows over 10 leaves of 7 under its root, page 2
+    let row = |row_id: i64| test_table_leaf_cell(row_id, &test_record(&[Value::Integer(row_id * 10)]));
+    let mut pages = vec![test_page(0x05, Some(12), &(0..9).map(|leaf| test_table_interior_cell(3 + leaf, 7 * (leaf as i64 + 1))).collect::<Vec<_>>())];
+    pages.extend((0..10).map(|leaf| test_page(0x0d, None, &(1..=7).map(|row_id| row(7 * leaf + row_id)).collect::<Vec<_>>())));
+    let database = test_database("limit-reads", &pages);
+    let schema_tables = [Schema {
+        schema_type: "table".to_string(),
+        name: "t".to_string(),
+        tbl_name: "t".to_string(),
+        root_page: 2,
+        sql: "CREATE TABLE t (k INTEGER)".to_string(),
+    }];
+    let run = |sql: &str| {
+        let statement = sql::parse_select(sql).unwrap();
+        let subqueries = Subqueries::new(&database, &schema_tables, 0, Vec::new());
+        database.pages_read.set(0);
+        let rows = Query::new(&subqueries, &statement, None).unwrap().collect(&subqueries).unwrap();
+        (rows.iter().map(|row| row.payload.values[0].to_string()).collect::<Vec<_>>().join(","), database.pages_read.get())
+    };
+    //the scan stops at the first row, having read the root and the first leaf
+    assert_eq!(run("SELECT k FROM t LIMIT 1"), ("10".to_string(), 2));
+    //rows skipped by OFFSET within that leaf don't read any further
+    assert_eq!(run("SELECT k FROM t LIMIT 2 OFFSET 4"), ("50,60".to_string(), 2));
+    //crossing into the next leaf reads just that one
+    assert_eq!(run("SELECT k FROM t LIMIT 2 OFFSET 6"), ("70,80".to_string(), 3));
+    assert_eq!(run("SELECT k FROM t").1, 11);
+}
+
+//whether any table of the FROM clause has a column called `name`
+fn is_source_column(sources: &[Source], name: &str) -> bool {
+    sources.iter().any(|source| source.table.column_index(name).is_some())
+}
+
+//the expression each ORDER BY or GROUP BY term stands for: a number k means the k-th result column,
+//and a bare name that matches a result column's alias means that column's expression
+//(in GROUP BY only when no table has a column of that name)
+fn result_column_references<'a>(terms: &[&'a Expr], clause: &str, outputs: &'a [(Expr, Option<String>)], sources: &[Source]) -> Result<Vec<&'a Expr>> {
+    terms.iter().enumerate().map(|(term_index, term)| match term {
+        Expr::Literal(Literal::Integer(k)) => match usize::try_from(*k) {
+            std::result::Result::Ok(k) if (1..=outputs.len()).contains(&k) => Ok(&outputs[k - 1].0),
+            _ => bail!("{} {} BY term out of range - should be between 1 and {}", ordinal(term_index + 1), clause, outputs.len()),
+        },
+        Expr::Column { table: None, name } if clause == "ORDER" || !is_source_column(sources, name) => {
+            let aliased = outputs.iter().find(|(_, alias)| alias.as_ref().is_some_and(|alias| alias.eq_ignore_ascii_case(name)));
+            Ok(aliased.map_or(*term, |(output, _)| output))
+        },
+        expr => Ok(*expr),
+    }).collect()
+}
+
+//the result columns the ORDER BY terms of a compound SELECT sort by, with how they sort: each term has to be
+//a column number or the name of a result column of one of the SELECTs (the first that has it), optionally with COLLATE.
+//`columns` are the result columns of each SELECT, the first SELECT's giving the collations
+fn compound_order_by(terms: &[OrderingTerm], columns: &[Vec<ColumnDef>]) -> Result<Vec<(usize, SortKey)>> {
+    let column_count = columns[0].len();
+    terms.iter().enumerate().map(|(term_index, term)| {
+        let collation = explicit_collation(&term.expr);
+        let mut expr = &term.expr;
+        while let Expr::Collate { expr: inner, .. } = expr {
+            expr = inner;
+        }
+        let column_index = match expr {
+            Expr::Literal(Literal::Integer(k)) => match usize::try_from(*k) {
+                std::result::Result::Ok(k) if (1..=column_count).contains(&k) => Some(k - 1),
+                _ => bail!("{} ORDER BY term out of range - should be between 1 and {}", ordinal(term_index + 1), column_count),
+            },
+            Expr::Column { table: None, name } => columns.iter().find_map(|columns| columns.iter().position(|column| column.name.eq_ignore_ascii_case(name))),
+            _ => None,
+        };
+        let Some(column_index) = column_index else {
+            bail!("{} ORDER BY term does not match any column in the result set", ordinal(term_index + 1));
+        };
+        let collation = collation.or(columns[0][column_index].collation()).map_or(Ok(Collation::Binary), Collation::from_name)?;
+        Ok((column_index, SortKey { descending: term.descending, nulls_first: term.nulls_first, collation }))
+    }).collect()
+}
+
+//1st, 2nd, 3rd, 4th, ..., as error messages number the terms of a clause
+fn ordinal(n: usize) -> String {
+    match n % 10 {
+        1 if n % 100 != 11 => format!("{}st", n),
+        2 if n % 100 != 12 => format!("{}nd", n),
+        3 if n % 100 != 13 => format!("{}rd", n),
+        _ => format!("{}th", n),
+    }
+}
+
+//replace the names in an expression that are result column aliases rather than columns of the tables
+//with the expressions they stand for
+fn replace_aliases(expr: &mut Expr, outputs: &[(Expr, Option<String>)], sources: &[Source]) {
+    if let Expr::Column { table: None, name } = expr {
+        if !is_source_column(sources, name) {
+            if let Some((output, _)) = outputs.iter().find(|(_, alias)| alias.as_ref().is_some_and(|alias| alias.eq_ignore_ascii_case(name))) {
+                *expr = output.clone();
+            }
+        }
+        return;
+    }
+    for child in expr.children_mut() {
+        replace_aliases(child, outputs, sources);
+    }
+}
+
+// ***JOINS***
+
+//how the rows of a query's join are read: the terms checked before reading any table, and the tables in the
+//order they're read, each with how its rows are found and what they're checked against
+struct JoinPlan<'a> {
+    //the terms that read none of the query's tables
+    constant_terms: Vec<Prepared>,
+    //a level for each of the query's own tables, outermost first (the tables of the enclosing queries
+    //already have their rows, and are never read)
+    levels: Vec<JoinLevel<'a>>,
+}
+
+//a table of the join: how its rows are found, and the terms they're checked against
+#[derive(Default)]
+struct JoinLevel<'a> {
+    //the table's position in the FROM clause, among the tables of the enclosing queries
+    source: usize,
+    //the ON terms of a LEFT JOIN, which decide whether a row matches rather than whether the joined row is kept
+    match_terms: Vec<Prepared>,
+    //the WHERE terms and the ON terms of inner joins whose tables have all been read by this level
+    filter_terms: Vec<Prepared>,
+    access: Access<'a>,
+}
+
+//how the rows of a table of the join are read
+#[derive(Default)]
+enum Access<'a> {
+    //every row in rowid order
+    #[default]
+    Scan,
+    //the rows whose rowids satisfy comparisons with values known by the time the table is read, each with the values
+    //it compares with (one, or those of an IN list, any of which can be equal) and the affinity they're compared with
+    RowIds(Vec<(BinaryOperator, Vec<Prepared>, Affinity)>),
+    //the rows an index finds: those whose first columns equal values known by the time the table is read, and whose
+    //next column (or rowid, once all the columns are equal) satisfies comparisons with such values; with no terms
+    //at all, every row in the order of the index. A covering index has every column of the table the query reads,
+    //which are then read from the index, the table column of each of its columns given here. The index's columns
+    //are ordered by the collations given, which its key is compared in, and `order` has the table columns its
+    //entries are in the order of: its columns up to the first in descending order, and if there's none, the rowid
+    Index { index: &'a Schema, key: Vec<KeyTerm>, covering: Option<Vec<usize>>, collations: Vec<Collation>, order: Vec<ColumnRef> },
+}
+
+//a comparison an index is searched with: the index column's name, the comparison turned around if needed so the
+//column is on the left, and the values with the affinity they're compared with: one, or those of an IN list,
+//each of which the index is searched for
+struct KeyTerm {
+    column: String,
+    operator: BinaryOperator,
+    exprs: Vec<Prepared>,
+    affinity: Affinity,
+}
+
+//what reading a table a certain way is expected to cost, and how many rows it's expected to find. Without
+//statistics every table is taken to have about a million rows, an index to find ten rows for a value
+//(fewer as more columns are equal, and one when a unique index has them all equal) and each bound of a
+//range to keep a quarter of them, much as sqlite guesses
+#[derive(Debug, Clone, Copy)]
+struct Estimate {
+    cost: f64,
+    rows: f64,
+}
+
+const TABLE_ROWS: f64 = 1048576.0;
+//going down a b-tree to a key
+const SEEK_COST: f64 = 20.0;
+//reading a row of a table while walking it
+const ROW_COST: f64 = 4.0;
+//reading an entry of an index while walking it
+const ENTRY_COST: f64 = 1.0;
+//reading the table row an index entry points at
+const ROW_LOOKUP_COST: f64 = 6.0;
+//the most tables whose order is chosen by trying every order; beyond that they're read in the order of the FROM clause
+const MAX_ORDERED_TABLES: usize = 10;
+
+//plan how a query reads its tables: the order they're read in, how the rows of each are found and which WHERE and ON
+//terms each is checked with. A term is checked as soon as all the tables it reads have a row, and a comparison between
+//a column of a table and a value known by then (from constants and the tables read before it) can find the table's rows:
+//as a rowid lookup or range, or a search of an index whose first columns it compares. The tables are read in the order
+//that's expected to cost least, unless a LEFT JOIN fixes their order to that of the FROM clause.
+//The query's own tables start at `first`, after the enclosing queries' ones, whose columns are known throughout
+fn plan_joins<'a>(
+    schema_tables: &'a [Schema],
+    sources: &[Source],
+    statement: &SelectStatement,
+    outputs: &[(Expr, Option<String>)],
+    first: usize,
+) -> Result<JoinPlan<'a>> {
+    //the WHERE terms and those of the inner joins, and the ON terms of each LEFT JOIN
+    let mut terms = Vec::new();
+    if let Some(condition) = &statement.where_clause {
+        conjuncts(condition, &mut terms);
+    }
+    let mut match_terms: Vec<Vec<&Expr>> = vec![Vec::new(); sources.len()];
+    let joins = statement.from.iter().flat_map(|from| &from.joins);
+    for (join_index, join) in joins.enumerate() {
+        let source_index = first + join_index + 1;
+        let mut join_terms = Vec::new();
+        if let JoinConstraint::On(condition) = &join.constraint {
+            conjuncts(condition, &mut join_terms);
+        }
+        join_terms.extend(&sources[source_index].using_terms);
+        if join.operator == JoinOperator::Left {
+            //the ON terms of a LEFT JOIN can't look at the tables to its right
+            for term in &join_terms {
+                if last_source(term, sources)?.is_some_and(|last| last > source_index) {
+                    bail!("ON clause references tables to its right");
+                }
+            }
+            match_terms[source_index] = join_terms;
+        } else {
+            terms.extend(join_terms);
+        }
+    }
+
+    //the columns the query reads, to tell which indexes cover a table
+    let mut read_exprs: Vec<&Expr> = outputs.iter().map(|(output, _)| output).chain(terms.iter().copied()).chain(match_terms.iter().flatten().copied()).collect();
+    read_exprs.extend(statement.group_by.iter().chain(&statement.having).chain(statement.order_by.iter().map(|term| &term.expr)));
+    read_exprs.extend(statement.windows.iter().flat_map(|(_, window)| window.expressions()));
+    let read = read_columns(sources, &read_exprs);
+    let indexes = sources.iter().map(|source| match source.rows {
+        Some(_) => Vec::new(),
+        None => table_indexes(schema_tables, &source.table_name, &source.table),
+    }).collect::<Vec<_>>();
+    let access = |source_index: usize, known: &[bool]| {
+        //a LEFT JOIN's table can only skip rows its ON terms would reject
+        let candidates = if sources[source_index].outer { &match_terms[source_index] } else { &terms };
+        let read = read.as_ref().map(|read| read[source_index].as_slice());
+        best_access(sources, source_index, candidates, known, &indexes[source_index], read)
+    };
+
+    let mut known: Vec<bool> = (0..sources.len()).map(|source_index| source_index < first).collect();
+    let order = match sources.len() - first {
+        count if count <= MAX_ORDERED_TABLES && sources[first..].iter().all(|source| !source.outer) => cheapest_order(first, &known, |source_index, known| Ok(access(source_index, known)?.1))?,
+        _ => (first..sources.len()).collect(),
+    };
+    let mut plan = JoinPlan { constant_terms: Vec::new(), levels: Vec::new() };
+    for &source_index in &order {
+        let (access, _) = access(source_index, &known)?;
+        known[source_index] = true;
+        let match_terms = prepare_all(match_terms[source_index].iter().copied(), sources)?;
+        plan.levels.push(JoinLevel { source: source_index, match_terms, filter_terms: Vec::new(), access });
+    }
+    for term in terms {
+        //a subquery could read any of the tables
+        let level = match has_subquery(term) {
+            true => plan.levels.len().checked_sub(1),
+            false => {
+                let mut read = Vec::new();
+                term_sources(term, sources, &mut read)?;
+                plan.levels.iter().rposition(|level| read.contains(&level.source))
+            },
+        };
+        let prepared = prepare(term, sources)?;
+        match level {
+            Some(level) => plan.levels[level].filter_terms.push(prepared),
+            None => plan.constant_terms.push(prepared),
+        }
+    }
+    Ok(plan)
+}
+
+//the order of the query's own tables, from `first` on, that's expected to cost least, trying every order by building up
+//the cheapest way to read each set of tables from the cheapest ways to read the sets a table smaller
+//(of orders that cost the same, the one closest to that of the FROM clause)
+fn cheapest_order(first: usize, known: &[bool], estimate: impl Fn(usize, &[bool]) -> Result<Estimate>) -> Result<Vec<usize>> {
+    let count = known.len() - first;
+    //for each set of tables, the cheapest way found to read them: its cost, the rows it finds and its last table
+    let mut best: Vec<Option<(Estimate, usize)>> = vec![None; 1 << count];
+    best[0] = Some((Estimate { cost: 0.0, rows: 1.0 }, 0));
+    for set in 0..best.len() {
+        let Some((so_far, _)) = best[set] else { continue };
+        let mut known = known.to_vec();
+        for table in 0..count {
+            known[first + table] = set & (1 << table) != 0;
+        }
+        for table in (0..count).filter(|table| set & (1 << table) == 0) {
+            let estimate = estimate(first + table, &known)?;
+            let total = Estimate { cost: so_far.cost + so_far.rows * estimate.cost, rows: so_far.rows * estimate.rows };
+            let next = &mut best[set | (1 << table)];
+            if next.map_or(true, |(next, _)| total.cost < next.cost) {
+                *next = Some((total, table));
+            }
+        }
+    }
+    let mut order = Vec::with_capacity(count);
+    let mut set = best.len() - 1;
+    while set != 0 {
+        let Some((_, table)) = best[set] else { unreachable!("every set of tables can be read") };
+        order.push(first + table);
+        set &= !(1 << table);
+    }
+    order.reverse();
+    Ok(order)
+}
+
+//the cheapest way to read the rows of a table, given the terms that could find them and which tables have a row by
+//then, and what it's expected to cost. `read` has the columns of the table the query reads, or is None when it
+//can't tell which it reads
+fn best_access<'a>(
+    sources: &[Source],
+    source_index: usize,
+    candidates: &[&Expr],
+    known: &[bool],
+    indexes: &[(&'a Schema, CreateIndex)],
+    read: Option<&[bool]>,
+) -> Result<(Access<'a>, Estimate)> {
+    use BinaryOperator::*;
+    let source = &sources[source_index];
+    let scan = Estimate { cost: TABLE_ROWS * ROW_COST, rows: TABLE_ROWS };
+    //a subquery's rows are only ever scanned
+    if source.rows.is_some() {
+        return Ok((Access::Scan, scan));
+    }
+    let lookups: Vec<Lookup> = candidates.iter().filter_map(|term| lookup_term(term, sources, source_index, known)).collect();
+    //the share of the rows a range keeps, for the comparisons that bound it
+    let range_share = |operators: &mut dyn Iterator<Item = BinaryOperator>| {
+        let (mut lower, mut upper) = (false, false);
+        for operator in operators {
+            lower |= matches!(operator, Greater | GreaterEqual);
+            upper |= matches!(operator, Less | LessEqual);
+        }
+        0.25f64.powi(lower as i32 + upper as i32)
+    };
+    let rowid_affinity = |lookup: &Lookup| lookup.affinities(Affinity::Integer, sources).1;
+
+    let bounds: Vec<(BinaryOperator, Vec<Prepared>, Affinity)> = lookups.iter()
+        .filter(|lookup| matches!(lookup.column, ColumnRef::RowId))
+        .map(|lookup| Ok((lookup.operator, prepare_all(lookup.exprs.iter().copied(), sources)?, rowid_affinity(lookup))))
+        .collect::<Result<_>>()?;
+    let (mut best, mut best_estimate) = (Access::Scan, scan);
+    if !bounds.is_empty() {
+        //a row for each value an equality allows
+        let (seeks, rows) = match bounds.iter().filter(|(operator, _, _)| *operator == Equal).map(|(_, exprs, _)| exprs.len()).min() {
+            Some(values) => (values as f64, values as f64),
+            None => (1.0, TABLE_ROWS * range_share(&mut bounds.iter().map(|(operator, _, _)| *operator))),
+        };
+        (best, best_estimate) = (Access::RowIds(bounds), Estimate { cost: seeks * SEEK_COST + rows * ROW_COST, rows });
+    }
+
+    for (index, definition) in indexes {
+        let Some(columns) = definition.columns.iter().map(|column| source.table.column_index(&column.name)).collect::<Option<Vec<_>>>() else {
+            continue;
+        };
+        let covering = read.is_some_and(|read| {
+            read.iter().enumerate().all(|(column_index, read)| !read || columns.contains(&column_index) || source.rowid_alias == Some(column_index))
+        });
+        //the collations the index's columns are ordered by: the index's own, else the table column's
+        let collations = definition.columns.iter().zip(&columns)
+            .map(|(indexed, &column_index)| indexed.collation.as_deref().or(source.table.columns[column_index].collation()).map_or(Ok(Collation::Binary), Collation::from_name))
+            .collect::<Result<Vec<_>>>()?;
+        //the columns can be searched while they're equal to values, and the first one that isn't can be searched for
+        //a range. The index holds the columns' values as stored, so the comparison mustn't convert them, and it's
+        //ordered by their collations in ascending order, so the comparison has to be in the column's collation
+        let mut key = Vec::new();
+        let mut equal_columns = 0;
+        for ((indexed, &column_index), &collation) in definition.columns.iter().zip(&columns).zip(&collations) {
+            let column = &source.table.columns[column_index];
+            if indexed.descending {
+                break;
+            }
+            let usable = |lookup: &&Lookup| {
+                let (column_affinity, _) = lookup.affinities(column.affinity(), sources);
+                matches!(lookup.column, ColumnRef::Column(index) if index == column_index) && lookup.collation == collation && column_affinity == Affinity::Blob
+            };
+            let key_term = |lookup: &Lookup| -> Result<KeyTerm> {
+                Ok(KeyTerm {
+                    column: indexed.name.clone(),
+                    operator: lookup.operator,
+                    exprs: prepare_all(lookup.exprs.iter().copied(), sources)?,
+                    affinity: lookup.affinities(column.affinity(), sources).1,
+                })
+            };
+            //an equality with a single value narrows the search most
+            if let Some(lookup) = lookups.iter().filter(usable).filter(|lookup| lookup.operator == Equal).min_by_key(|lookup| lookup.exprs.len()) {
+                key.push(key_term(lookup)?);
+                equal_columns += 1;
+                continue;
+            }
+            for lookup in lookups.iter().filter(usable) {
+                key.push(key_term(lookup)?);
+            }
+            break;
+        }
+        //after all of its columns, an index is ordered by rowid
+        if equal_columns == columns.len() {
+            let rowid_range = lookups.iter().filter(|lookup| matches!(lookup.column, ColumnRef::RowId) && lookup.operator != Equal);
+            for lookup in rowid_range {
+                key.push(KeyTerm { column: "rowid".to_string(), operator: lookup.operator, exprs: prepare_all(lookup.exprs.iter().copied(), sources)?, affinity: rowid_affinity(lookup) });
+            }
+        }
+
+        let estimate = match (key.is_empty(), covering) {
+            //an index that's narrower than its table is quicker to read through
+            (true, true) if columns.len() + 1 < source.table.columns.len() => Estimate {
+                cost: TABLE_ROWS * ROW_COST * (columns.len() + 1) as f64 / source.table.columns.len() as f64,
+                rows: TABLE_ROWS,
+            },
+            (true, _) => continue,
+            (false, _) => {
+                let rows = match equal_columns {
+                    0 => TABLE_ROWS,
+                    count if count == columns.len() && definition.unique => 1.0,
+                    count => (11 - count.min(5)) as f64,
+                };
+                //a search for each combination of the values of the equal columns
+                let seeks: f64 = key[..equal_columns].iter().map(|term| term.exprs.len() as f64).product();
+                let rows = seeks * rows * range_share(&mut key[equal_columns..].iter().map(|term| term.operator));
+                let row_cost = if covering { ENTRY_COST } else { ENTRY_COST + ROW_LOOKUP_COST };
+                Estimate { cost: seeks * SEEK_COST + rows * row_cost, rows }
+            },
+        };
+        if estimate.cost < best_estimate.cost {
+            let ascending = definition.columns.iter().position(|indexed| indexed.descending).unwrap_or(columns.len());
+            let mut order: Vec<ColumnRef> = columns[..ascending].iter()
+                .map(|&column_index| if source.rowid_alias == Some(column_index) { ColumnRef::RowId } else { ColumnRef::Column(column_index) })
+                .collect();
+            if ascending == columns.len() {
+                order.push(ColumnRef::RowId);
+            }
+            (best, best_estimate) = (Access::Index { index, key, covering: covering.then_some(columns), collations, order }, estimate);
+        }
+    }
+    Ok((best, best_estimate))
+}
+
+//the usable indexes of a table, whose definitions can be read (partial indexes don't have all the rows); those sqlite
+//makes for its PRIMARY KEY and UNIQUE constraints have no SQL, so theirs is worked out from the table's
+fn table_indexes<'a>(schema_tables: &'a [Schema], table_name: &str, table: &CreateTable) -> Vec<(&'a Schema, CreateIndex)> {
+    let autoindexes = table.autoindexes();
+    schema_tables.iter().filter(|schema| schema.schema_type == "index" && schema.tbl_name.eq_ignore_ascii_case(table_name))
+        .filter_map(|schema| {
+            if schema.name.starts_with("sqlite_autoindex_") {
+                let (_, number) = schema.name.rsplit_once('_')?;
+                let columns = autoindexes.get(number.parse::<usize>().ok()?.checked_sub(1)?)?;
+                return Some((schema, CreateIndex { name: schema.name.clone(), table: table_name.to_string(), columns: columns.clone(), unique: true, where_clause: None }));
+            }
+            match sql::parse_create_index(&schema.sql) {
+                std::result::Result::Ok(index) if index.where_clause.is_none() => Some((schema, index)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+//for each table, which of its columns the expressions read, or None if they have a subquery, which could read any
+//(names that aren't columns are aliases of result columns, whose own columns are among the expressions)
+fn read_columns(sources: &[Source], exprs: &[&Expr]) -> Option<Vec<Vec<bool>>> {
+    fn visit(expr: &Expr, sources: &[Source], read: &mut [Vec<bool>]) -> bool {
+        match expr {
+            Expr::Subquery(_) | Expr::Exists(_) | Expr::In { list: InList::Select(_), .. } => return false,
+            Expr::Column { table, name } => {
+                if let std::result::Result::Ok((source_index, ColumnRef::Column(column_index))) = resolve_source_column(sources, table.as_deref(), name) {
+                    read[source_index][column_index] = true;
+                }
+            },
+            _ => {},
+        }
+        expr.children().into_iter().all(|child| visit(child, sources, read))
+    }
+    let mut read: Vec<Vec<bool>> = sources.iter().map(|source| vec![false; source.table.columns.len()]).collect();
+    exprs.iter().all(|expr| visit(expr, sources, &mut read)).then_some(read)
+}
+
+//whether an expression has a subquery in it
+fn has_subquery(expr: &Expr) -> bool {
+    matches!(expr, Expr::Subquery(_) | Expr::Exists(_) | Expr::In { list: InList::Select(_), .. }) || expr.children().into_iter().any(has_subquery)
+}
+
+//the index of the last table of the FROM clause an expression reads a column of, if it reads any
+fn last_source(expr: &Expr, sources: &[Source]) -> Result<Option<usize>> {
+    let mut read = Vec::new();
+    term_sources(expr, sources, &mut read)?;
+    Ok(read.into_iter().max())
+}
+
+//add the indexes of the tables of the FROM clause an expression reads columns of to `read`
+fn term_sources(expr: &Expr, sources: &[Source], read: &mut Vec<usize>) -> Result<()> {
+    if let Expr::Column { table, name } = expr {
+        read.push(resolve_source_column(sources, table.as_deref(), name)?.0);
+    }
+    for child in expr.children() {
+        term_sources(child, sources, read)?;
+    }
+    Ok(())
+}
+
+//a comparison between a column of the table being read and a value known before it's read, or an IN list of such
+//values, which is an equality with any of them
+struct Lookup<'a> {
+    column: ColumnRef,
+    //turned around if needed so the column is on the left
+    operator: BinaryOperator,
+    exprs: Vec<&'a Expr>,
+    collation: Collation,
+    in_list: bool,
+}
+
+impl Lookup<'_> {
+    //the affinities the column, whose own is `column_affinity`, and the values are converted to before they're compared:
+    //an IN list converts its values to the column's
+    fn affinities(&self, column_affinity: Affinity, sources: &[Source]) -> (Affinity, Affinity) {
+        match self.in_list {
+            true => (Affinity::Blob, column_affinity),
+            false => value::comparison_affinities(Some(column_affinity), expression_affinity(self.exprs[0], sources)),
+        }
+    }
+}
+
+//the comparison a term makes between a column of the table at `source_index` and a value that only reads
+//the tables that are `known`, if it makes one
+fn lookup_term<'a>(term: &'a Expr, sources: &[Source], source_index: usize, known: &[bool]) -> Option<Lookup<'a>> {
+    use BinaryOperator::*;
+    let column_of_source = |expr: &Expr| match expr {
+        Expr::Column { table, name } => match resolve_source_column(sources, table.as_deref(), name) {
+            std::result::Result::Ok((column_source, column)) if column_source == source_index => Some(column),
+            _ => None,
+        },
+        _ => None,
+    };
+    let known = |expr: &Expr| {
+        let mut read = Vec::new();
+        !has_subquery(expr) && term_sources(expr, sources, &mut read).is_ok() && read.iter().all(|&read| known[read])
+    };
+    if let Expr::In { expr: left, list: InList::Values(values), negated: false } = term {
+        let column = column_of_source(left)?;
+        if values.is_empty() || !values.iter().all(known) {
+            return None;
+        }
+        let collation = expression_collation(left, sources).ok()?;
+        return Some(Lookup { column, operator: Equal, exprs: values.iter().collect(), collation, in_list: true });
+    }
+    let Expr::Binary(left, operator, right) = term else { return None };
+    if !matches!(operator, Equal | Less | LessEqual | Greater | GreaterEqual) {
+        return None;
+    }
+    let collation = comparison_collation(left, right, sources).ok()?;
+    if let (Some(column), true) = (column_of_source(left), known(right)) {
+        return Some(Lookup { column, operator: *operator, exprs: vec![right.as_ref()], collation, in_list: false });
+    }
+    if let (Some(column), true) = (column_of_source(right), known(left)) {
+        let flipped = match operator {
+            Less => Greater,
+            LessEqual => GreaterEqual,
+            Greater => Less,
+            GreaterEqual => LessEqual,
+            other => *other,
+        };
+        return Some(Lookup { column, operator: flipped, exprs: vec![left.as_ref()], collation, in_list: false });
+    }
+    None
+}
+
+impl<'a> Access<'a> {
+    //the columns of `source` whose order the rows are read in, ascending, each with the collation it's ordered by and
+    //whether all the rows read have the same value in it (the rowid, which no two rows share, ends them)
+    fn order(&self, source: &Source) -> Vec<(ColumnRef, Collation, bool)> {
+        match self {
+            Access::Scan if source.rows.is_some() => Vec::new(),
+            Access::Scan | Access::RowIds(_) => vec![(ColumnRef::RowId, Collation::Binary, false)],
+            Access::Index { key, collations, order, .. } => {
+                let equal = key.iter().take_while(|term| term.operator == BinaryOperator::Equal && term.exprs.len() == 1).count();
+                order.iter().enumerate()
+                    .map(|(position, column)| (*column, collations.get(position).copied().unwrap_or(Collation::Binary), position < equal))
+                    .collect()
+            },
+        }
+    }
+
+    //start reading the rows of `source` this access path picks out, given the rows of the tables read before it
+    fn open(&self, source: &Source, row: &RowContext) -> Result<SourceScan<'a>> {
+        match self {
+            Access::Scan if source.rows.is_some() => Ok(SourceScan::Subquery(0)),
+            Access::Scan => Ok(SourceScan::Table(TableCursor::new(source.root_page))),
+            Access::RowIds(bounds) => {
+                //narrow the rowids down to an inclusive range, and to those an equality allows
+                let (mut lowest, mut highest) = (i64::MIN as i128, i64::MAX as i128);
+                let mut allowed: Option<Vec<i64>> = None;
+                for (operator, exprs, affinity) in bounds {
+                    let values = exprs.iter().map(|expr| Ok(evaluate(expr, row)?.apply_affinity(*affinity))).collect::<Result<Vec<_>>>()?;
+                    if *operator == BinaryOperator::Equal {
+                        //no rowid equals a value that isn't an integer (nor NULL, which nothing compares true with)
+                        let row_ids: Vec<i64> = values.iter().filter_map(|value| match value {
+                            Value::Integer(n) => Some(*n),
+                            _ => None,
+                        }).collect();
+                        allowed = Some(match allowed {
+                            Some(allowed) => allowed.into_iter().filter(|row_id| row_ids.contains(row_id)).collect(),
+                            None => row_ids,
+                        });
+                        continue;
+                    }
+                    let n = match values[0] {
+                        //nothing compares true with NULL
+                        Value::Null => return Ok(SourceScan::Empty),
+                        Value::Integer(n) => n as i128,
+                        //other values are left to the comparison itself
+                        _ => continue,
+                    };
+                    match operator {
+                        BinaryOperator::Greater => lowest = lowest.max(n + 1),
+                        BinaryOperator::GreaterEqual => lowest = lowest.max(n),
+                        BinaryOperator::Less => highest = highest.min(n - 1),
+                        _ => highest = highest.min(n),
+                    }
+                }
+                //the rows an equality allows are read in rowid order, each once
+                if let Some(mut row_ids) = allowed {
+                    row_ids.retain(|&row_id| (lowest..=highest).contains(&(row_id as i128)));
+                    row_ids.sort_unstable();
+                    row_ids.dedup();
+                    return Ok(SourceScan::Rows(row_ids.into_iter()));
+                }
+                if highest < lowest {
+                    return Ok(SourceScan::Empty);
+                }
+                if lowest == highest {
+                    return Ok(SourceScan::Rows(vec![lowest as i64].into_iter()));
+                }
+                let lower = if lowest <= i64::MIN as i128 { Bound::Unbounded } else { Bound::Included(lowest as i64) };
+                let upper = if highest >= i64::MAX as i128 { Bound::Unbounded } else { Bound::Included(highest as i64) };
+                Ok(SourceScan::Table(TableCursor::range(source.root_page, lower, upper)))
+            },
+            Access::Index { index, key, covering, collations, .. } => {
+                //search for the values converted the way comparing them with the columns would convert them:
+                //the equal columns make up a prefix of the key, and the range bounds the column after them,
+                //in that column's collation
+                let mut equal_values: Vec<Vec<Value>> = Vec::new();
+                //each bound with whether it's inclusive
+                let mut lower: Option<(Value, bool)> = None;
+                let mut upper: Option<(Value, bool)> = None;
+                for term in key {
+                    let mut values = term.exprs.iter().map(|expr| Ok(evaluate(expr, row)?.apply_affinity(term.affinity))).collect::<Result<Vec<_>>>()?;
+                    //nothing compares true with NULL
+                    values.retain(|value| !value.is_null());
+                    let collation = collations.get(equal_values.len()).copied().unwrap_or(Collation::Binary);
+                    if term.operator == BinaryOperator::Equal {
+                        values.sort_by(|a, b| a.compare_collated(b, collation));
+                        values.dedup_by(|a, b| a.compare_collated(b, collation) == Ordering::Equal);
+                    }
+                    let Some(value) = values.first().cloned() else {
+                        return Ok(SourceScan::Empty);
+                    };
+                    //of several bounds on the same side the tightest counts, and of equal ones the exclusive one
+                    let tighter = |bound: &Option<(Value, bool)>, wanted: Ordering, inclusive: bool| match bound {
+                        Some((current, current_inclusive)) => match value.compare_collated(current, collation) {
+                            Ordering::Equal => *current_inclusive && !inclusive,
+                            ordering => ordering == wanted,
+                        },
+                        None => true,
+                    };
+                    match term.operator {
+                        BinaryOperator::Equal => equal_values.push(values),
+                        operator @ (BinaryOperator::Greater | BinaryOperator::GreaterEqual) => {
+                            let inclusive = operator == BinaryOperator::GreaterEqual;
+                            if tighter(&lower, Ordering::Greater, inclusive) {
+                                lower = Some((value, inclusive));
+                            }
+                        },
+                        operator => {
+                            let inclusive = operator == BinaryOperator::LessEqual;
+                            if tighter(&upper, Ordering::Less, inclusive) {
+                                upper = Some((value, inclusive));
+                            }
+                        },
+                    }
+                }
+                //a search for each combination of the equal columns' values, in the order of the index
+                let mut prefixes = vec![Vec::new()];
+                for values in &equal_values {
+                    prefixes = prefixes.iter().flat_map(|prefix: &Vec<Value>| values.iter().map(|value| prefix.iter().chain([value]).cloned().collect())).collect();
+                }
+                let has_range = lower.is_some() || upper.is_some();
+                let cursors = prefixes.into_iter().map(|prefix| {
+                    let bound = |bound: &Option<(Value, bool)>, unbounded: Bound<Vec<Value>>| match bound {
+                        Some((value, inclusive)) => {
+                            let key = prefix.iter().chain([value]).cloned().collect();
+                            if *inclusive { Bound::Included(key) } else { Bound::Excluded(key) }
+                        },
+                        None => unbounded,
+                    };
+                    //a range with no lower bound still starts after the NULLs, which compare with nothing
+                    let lower = match has_range {
+                        true => bound(&lower, Bound::Excluded(prefix.iter().cloned().chain([Value::Null]).collect())),
+                        false => Bound::Included(prefix.clone()),
+                    };
+                    let upper = bound(&upper, Bound::Included(prefix.clone()));
+                    IndexCursor::range(index.root_page, collations.clone(), lower, upper)
+                }).collect();
+                Ok(SourceScan::Index { index, cursors, covering: covering.clone() })
+            },
+        }
+    }
+}
+
+//the rows of a table an access path picks out, read one at a time as the join asks for them
+enum SourceScan<'a> {
+    Empty,
+    //the rows of these rowids, in turn
+    Rows(std::vec::IntoIter<i64>),
+    Table(TableCursor),
+    //the entries of an index, from each of its searches in turn, whose rows are read from the table unless the index
+    //covers the columns the query reads, in which case the table column of each of its columns is given
+    Index { index: &'a Schema, cursors: VecDeque<IndexCursor>, covering: Option<Vec<usize>> },
+    //the position of the next of a subquery's rows
+    Subquery(usize),
+}
+
+impl SourceScan<'_> {
+    fn next(&mut self, database: &Database, source: &Source) -> Result<Option<TableLeafCell>> {
+        match self {
+            SourceScan::Empty => Ok(None),
+            SourceScan::Rows(row_ids) => {
+                for row_id in row_ids.by_ref() {
+                    if let Some(cell) = database.read_row(source.root_page, row_id)? {
+                        return Ok(Some(cell));
+                    }
+                }
+                Ok(None)
+            },
+            SourceScan::Table(cursor) => cursor.next(database),
+            SourceScan::Index { index, cursors, covering } => {
+                let entry = loop {
+                    let Some(cursor) = cursors.front_mut() else { return Ok(None) };
+                    match cursor.next(database)? {
+                        Some(entry) => break entry,
+                        None => { cursors.pop_front(); },
+                    }
+                };
+                //the columns the query doesn't read are left NULL
+                if let Some(columns) = covering {
+                    let mut values = vec![Value::Null; source.table.columns.len()];
+                    for (value, column_index) in entry.key.into_iter().zip(columns.iter()) {
+                        values[*column_index] = value;
+                    }
+                    return Ok(Some(TableLeafCell { row_id: entry.row_id, payload: Record { values } }));
+                }
+                match database.read_row(source.root_page, entry.row_id)? {
+                    Some(cell) => Ok(Some(cell)),
+                    None => bail!("index {} points at missing rowid {}", index.name, entry.row_id),
+                }
+            },
+            SourceScan::Subquery(position) => {
+                let cell = source.rows.as_ref().and_then(|rows| rows.get(*position).cloned());
+                *position += 1;
+                Ok(cell)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_index_lookup_reads() {
+    //t has 70 rows over 10 leaves under its root, page 2, and its index on k has 8 leaves of 9 entries
+    //under its root, page 13, which holds the 7 entries in between
+    let row = |row_id: i64| test_table_leaf_cell(row_id, &test_record(&[Value::Null, Value::Integer(row_id * 10), Value::Text(format!("row {}", row_id))]));
+    let mut pages = vec![test_page(0x05, Some(12), &(0..9).map(|leaf| test_table_interior_cell(3 + leaf, 7 * (leaf as i64 + 1))).collect::<Vec<_>>())];
+    pages.extend((0..10).map(|leaf| test_page(0x0d, None, &(1..=7).map(|row_id| row(7 * leaf + row_id)).collect::<Vec<_>>())));
+    let entry = |left_child: Option<u32>, row_id: i64| test_index_cell(left_child, &test_record(&[Value::Integer(row_id * 10), Value::Integer(row_id)]));
+    pages.push(test_page(0x02, Some(21), &(0..7).map(|leaf| entry(Some(14 + leaf), 10 * (leaf as i64 + 1))).collect::<Vec<_>>()));
+    pages.extend((0..8).map(|leaf| test_page(0x0a, None, &(1..=9).map(|row_id| entry(None, 10 * leaf + row_id)).collect::<Vec<_>>())));
+    let database = test_database("index-lookup-reads", &pages);
+    let schema = |schema_type: &str, name: &str, root_page: u32, sql: &str| Schema {
+        schema_type: schema_type.to_string(),
+        name: name.to_string(),
+        tbl_name: "t".to_string(),
+        root_page,
+        sql: sql.to_string(),
+    };
+    let schema_tables = [
+        schema("table", "t", 2, "CREATE TABLE t (id INTEGER PRIMARY KEY, k INTEGER, v TEXT)"),
+        schema("index", "t_k", 13, "CREATE INDEX t_k ON t (k)"),
+    ];
+    let run = |sql: &str| {
+        let statement = sql::parse_select(sql).unwrap();
+        let subqueries = Subqueries::new(&database, &schema_tables, 0, Vec::new());
+        database.pages_read.set(0);
+        let rows = Query::new(&subqueries, &statement, None).unwrap().collect(&subqueries).unwrap();
+        (rows.iter().map(|row| row.payload.values[0].to_string()).collect::<Vec<_>>(), database.pages_read.get())
+    };
+    //the lookup reads a path down each b-tree, the index's root and leaf then the table's
+    let (rows, pages_read) = run("SELECT v FROM t WHERE k = 370");
+    assert_eq!((rows, pages_read), (vec!["row 37".to_string()], 4));
+    //an entry on the index's root is followed by the first entry of the next leaf, which ends the range
+    let (rows, pages_read) = run("SELECT v FROM t WHERE k = 300");
+    assert_eq!((rows, pages_read), (vec!["row 30".to_string()], 5));
+    //the rowid is in the index too, so the table isn't read at all
+    let (rows, pages_read) = run("SELECT id FROM t WHERE k = 370");
+    assert_eq!((rows, pages_read), (vec!["37".to_string()], 2));
+    //when the index can't be used, every leaf of the table is read
+    let (rows, pages_read) = run("SELECT v FROM t WHERE k + 0 = 370");
+    assert_eq!((rows, pages_read), (vec!["row 37".to_string()], 11));
+    //an IN list searches for each of its values once, in order
+    let (rows, pages_read) = run("SELECT id FROM t WHERE k IN (370, 10, 370)");
+    assert_eq!((rows, pages_read), (vec!["1".to_string(), "37".to_string()], 4));
+    let (rows, pages_read) = run("SELECT v FROM t WHERE id IN (37, 1)");
+    assert_eq!((rows, pages_read), (vec!["row 1".to_string(), "row 37".to_string()], 4));
+    //rows read in rowid order stop at the LIMIT rather than all being sorted
+    let (rows, pages_read) = run("SELECT v FROM t ORDER BY id LIMIT 2");
+    assert_eq!((rows, pages_read), (vec!["row 1".to_string(), "row 2".to_string()], 2));
+}
+
+//whether the join reads its rows in the order the ORDER BY terms (with their sort keys) put them in, so they needn't be
+//sorted: the terms are columns of the first table it reads, ascending with NULLs first, that its rows are read in the
+//order of, in the same collations, passing over the columns all its rows have the same value in. Once they reach the
+//rowid of the only table, the terms after it make no difference
+fn reads_in_order(plan: &JoinPlan, sources: &[Source], order_by: &[&Expr], sort_keys: &[SortKey]) -> bool {
+    let Some(level) = plan.levels.first() else { return false };
+    let mut order = level.access.order(&sources[level.source]).into_iter();
+    for (position, (expr, key)) in order_by.iter().zip(sort_keys).enumerate() {
+        let mut expr = *expr;
+        while let Expr::Collate { expr: inner, .. } = expr {
+            expr = inner;
+        }
+        let Expr::Column { table, name } = expr else { return false };
+        let std::result::Result::Ok((source_index, column)) = resolve_source_column(sources, table.as_deref(), name) else { return false };
+        if source_index != level.source || key.descending || !key.nulls_first {
+            return false;
+        }
+        loop {
+            let Some((ordered, collation, same)) = order.next() else { return false };
+            //integers compare the same in every collation
+            if ordered == column && (collation == key.collation || column == ColumnRef::RowId) {
+                break;
+            }
+            if !same {
+                return false;
+            }
+        }
+        if column == ColumnRef::RowId {
+            return plan.levels.len() == 1 || position + 1 == order_by.len();
+        }
+    }
+    true
+}
+
+//call `visit` with every row of the join, each alongside `cells`, the row of the enclosing queries, until it asks to stop
+fn scan_join(
+    sources: &[Source],
+    subqueries: &Subqueries,
+    plan: &JoinPlan,
+    mut cells: Vec<Option<TableLeafCell>>,
+    mut visit: impl FnMut(&[Option<TableLeafCell>]) -> Result<ControlFlow<()>>,
+) -> Result<()> {
+    let row = RowContext { sources, subqueries, cells: &cells, aggregates: &[] };
+    for term in &plan.constant_terms {
+        if evaluate(term, &row)?.truth() != Some(true) {
+            return Ok(());
+        }
+    }
+    //the query's own tables have no row until they're read
+    cells.resize(sources.len(), None);
+    //whether it stopped early makes no difference to the caller
+    let _ = join_rows(sources, subqueries, &plan.levels, &mut cells, &mut visit)?;
+    Ok(())
+}
+
+//call `visit` with the rows of the tables of `levels` that pass the join's terms, each joined onto the rows of the
+//tables already read in `cells`, until it asks to stop: a nested loop over the tables in the order of the levels
+//(the cursors read pages as they go, so stopping early leaves the rest of the tables unread)
+fn join_rows(
+    sources: &[Source],
+    subqueries: &Subqueries,
+    levels: &[JoinLevel],
+    cells: &mut Vec<Option<TableLeafCell>>,
+    visit: &mut impl FnMut(&[Option<TableLeafCell>]) -> Result<ControlFlow<()>>,
+) -> Result<ControlFlow<()>> {
+    let Some((level, inner_levels)) = levels.split_first() else {
+        return visit(cells);
+    };
+    let source_index = level.source;
+    let source = &sources[source_index];
+    let passes = |terms: &[Prepared], cells: &[Option<TableLeafCell>]| -> Result<bool> {
+        let row = RowContext { sources, subqueries, cells, aggregates: &[] };
+        for term in terms {
+            if evaluate(term, &row)?.truth() != Some(true) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    };
+
+    let mut scan = level.access.open(source, &RowContext { sources, subqueries, cells, aggregates: &[] })?;
+    let mut matched = false;
+    while let Some(cell) = scan.next(subqueries.database, source)? {
+        cells[source_index] = Some(cell);
+        let mut flow = ControlFlow::Continue(());
+        if passes(&level.match_terms, cells)? {
+            matched = true;
+            if passes(&level.filter_terms, cells)? {
+                flow = join_rows(sources, subqueries, inner_levels, cells, visit)?;
+            }
+        }
+        cells[source_index] = None;
+        if flow.is_break() {
+            return Ok(flow);
+        }
+    }
+    //a LEFT JOIN keeps each row on its left that none of its rows matched, with NULLs for its columns
+    if source.outer && !matched && passes(&level.filter_terms, cells)? {
+        return join_rows(sources, subqueries, inner_levels, cells, visit);
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+//split a condition into the terms that are AND-ed together
+fn conjuncts<'a>(condition: &'a Expr, terms: &mut Vec<&'a Expr>) {
+    match condition {
+        Expr::Binary(left, BinaryOperator::And, right) => {
+            conjuncts(left, terms);
+            conjuncts(right, terms);
+        },
+        _ => terms.push(condition),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnRef {
+    Column(usize),
+    //the rowid itself, or the INTEGER PRIMARY KEY column that aliases it
+    RowId,
+}
+
+//look a column name up in the table definition; rowid, _rowid_ and oid name the rowid unless a real column uses them
+//(or the table has no rowid)
+fn resolve_column(table: &CreateTable, rowid_alias: Option<usize>, name: &str) -> Option<ColumnRef> {
+    match table.column_index(name) {
+        Some(column_index) if rowid_alias == Some(column_index) => Some(ColumnRef::RowId),
+        Some(column_index) => Some(ColumnRef::Column(column_index)),
+        None if !table.without_rowid && ["rowid", "_rowid_", "oid"].iter().any(|rowid| rowid.eq_ignore_ascii_case(name)) => Some(ColumnRef::RowId),
+        None => None,
+    }
+}
+
+//the table of the FROM clause a column name refers to, and the column: a qualified name looks in the table of
+//that name (or alias), and an unqualified one in all of them, where it mustn't be found more than once
+//(a column shared by USING counts as the left table's). A subquery's own tables are searched before those
+//of the queries around it, and the innermost of those first
+fn resolve_source_column(sources: &[Source], qualifier: Option<&str>, name: &str) -> Result<(usize, ColumnRef)> {
+    if let Some(qualifier) = qualifier {
+        let found = sources.iter().enumerate().rev()
+            .filter(|(_, source)| source.name.eq_ignore_ascii_case(qualifier))
+            .max_by_key(|(_, source)| source.depth)
+            .and_then(|(source_index, source)| Some((source_index, resolve_column(&source.table, source.rowid_alias, name)?)));
+        return match found {
+            Some(found) => Ok(found),
+            None => bail!("no such column: {}.{}", qualifier, name),
+        };
+    }
+    let mut found: Option<(usize, ColumnRef)> = None;
+    for (source_index, source) in sources.iter().enumerate().rev() {
+        if found.is_some_and(|(found_index, _)| sources[found_index].depth > source.depth) {
+            break;
+        }
+        if source.using_columns.iter().any(|column| column.eq_ignore_ascii_case(name)) {
+            continue;
+        }
+        if let Some(column) = resolve_column(&source.table, source.rowid_alias, name) {
+            if found.is_some() {
+                bail!("ambiguous column name: {}", name);
+            }
+            found = Some((source_index, column));
+        }
+    }
+    match found {
+        Some(found) => Ok(found),
+        None => bail!("no such column: {}", name),
+    }
+}
+
+// ***QUERY PLANS***
+
+//a line of the plan EXPLAIN QUERY PLAN prints, with the lines of the steps it's made of below it
+struct PlanLine {
+    detail: String,
+    children: Vec<PlanLine>,
+}
+
+impl PlanLine {
+    fn new(detail: impl Into<String>, children: Vec<PlanLine>) -> Self {
+        PlanLine { detail: detail.into(), children }
+    }
+
+    //print the lines as a tree the way the sqlite shell does, each line after `prefix`
+    fn print(lines: &[PlanLine], prefix: &str) {
+        for (position, line) in lines.iter().enumerate() {
+            let last = position + 1 == lines.len();
+            println!("{}{}{}", prefix, if last { "`--" } else { "|--" }, line.detail);
+            PlanLine::print(&line.children, &format!("{}{}", prefix, if last { "   " } else { "|  " }));
+        }
+    }
+}
+
+//print how a query would be run rather than running it: the subqueries in FROM and the common table expressions
+//it materializes, how the rows of each of its tables are found in the order they're read, the subqueries in its
+//expressions and the temporary b-trees it sorts and groups rows in
+fn explain_query_plan(database: &Database, statement: &SelectStatement) -> Result<()> {
+    let schema_tables = database.get_schema_table()?;
+    let subqueries = Subqueries { explaining: true, ..Subqueries::new(database, &schema_tables, 0, named_tables(&[], statement)) };
+    let query = Query::new(&subqueries, statement, None)?;
+    let (lines, _) = Explainer { subquery_count: 0 }.query(&subqueries, &query, None)?;
+    println!("QUERY PLAN");
+    PlanLine::print(&lines, "");
+    Ok(())
+}
+
+//builds the plans of a query and of the queries inside it, numbering the subqueries of expressions in the order
+//they're come across. Each plan comes with the depth of the outermost query whose tables the query reads (its own
+//depth when it reads none of the enclosing queries' tables), which tells whether a subquery is correlated
+struct Explainer {
+    subquery_count: usize,
+}
+
+impl Explainer {
+    //the plan of a query run by `subqueries`, as a subquery of the query whose row is `enclosing`
+    fn query<'a>(&mut self, subqueries: &Subqueries<'a>, query: &Query<'a>, enclosing: Option<&RowContext>) -> Result<(Vec<PlanLine>, usize)> {
+        if query.compound.is_empty() {
+            return self.select(subqueries, query, enclosing);
+        }
+        let (lines, mut depth) = self.select(subqueries, query, enclosing)?;
+        let mut selects = vec![PlanLine::new("LEFT-MOST SUBQUERY", lines)];
+        for (operator, select_subqueries, select) in &query.compound {
+            let (lines, select_depth) = self.select(select_subqueries, select, enclosing)?;
+            depth = depth.min(select_depth);
+            let detail = match operator {
+                CompoundOperator::UnionAll => operator.name().to_string(),
+                _ => format!("{} USING TEMP B-TREE", operator.name()),
+            };
+            selects.push(PlanLine::new(detail, lines));
+        }
+        let mut lines = vec![PlanLine::new("COMPOUND QUERY", selects)];
+        if !query.statement.order_by.is_empty() {
+            lines.push(PlanLine::new("USE TEMP B-TREE FOR ORDER BY", Vec::new()));
+        }
+        Ok((lines, depth))
+    }
+
+    //the plan of a SELECT alone, without the ORDER BY a compound SELECT applies to all of its SELECTs
+    fn select<'a>(&mut self, subqueries: &Subqueries<'a>, query: &Query<'a>, enclosing: Option<&RowContext>) -> Result<(Vec<PlanLine>, usize)> {
+        let statement = query.statement.as_ref();
+        let sources = &query.sources;
+        let ordering_terms = query.ordering_terms();
+        let first = query.first_source();
+        let mut lines = Vec::new();
+        let mut depth = subqueries.depth;
+
+        //the subqueries in FROM and common table expressions, whose rows are read before the query runs;
+        //a subquery with no alias is numbered like those of the expressions
+        let mut names: Vec<String> = sources.iter().map(|source| source.name.clone()).collect();
+        let tables = statement.from.iter().flat_map(|from| std::iter::once(&from.table).chain(from.joins.iter().map(|join| &join.table)));
+        for (source_index, table) in (first..).zip(tables) {
+            let (materialized, materialized_depth) = match table {
+                TableOrSubquery::Subquery { select, alias } => {
+                    if alias.is_none() {
+                        self.subquery_count += 1;
+                        names[source_index] = format!("(subquery-{})", self.subquery_count);
+                    }
+                    let inner = subqueries.nested(subqueries.depth, named_tables(&subqueries.tables, select));
+                    let subquery = Query::new(&inner, select, enclosing)?;
+                    self.query(&inner, &subquery, enclosing)?
+                },
+                TableOrSubquery::Table(table_name) => match subqueries.named_table(&table_name.name) {
+                    Some(NamedTable::With { cte, scope, .. }) => self.with(subqueries, cte, *scope, enclosing)?,
+                    _ => continue,
+                },
+            };
+            depth = depth.min(materialized_depth);
+            lines.push(PlanLine::new(format!("MATERIALIZE {}", names[source_index]), materialized));
+        }
+
+        let compiled = query.compiled(subqueries)?;
+        let plan = &compiled.plan;
+        if plan.levels.is_empty() {
+            lines.push(PlanLine::new("SCAN CONSTANT ROW", Vec::new()));
+        }
+        for level in &plan.levels {
+            lines.push(PlanLine::new(level.access.describe(&names[level.source], sources[level.source].outer), Vec::new()));
+        }
+
+        //the expressions of the query, in the order they're written
+        let mut exprs: Vec<&Expr> = query.outputs.iter().map(|(output, _)| output).collect();
+        for join in statement.from.iter().flat_map(|from| &from.joins) {
+            if let JoinConstraint::On(condition) = &join.constraint {
+                exprs.push(condition);
+            }
+        }
+        exprs.extend(statement.where_clause.iter().chain(&statement.group_by).chain(&statement.having));
+        exprs.extend(ordering_terms.iter().map(|term| &term.expr));
+        for expr in &exprs {
+            depth = depth.min(read_depth(expr, sources));
+        }
+        let mut subquery_exprs = Vec::new();
+        for expr in &exprs {
+            find_subqueries(expr, &mut subquery_exprs);
+        }
+        let row = RowContext { sources, subqueries, cells: &[], aggregates: &[] };
+        for expr in subquery_exprs {
+            let (select, kind) = match expr {
+                Expr::Subquery(select) => (select, "SCALAR SUBQUERY"),
+                //EXISTS only runs its subquery until the first row
+                Expr::Exists(select) => (select, "EXISTS SUBQUERY"),
+                Expr::In { list: InList::Select(select), .. } => (select, "LIST SUBQUERY"),
+                _ => unreachable!("only subqueries are found"),
+            };
+            self.subquery_count += 1;
+            let number = self.subquery_count;
+            let inner = subqueries.nested(subqueries.depth + 1, named_tables(&subqueries.tables, select));
+            let subquery = Query::new(&inner, select, Some(&row))?;
+            if !matches!(expr, Expr::Exists(_)) {
+                subquery.single_column()?;
+            }
+            let (subquery_lines, subquery_depth) = self.query(&inner, &subquery, Some(&row))?;
+            depth = depth.min(subquery_depth);
+            //a correlated subquery reads the row of this query (or of one around it), and runs again for each row
+            let correlated = if subquery_depth <= subqueries.depth { "CORRELATED " } else { "" };
+            lines.push(PlanLine::new(format!("{}{} {}", correlated, kind, number), subquery_lines));
+        }
+
+        //the steps after the join, in the order the query takes them: the groups are found in a hash table,
+        //the rows are sorted for each window in turn, from the last window function to the first, and the
+        //distinct rows are found in a hash table before they're sorted for ORDER BY
+        if !statement.group_by.is_empty() {
+            lines.push(PlanLine::new("USE HASH TABLE FOR GROUP BY", Vec::new()));
+        }
+        let order_by_terms: Vec<&Expr> = ordering_terms.iter().map(|term| &term.expr).collect();
+        let order_by = result_column_references(&order_by_terms, "ORDER", &query.outputs, sources)?;
+        let (window_calls, windows) = query_windows(statement, &query.outputs, &order_by)?;
+        for (call, window) in window_calls.iter().zip(&windows).rev() {
+            let Expr::WindowFunction { name, .. } = call else { unreachable!("window calls are window function calls") };
+            if !window.partition_by.is_empty() || !window.order_by.is_empty() {
+                lines.push(PlanLine::new(format!("SORT FOR WINDOW {}()", name), Vec::new()));
+            }
+        }
+        if statement.distinct {
+            lines.push(PlanLine::new("USE HASH TABLE FOR DISTINCT", Vec::new()));
+        }
+        if !compiled.order_by.is_empty() {
+            lines.push(PlanLine::new("USE TEMP B-TREE FOR ORDER BY", Vec::new()));
+        }
+        Ok((lines, depth))
+    }
+
+    //the plan of a common table expression read by a query run by `subqueries`, which sees the first `scope` of the named
+    //tables around the query: a recursive one sets its table up with its initial SELECT, then repeats its recursive SELECT
+    fn with(&mut self, subqueries: &Subqueries, cte: &CommonTableExpression, scope: usize, enclosing: Option<&RowContext>) -> Result<(Vec<PlanLine>, usize)> {
+        let (columns, _, _) = with_rows(subqueries, cte, scope, enclosing, None)?;
+        let tables = &subqueries.tables[..scope];
+        let Some((initial, recursive)) = recursive_parts(cte) else {
+            let inner = subqueries.nested(subqueries.depth, named_tables(tables, &cte.select));
+            let query = Query::new(&inner, &cte.select, enclosing)?;
+            return self.query(&inner, &query, enclosing);
+        };
+        let inner = subqueries.nested(subqueries.depth, named_tables(tables, &initial));
+        let query = Query::new(&inner, &initial, enclosing)?;
+        let (setup, setup_depth) = self.query(&inner, &query, enclosing)?;
+
+        let mut step_tables = tables.to_vec();
+        step_tables.push(NamedTable::Rows { name: cte.name.clone(), columns, rows: Rc::new(Vec::new()) });
+        let inner = subqueries.nested(subqueries.depth, named_tables(&step_tables, &recursive.select));
+        let query = Query::new(&inner, &recursive.select, enclosing)?;
+        let (step, step_depth) = self.query(&inner, &query, enclosing)?;
+        Ok((vec![PlanLine::new("SETUP", setup), PlanLine::new("RECURSIVE STEP", step)], setup_depth.min(step_depth)))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_explain_strategy() {
+    let database = test_database("explain-strategy", &[]);
+    let schema = |schema_type: &str, name: &str, tbl_name: &str, root_page: u32, sql: &str| Schema {
+        schema_type: schema_type.to_string(),
+        name: name.to_string(),
+        tbl_name: tbl_name.to_string(),
+        root_page,
+        sql: sql.to_string(),
+    };
+    let schema_tables = [
+        schema("table", "owners", "owners", 2, "CREATE TABLE owners (id INTEGER PRIMARY KEY, name TEXT)"),
+        schema("table", "pets", "pets", 3, "CREATE TABLE pets (id INTEGER PRIMARY KEY, owner_id INTEGER, kind TEXT COLLATE NOCASE, age REAL)"),
+        schema("index", "pets_kind", "pets", 4, "CREATE INDEX pets_kind ON pets (kind)"),
+        schema("table", "depts", "depts", 5, "CREATE TABLE depts (code TEXT PRIMARY KEY, name TEXT UNIQUE COLLATE NOCASE, UNIQUE (code))"),
+        schema("index", "sqlite_autoindex_depts_1", "depts", 6, ""),
+        schema("index", "sqlite_autoindex_depts_2", "depts", 7, ""),
+    ];
+    //each line of the plan, indented under the one it belongs to
+    let plan = |sql: &str| {
+        fn flatten(lines: &[PlanLine], indent: &str, flat: &mut Vec<String>) {
+            for line in lines {
+                flat.push(format!("{}{}", indent, line.detail));
+                flatten(&line.children, &format!("{}  ", indent), flat);
+            }
+        }
+        let statement = sql::parse_select(sql).unwrap();
+        let subqueries = Subqueries { explaining: true, ..Subqueries::new(&database, &schema_tables, 0, named_tables(&[], &statement)) };
+        let query = Query::new(&subqueries, &statement, None).unwrap();
+        let (lines, _) = Explainer { subquery_count: 0 }.query(&subqueries, &query, None).unwrap();
+        let mut flat = Vec::new();
+        flatten(&lines, "", &mut flat);
+        flat
+    };
+    //the index is in NOCASE order, which is the collation kind is compared in
+    assert_eq!(plan("SELECT age FROM pets WHERE kind = 'Cat'"), ["SEARCH pets USING INDEX pets_kind (kind=?)"]);
+    assert_eq!(plan("SELECT age FROM pets WHERE kind = 'Cat' COLLATE BINARY"), ["SCAN pets"]);
+    //an IN list is searched for each of its values
+    assert_eq!(plan("SELECT age FROM pets WHERE kind IN ('Cat', 'Dog')"), ["SEARCH pets USING INDEX pets_kind (kind=?)"]);
+    assert_eq!(plan("SELECT age FROM pets WHERE id IN (1, 2)"), ["SEARCH pets USING INTEGER PRIMARY KEY (rowid=?)"]);
+    //rows read in rowid order, or in the order of an index, needn't be sorted by it
+    assert_eq!(plan("SELECT age FROM pets WHERE age > 5 ORDER BY id LIMIT 3"), ["SCAN pets"]);
+    assert_eq!(plan("SELECT age FROM pets WHERE kind = 'Cat' ORDER BY id"), ["SEARCH pets USING INDEX pets_kind (kind=?)"]);
+    assert_eq!(plan("SELECT age FROM pets WHERE kind > 'Cat' ORDER BY kind"), ["SEARCH pets USING INDEX pets_kind (kind>?)"]);
+    assert_eq!(plan("SELECT age FROM pets WHERE kind > 'Cat' ORDER BY kind COLLATE BINARY"), ["SEARCH pets USING INDEX pets_kind (kind>?)", "USE TEMP B-TREE FOR ORDER BY"]);
+    assert_eq!(plan("SELECT age FROM pets WHERE kind IN ('Cat', 'Dog') ORDER BY id"), ["SEARCH pets USING INDEX pets_kind (kind=?)", "USE TEMP B-TREE FOR ORDER BY"]);
+    assert_eq!(plan("SELECT age FROM pets ORDER BY id DESC"), ["SCAN pets", "USE TEMP B-TREE FOR ORDER BY"]);
+    //the indexes of PRIMARY KEY and UNIQUE constraints have no SQL, but the table's constraints give their columns
+    assert_eq!(plan("SELECT name FROM depts WHERE code = 'eng'"), ["SEARCH depts USING INDEX sqlite_autoindex_depts_1 (code=?)"]);
+    assert_eq!(plan("SELECT code FROM depts WHERE name = 'Eng'"), ["SEARCH depts USING INDEX sqlite_autoindex_depts_2 (name=?)"]);
+    //groups and distinct rows are found in hash tables, and the rows are sorted for each window, the last one first
+    assert_eq!(plan("SELECT owner_id, count(*) FROM pets GROUP BY owner_id"), ["SCAN pets", "USE HASH TABLE FOR GROUP BY"]);
+    assert_eq!(plan("SELECT DISTINCT owner_id FROM pets ORDER BY owner_id"), ["SCAN pets", "USE HASH TABLE FOR DISTINCT", "USE TEMP B-TREE FOR ORDER BY"]);
+    assert_eq!(plan("SELECT rank() OVER (ORDER BY age), sum(age) OVER (PARTITION BY owner_id), count(*) OVER () FROM pets"),
+        ["SCAN pets", "SORT FOR WINDOW sum()", "SORT FOR WINDOW rank()"]);
+    assert_eq!(plan("SELECT name FROM owners WHERE EXISTS (SELECT 1 FROM pets WHERE pets.owner_id = owners.id)"),
+        ["SCAN owners", "CORRELATED EXISTS SUBQUERY 1", "  SCAN pets"]);
+    assert_eq!(plan("SELECT name, (SELECT max(age) FROM pets) FROM owners"), ["SCAN owners", "SCALAR SUBQUERY 1", "  SCAN pets"]);
+}
+
+impl Access<'_> {
+    //how the rows of the table called `name` are read, as EXPLAIN QUERY PLAN puts it
+    fn describe(&self, name: &str, outer: bool) -> String {
+        let detail = match self {
+            Access::Scan => format!("SCAN {}", name),
+            Access::RowIds(bounds) => {
+                let key = key_description(bounds.iter().map(|(operator, _, _)| ("rowid", *operator)));
+                format!("SEARCH {} USING INTEGER PRIMARY KEY ({})", name, key)
+            },
+            Access::Index { index, key, covering, .. } => {
+                let kind = if covering.is_some() { "COVERING INDEX" } else { "INDEX" };
+                match key.is_empty() {
+                    true => format!("SCAN {} USING {} {}", name, kind, index.name),
+                    false => {
+                        let key = key_description(key.iter().map(|term| (term.column.as_str(), term.operator)));
+                        format!("SEARCH {} USING {} {} ({})", name, kind, index.name, key)
+                    },
+                }
+            },
+        };
+        match outer {
+            true => format!("{} LEFT-JOIN", detail),
+            false => detail,
+        }
+    }
+}
+
+//the comparisons a table or index is searched with, as sqlite lists them: the columns that are equal to a value,
+//then the lower and upper bounds of the range, whether or not they include the value
+fn key_description<'a>(terms: impl Iterator<Item = (&'a str, BinaryOperator)>) -> String {
+    let (mut equal, mut lower, mut upper) = (Vec::new(), None, None);
+    for (column, operator) in terms {
+        match operator {
+            BinaryOperator::Equal => {
+                let term = format!("{}=?", column);
+                if !equal.contains(&term) {
+                    equal.push(term);
+                }
+            },
+            BinaryOperator::Greater | BinaryOperator::GreaterEqual => lower = Some(format!("{}>?", column)),
+            _ => upper = Some(format!("{}<?", column)),
+        }
+    }
+    equal.into_iter().chain(lower).chain(upper).collect::<Vec<_>>().join(" AND ")
+}
+
+//the subqueries in an expression, outside of those of the subqueries themselves
+fn find_subqueries<'a>(expr: &'a Expr, found: &mut Vec<&'a Expr>) {
+    if matches!(expr, Expr::Subquery(_) | Expr::Exists(_) | Expr::In { list: InList::Select(_), .. }) {
+        found.push(expr);
+    }
+    for child in expr.children() {
+        find_subqueries(child, found);
+    }
+}
+
+//the depth of the outermost query whose tables' columns an expression reads, outside of its subqueries
+//(usize::MAX when it reads none; names that don't name a column, such as aliases, are left out)
+fn read_depth(expr: &Expr, sources: &[Source]) -> usize {
+    let own = match expr {
+        Expr::Column { table, name } => match resolve_source_column(sources, table.as_deref(), name) {
+            std::result::Result::Ok((source_index, _)) => sources[source_index].depth,
+            Err(_) => usize::MAX,
+        },
+        _ => usize::MAX,
+    };
+    expr.children().into_iter().map(|child| read_depth(child, sources)).fold(own, usize::min)
+}
+
+// ***SUBQUERIES***
+
+//runs the subqueries in the expressions of a query as they're evaluated, each with the query's row as the row
+//of its enclosing query. Most don't read that row, and those only run once: after running a subquery, it's
+//clear whether it read a column of one of the query's tables. The others are prepared the first time they run,
+//and only given the new row each time after that
+struct Subqueries<'a> {
+    database: &'a Database,
+    schema_tables: &'a [Schema],
+    //the tables the query can read by name besides those of the database, which hide those of the same name
+    tables: Vec<NamedTable>,
+    //how many queries the query is a subquery of, which is the depth of its tables
+    depth: usize,
+    //for each of the enclosing queries, outermost first, whether its tables' columns have been read,
+    //by the query's expressions or its subqueries'
+    enclosing_reads: Vec<Cell<bool>>,
+    //the results of the subqueries that didn't read the query's row, by their expressions, with whether they read
+    //the row of a query enclosing it, which can change when the query runs again
+    results: RefCell<Vec<(Expr, Rc<SubqueryResult>, bool)>>,
+    //the subqueries prepared to run again, by their expressions: those whose subqueries in FROM and common table
+    //expressions, which are read while a subquery is prepared, read no row of the queries around them
+    prepared: RefCell<Vec<(Expr, Rc<PreparedSubquery<'a>>)>>,
+    //whether the queries are only being prepared for EXPLAIN QUERY PLAN, in which case the subqueries in FROM
+    //and common table expressions are left without rows rather than run
+    explaining: bool,
+}
+
+//a subquery of an expression, ready to run against another row: its query, and the subqueries of its own expressions
+struct PreparedSubquery<'a> {
+    subqueries: Subqueries<'a>,
+    query: Query<'a>,
+}
+
+//a table named by a WITH clause
+#[derive(Clone)]
+enum NamedTable {
+    //a common table expression, which sees the first `scope` of the tables named before it, with its columns and rows
+    //once they're known to be the same wherever it's read
+    With { cte: Rc<CommonTableExpression>, scope: usize, rows: Rc<OnceCell<TableRows>> },
+    //the row a recursive common table expression's recursive SELECT reads as the whole table
+    Rows { name: String, columns: Vec<ColumnDef>, rows: Rc<Vec<TableLeafCell>> },
+}
+
+//the columns and rows of a common table expression
+type TableRows = (Vec<ColumnDef>, Rc<Vec<TableLeafCell>>);
+
+impl NamedTable {
+    fn name(&self) -> &str {
+        match self {
+            NamedTable::With { cte, .. } => &cte.name,
+            NamedTable::Rows { name, .. } => name,
+        }
+    }
+}
+
+//the named tables a statement can read: `tables`, then those of its own WITH clause
+fn named_tables(tables: &[NamedTable], statement: &SelectStatement) -> Vec<NamedTable> {
+    let mut tables = tables.to_vec();
+    for cte in &statement.with {
+        let scope = tables.len();
+        tables.push(NamedTable::With { cte: Rc::new(cte.clone()), scope, rows: Rc::new(OnceCell::new()) });
+    }
+    tables
+}
+
+enum SubqueryResult {
+    //the value of a scalar subquery, or of EXISTS
+    Value(Value),
+    //the values of an IN subquery, converted the way they're compared with the left operand, as keys of the comparison's collation
+    Set { values: HashSet<Value>, has_null: bool, left_affinity: Affinity, collation: Collation },
+}
+
+impl<'a> Subqueries<'a> {
+    fn new(database: &'a Database, schema_tables: &'a [Schema], depth: usize, tables: Vec<NamedTable>) -> Self {
+        Subqueries {
+            database,
+            schema_tables,
+            tables,
+            depth,
+            enclosing_reads: vec![Cell::new(false); depth],
+            results: RefCell::new(Vec::new()),
+            prepared: RefCell::new(Vec::new()),
+            explaining: false,
+        }
+    }
+
+    //the subqueries of a query run by these ones, which can read `tables` by name and whose tables are at `depth`
+    fn nested(&self, depth: usize, tables: Vec<NamedTable>) -> Self {
+        Subqueries { explaining: self.explaining, ..Subqueries::new(self.database, self.schema_tables, depth, tables) }
+    }
+
+    //the table called `name` among those named by the WITH clauses of the query and those around it,
+    //where the innermost one wins
+    fn named_table(&self, name: &str) -> Option<&NamedTable> {
+        self.tables.iter().rev().find(|table| table.name().eq_ignore_ascii_case(name))
+    }
+
+    //note that a column of a table of this depth has been read
+    fn read(&self, depth: usize) {
+        if let Some(read) = self.enclosing_reads.get(depth) {
+            read.set(true);
+        }
+    }
+
+    //note the reads of a subquery's expressions
+    fn read_all(&self, subquery: &Subqueries) {
+        for (depth, read) in subquery.enclosing_reads.iter().enumerate() {
+            if read.get() {
+                self.read(depth);
+            }
+        }
+    }
+
+    //start the query over with new rows of the enclosing queries: forget what it read of theirs, and the results
+    //of the subqueries that read them
+    fn restart(&self) {
+        for read in &self.enclosing_reads {
+            read.set(false);
+        }
+        self.results.borrow_mut().retain(|(_, _, reads_enclosing)| !reads_enclosing);
+    }
+
+    //the result of the subquery in `expr`, evaluated against `row`
+    fn result(&self, expr: &Expr, row: &RowContext) -> Result<Rc<SubqueryResult>> {
+        if let Some((_, result, _)) = self.results.borrow().iter().find(|(subquery, _, _)| subquery == expr) {
+            return Ok(result.clone());
+        }
+        let prepared = self.prepared.borrow().iter().find(|(subquery, _)| subquery == expr).map(|(_, prepared)| prepared.clone());
+        let prepared = match prepared {
+            Some(prepared) => {
+                prepared.query.bind(&prepared.subqueries, row);
+                prepared
+            },
+            None => {
+                let select = match expr {
+                    Expr::Subquery(select) | Expr::Exists(select) | Expr::In { list: InList::Select(select), .. } => select,
+                    _ => unreachable!("only subqueries have results"),
+                };
+                let inner = self.nested(self.depth + 1, named_tables(&self.tables, select));
+                let query = Query::new(&inner, select, Some(row))?;
+                let prepared = Rc::new(PreparedSubquery { subqueries: inner, query });
+                if !prepared.subqueries.enclosing_reads.iter().any(Cell::get) {
+                    self.prepared.borrow_mut().push((expr.clone(), prepared.clone()));
+                }
+                prepared
+            },
+        };
+        let (inner, query) = (&prepared.subqueries, &prepared.query);
+        let result = match expr {
+            Expr::Subquery(_) => {
+                query.single_column()?;
+                let mut value = Value::Null;
+                query.run(inner, &mut |values| {
+                    value = values.into_iter().next().unwrap_or(Value::Null);
+                    Ok(ControlFlow::Break(()))
+                })?;
+                SubqueryResult::Value(value)
+            },
+            Expr::Exists(_) => {
+                let mut exists = false;
+                query.run(inner, &mut |_| {
+                    exists = true;
+                    Ok(ControlFlow::Break(()))
+                })?;
+                SubqueryResult::Value(Value::from_bool(Some(exists)))
+            },
+            Expr::In { expr: left, list: InList::Select(_), .. } => {
+                let column = query.single_column()?;
+                //the two sides compare as in an ordinary comparison: the affinities decide which side is converted,
+                //and an explicit COLLATE wins over a column's collation, and the left side over the subquery's column
+                let (left_affinity, right_affinity) = value::comparison_affinities(expression_affinity(left, row.sources), expression_affinity(column, &query.sources));
+                let collation = explicit_collation(left)
+                    .or(explicit_collation(column))
+                    .or(column_collation(left, row.sources))
+                    .or(column_collation(column, &query.sources));
+                let collation = collation.map_or(Ok(Collation::Binary), Collation::from_name)?;
+                let mut values = HashSet::new();
+                let mut has_null = false;
+                query.run(inner, &mut |row| {
+                    match row.into_iter().next().unwrap_or(Value::Null) {
+                        Value::Null => has_null = true,
+                        value => {
+                            values.insert(value.apply_affinity(right_affinity).collation_key(collation));
+                        },
+                    }
+                    Ok(ControlFlow::Continue(()))
+                })?;
+                SubqueryResult::Set { values, has_null, left_affinity, collation }
+            },
+            _ => unreachable!("only subqueries have results"),
+        };
+        //the rows of the queries enclosing this one stay the same while it runs,
+        //so only reading this one's row stops the result from being kept
+        let result = Rc::new(result);
+        self.read_all(inner);
+        let reads = &inner.enclosing_reads;
+        if !reads[self.depth].get() {
+            self.results.borrow_mut().push((expr.clone(), result.clone(), reads[..self.depth].iter().any(Cell::get)));
+        }
+        Ok(result)
+    }
+}
+
+//a row of the FROM clause's tables as seen by the expressions evaluated against it
+struct RowContext<'r, 'a> {
+    sources: &'r [Source],
+    subqueries: &'r Subqueries<'a>,
+    //the row of each table, which is None for a LEFT JOIN's table when none of its rows matched;
+    //tables past the end have no row (as in the row an aggregate query outputs when no rows matched),
+    //and their columns are all NULL
+    cells: &'r [Option<TableLeafCell>],
+    //the values of the aggregate calls, once a group of rows has been aggregated
+    aggregates: &'r [(Expr, Value)],
+}
+
+//an expression ready to be evaluated against the rows of a query: its columns looked up among the query's tables,
+//its function calls among the functions, and the affinities and collating sequences of its comparisons worked out,
+//once when the query is prepared rather than for every row
+enum Prepared {
+    Value(Value),
+    Column { source: usize, column: ColumnRef },
+    Unary(UnaryOperator, Box<Prepared>),
+    IsNull { expr: Box<Prepared>, negated: bool },
+    //the logic, arithmetic and concatenation operators
+    Binary(Box<Prepared>, BinaryOperator, Box<Prepared>),
+    //the comparison operators, IS and IS NOT among them
+    Compare { left: Box<Prepared>, operator: BinaryOperator, right: Box<Prepared>, comparison: Comparison },
+    //each WHEN has the comparison it makes with the operand, when there is one
+    Case { operand: Option<Box<Prepared>>, when_then: Vec<(Prepared, Prepared, Option<Comparison>)>, else_expr: Option<Box<Prepared>> },
+    //the values are converted to x's affinity and compared in x's collation
+    InValues { expr: Box<Prepared>, values: Vec<Prepared>, affinity: Affinity, collation: Collation, negated: bool },
+    //the subquery is run by the query's subqueries, which know it by the whole IN expression
+    InSelect { expr: Box<Prepared>, subquery: Box<Expr>, negated: bool },
+    //a scalar subquery or EXISTS
+    Subquery(Box<Expr>),
+    Cast { expr: Box<Prepared>, affinity: Affinity },
+    //an aggregate or window function call, whose value the row has once it's been computed
+    Aggregate { call: Box<Expr>, name: String, window: bool },
+    Function { function: ScalarFunction, args: Vec<Prepared> },
+}
+
+//how the two sides of a comparison are compared: converted the way their affinities ask for, so a TEXT column
+//compared with 5 compares with '5' and an INTEGER column compared with '5' with 5, in the collating sequence
+//the two expressions pick between them
+#[derive(Clone, Copy)]
+struct Comparison {
+    left_affinity: Affinity,
+    right_affinity: Affinity,
+    collation: Collation,
+}
+
+impl Comparison {
+    fn new(left: &Expr, right: &Expr, sources: &[Source]) -> Result<Self> {
+        let (left_affinity, right_affinity) = value::comparison_affinities(expression_affinity(left, sources), expression_affinity(right, sources));
+        Ok(Comparison { left_affinity, right_affinity, collation: comparison_collation(left, right, sources)? })
+    }
+
+    fn compare(&self, left: Value, right: Value) -> Ordering {
+        left.apply_affinity(self.left_affinity).compare_collated(&right.apply_affinity(self.right_affinity), self.collation)
+    }
+}
+
+//prepare an expression to be evaluated against the rows of a query whose tables are `sources`
+fn prepare(expr: &Expr, sources: &[Source]) -> Result<Prepared> {
+    use BinaryOperator::*;
+    let boxed = |expr: &Expr| -> Result<Box<Prepared>> { Ok(Box::new(prepare(expr, sources)?)) };
+    let all = |exprs: &[Expr]| exprs.iter().map(|expr| prepare(expr, sources)).collect::<Result<Vec<_>>>();
+    Ok(match expr {
+        Expr::Literal(literal) => Prepared::Value(Value::from_literal(literal)),
+        Expr::Column { table, name } => {
+            let (source, column) = resolve_source_column(sources, table.as_deref(), name)?;
+            Prepared::Column { source, column }
+        },
+        Expr::Unary(operator, inner) => Prepared::Unary(*operator, boxed(inner)?),
+        Expr::IsNull { expr, negated } => Prepared::IsNull { expr: boxed(expr)?, negated: *negated },
+        Expr::Binary(left, operator @ (Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual | Is | IsNot), right) => Prepared::Compare {
+            left: boxed(left)?,
+            operator: *operator,
+            right: boxed(right)?,
+            comparison: Comparison::new(left, right, sources)?,
+        },
+        Expr::Binary(left, operator, right) => Prepared::Binary(boxed(left)?, *operator, boxed(right)?),
+        Expr::Case { operand, when_then, else_expr } => Prepared::Case {
+            operand: operand.as_deref().map(boxed).transpose()?,
+            when_then: when_then.iter().map(|(when, then)| Ok((
+                prepare(when, sources)?,
+                prepare(then, sources)?,
+                operand.as_deref().map(|operand| Comparison::new(operand, when, sources)).transpose()?,
+            ))).collect::<Result<Vec<_>>>()?,
+            else_expr: else_expr.as_deref().map(boxed).transpose()?,
+        },
+        Expr::In { expr: left, list: InList::Values(values), negated } => Prepared::InValues {
+            expr: boxed(left)?,
+            values: all(values)?,
+            affinity: expression_affinity(left, sources).unwrap_or(Affinity::Blob),
+            collation: expression_collation(left, sources)?,
+            negated: *negated,
+        },
+        Expr::In { expr: left, list: InList::Select(_), negated } => Prepared::InSelect { expr: boxed(left)?, subquery: Box::new(expr.clone()), negated: *negated },
+        Expr::Subquery(_) | Expr::Exists(_) => Prepared::Subquery(Box::new(expr.clone())),
+        Expr::Cast { expr, type_name } => Prepared::Cast { expr: boxed(expr)?, affinity: Affinity::from_type_name(Some(type_name)) },
+        Expr::Collate { expr, .. } => prepare(expr, sources)?,
+        Expr::WindowFunction { name, .. } => Prepared::Aggregate { call: Box::new(expr.clone()), name: name.clone(), window: true },
+        Expr::Function { name, args } => {
+            if WindowFunction::from_call(name, args)?.is_some() {
+                bail!("misuse of window function {}()", name);
+            }
+            if AggregateFunction::from_call(name, args)?.is_some() {
+                return Ok(Prepared::Aggregate { call: Box::new(expr.clone()), name: name.clone(), window: false });
+            }
+            let Some(function) = ScalarFunction::from_call(name, args)? else {
+                bail!("no such function: {}", name);
+            };
+            let args = match args {
+                FunctionArgs::List { args, .. } => all(args)?,
+                FunctionArgs::Star => Vec::new(),
+            };
+            Prepared::Function { function, args }
+        },
+    })
+}
+
+//prepare each of a list of expressions
+fn prepare_all<'e>(exprs: impl IntoIterator<Item = &'e Expr>, sources: &[Source]) -> Result<Vec<Prepared>> {
+    exprs.into_iter().map(|expr| prepare(expr, sources)).collect()
+}
+
+//evaluate an expression against a row, with sqlite's three-valued logic: comparisons involving NULL are NULL
+fn evaluate(expr: &Prepared, row: &RowContext) -> Result<Value> {
+    use BinaryOperator::*;
+    let boolean = Value::from_bool;
+    match expr {
+        Prepared::Value(value) => Ok(value.clone()),
+        Prepared::Column { source, column } => {
+            row.subqueries.read(row.sources[*source].depth);
+            match row.cells.get(*source) {
+                Some(Some(cell)) => Ok(row.sources[*source].value(cell, *column)),
+                _ => Ok(Value::Null),
+            }
+        },
+        Prepared::Unary(operator, inner) => {
+            let value = evaluate(inner, row)?;
+            if matches!(value, Value::Null) {
+                return Ok(Value::Null);
+            }
+            Ok(match operator {
+                UnaryOperator::Not => boolean(value.truth().map(|truth| !truth)),
+                //unary plus is a no-op, even on text
+                UnaryOperator::Plus => value,
+                UnaryOperator::Negate => match value.to_number() {
+                    Number::Integer(n) => match n.checked_neg() {
+                        Some(n) => Number::Integer(n),
+                        None => Number::Real(-(n as f64)),
+                    },
+                    Number::Real(n) => Number::Real(-n),
+                }.into_value(),
+                UnaryOperator::BitNot => Number::Integer(!value.to_number().as_integer()).into_value(),
+            })
+        },
+        Prepared::IsNull { expr, negated } => {
+            let is_null = matches!(evaluate(expr, row)?, Value::Null);
+            Ok(boolean(Some(is_null != *negated)))
+        },
+        Prepared::Binary(left, And, right) => {
+            let left = evaluate(left, row)?.truth();
+            if left == Some(false) {
+                return Ok(boolean(Some(false)));
+            }
+            let right = evaluate(right, row)?.truth();
+            Ok(boolean(match (left, right) {
+                (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            }))
+        },
+        Prepared::Binary(left, Or, right) => {
+            let left = evaluate(left, row)?.truth();
+            if left == Some(true) {
+                return Ok(boolean(Some(true)));
+            }
+            let right = evaluate(right, row)?.truth();
+            Ok(boolean(match (left, right) {
+                (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            }))
+        },
+        Prepared::Binary(left, operator, right) => {
+            let left = evaluate(left, row)?;
+            let right = evaluate(right, row)?;
+            if left.is_null() || right.is_null() {
+                return Ok(Value::Null);
+            }
+            match operator {
+                Concat => Ok(Value::Text(format!("{}{}", left.to_text(), right.to_text()))),
+                _ => Ok(value::arithmetic(left.to_number(), *operator, right.to_number()).into_value()),
+            }
+        },
+        Prepared::Compare { left, operator: operator @ (Is | IsNot), right, comparison } => {
+            //IS compares NULLs like any other value
+            let left = evaluate(left, row)?;
+            let right = evaluate(right, row)?;
+            let same = match (&left, &right) {
+                (Value::Null, Value::Null) => true,
+                (Value::Null, _) | (_, Value::Null) => false,
+                _ => comparison.compare(left, right) == Ordering::Equal,
+            };
+            Ok(boolean(Some(same == (*operator == Is))))
+        },
+        Prepared::Compare { left, operator, right, comparison } => {
+            let left = evaluate(left, row)?;
+            let right = evaluate(right, row)?;
+            if left.is_null() || right.is_null() {
+                return Ok(Value::Null);
+            }
+            let ordering = comparison.compare(left, right);
+            Ok(boolean(Some(match operator {
+                Equal => ordering == Ordering::Equal,
+                NotEqual => ordering != Ordering::Equal,
+                Less => ordering == Ordering::Less,
+                LessEqual => ordering != Ordering::Greater,
+                Greater => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            })))
+        },
+        Prepared::Case { operand, when_then, else_expr } => {
+            let operand = match operand {
+                Some(operand) => Some(evaluate(operand, row)?),
+                None => None,
+            };
+            for (when, then, comparison) in when_then {
+                let when = evaluate(when, row)?;
+                let matched = match (&operand, comparison) {
+                    //CASE x WHEN y compares x = y, so a NULL on either side never matches
+                    (Some(operand), Some(comparison)) => !operand.is_null() && !when.is_null() && comparison.compare(operand.clone(), when) == Ordering::Equal,
+                    _ => when.truth() == Some(true),
+                };
+                if matched {
+                    return evaluate(then, row);
+                }
+            }
+            match else_expr {
+                Some(else_expr) => evaluate(else_expr, row),
+                None => Ok(Value::Null),
+            }
+        },
+        Prepared::InValues { expr: left, values, affinity, collation, negated } => {
+            let left = evaluate(left, row)?;
+            //x IN () is false even when x is NULL
+            if values.is_empty() {
+                return Ok(boolean(Some(*negated)));
+            }
+            let left = left.apply_affinity(*affinity);
+            //whether x is one of the values, and whether any of them is NULL
+            let (mut found, mut has_null) = (false, false);
+            for value in values {
+                let value = evaluate(value, row)?;
+                if value.is_null() {
+                    has_null = true;
+                } else if !left.is_null() && left.compare_collated(&value.apply_affinity(*affinity), *collation) == Ordering::Equal {
+                    found = true;
+                    break;
+                }
+            }
+            Ok(in_result(found, left.is_null() || has_null, *negated))
+        },
+        Prepared::InSelect { expr: left, subquery, negated } => {
+            let left = evaluate(left, row)?;
+            let result = row.subqueries.result(subquery, row)?;
+            let SubqueryResult::Set { values, has_null, left_affinity, collation } = result.as_ref() else {
+                unreachable!("IN subqueries have sets of values")
+            };
+            if values.is_empty() && !has_null {
+                return Ok(boolean(Some(*negated)));
+            }
+            let key = left.clone().apply_affinity(*left_affinity).collation_key(*collation);
+            Ok(in_result(!left.is_null() && values.contains(&key), left.is_null() || *has_null, *negated))
+        },
+        Prepared::Subquery(subquery) => match row.subqueries.result(subquery, row)?.as_ref() {
+            SubqueryResult::Value(value) => Ok(value.clone()),
+            SubqueryResult::Set { .. } => unreachable!("only IN subqueries have sets of values"),
+        },
+        Prepared::Cast { expr, affinity } => Ok(evaluate(expr, row)?.cast(*affinity)),
+        Prepared::Aggregate { call, name, window } => match row.aggregates.iter().find(|(computed, _)| computed == call.as_ref()) {
+            Some((_, value)) => Ok(value.clone()),
+            None if *window => bail!("misuse of window function {}()", name),
+            None => bail!("misuse of aggregate: {}()", name),
+        },
+        Prepared::Function { function, args } => {
+            let values = args.iter().map(|arg| evaluate(arg, row)).collect::<Result<Vec<_>>>()?;
+            function.call(&values)
+        },
+    }
+}
+
+//the value of x IN (...), given whether x was found among the values and whether x or any of the values is NULL
+fn in_result(found: bool, has_null: bool, negated: bool) -> Value {
+    match (found, has_null) {
+        (true, _) => Value::from_bool(Some(!negated)),
+        (false, true) => Value::Null,
+        (false, false) => Value::from_bool(Some(negated)),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_pattern_and_in_predicates() {
+    let rows = [Value::Integer(1), Value::Integer(2), Value::Null];
+    let leaf = test_page(0x0d, None, &rows.iter().enumerate().map(|(i, x)| test_table_leaf_cell(i as i64 + 1, &test_record(std::slice::from_ref(x)))).collect::<Vec<_>>());
+    let database = test_database("pattern-and-in-predicates", &[leaf]);
+    let schema_tables = [Schema {
+        schema_type: "table".to_string(),
+        name: "t".to_string(),
+        tbl_name: "t".to_string(),
+        root_page: 2,
+        sql: "CREATE TABLE t (x)".to_string(),
+    }];
+    let run = |sql: &str| -> Result<Vec<String>> {
+        let statement = sql::parse_select(sql)?;
+        let subqueries = Subqueries::new(&database, &schema_tables, 0, Vec::new());
+        let rows = Query::new(&subqueries, &statement, None)?.collect(&subqueries)?;
+        Ok(rows[0].payload.values.iter().map(|value| value.to_string()).collect())
+    };
+    let results = |sql: &str| run(sql).unwrap().join("|");
+    assert_eq!(results("SELECT '10%' LIKE '10!%' ESCAPE '!', '100' LIKE '10!%' ESCAPE '!', 'a_c' LIKE 'a!_c' ESCAPE '!', 'abc' LIKE 'a!_c' ESCAPE '!'"), "1|0|1|0");
+    assert_eq!(results("SELECT 'abc' NOT LIKE 'A%', 'abc' NOT GLOB 'b*', 'abc' NOT REGEXP '^a', 5 NOT BETWEEN 1 AND 3, 2 NOT IN (1, 2), NULL NOT LIKE 'a'"), "0|1|0|1|0|null");
+    //a NULL in the list makes a value that isn't found unknown rather than false, either way round
+    assert_eq!(results("SELECT 1 IN (2, NULL), 1 NOT IN (2, NULL), 1 IN (1, NULL), NULL IN (1), NULL IN (), 1 NOT IN (2, 3)"), "null|null|1|null|0|1");
+    assert_eq!(results("SELECT 2 IN (SELECT x FROM t), 3 IN (SELECT x FROM t), 3 IN (SELECT x FROM t WHERE x NOT NULL), 3 NOT IN (SELECT x FROM t WHERE x NOT NULL), 3 NOT IN (SELECT x FROM t)"), "1|null|0|1|null");
+    assert!(run("SELECT 'a' REGEXP '('").unwrap_err().to_string().contains("regex parse error"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_select_distinct_rows() {
+    let row = |x: Value, y: &str| vec![x, Value::Text(y.to_string())];
+    let rows = vec![row(Value::Integer(1), "a"), row(Value::Real(1.0), "A"), row(Value::Null, "b"), row(Value::Null, "B"), row(Value::Integer(2), "b")];
+    let (database, schema_tables) = test_tables("select-distinct-rows", &[("CREATE TABLE t (x, y TEXT COLLATE NOCASE)", rows)]);
+    let query = |sql: &str| test_query(&database, &schema_tables, sql);
+    //1 and 1.0 are the same value, and the NULLs all one; the first of the rows that are the same is output
+    assert_eq!(query("SELECT DISTINCT x FROM t"), ["1", "null", "2"]);
+    //text is compared in its column's collation
+    assert_eq!(query("SELECT DISTINCT x, y FROM t"), ["1|a", "null|b", "2|b"]);
+    assert_eq!(query("SELECT count(DISTINCT x), count(DISTINCT y) FROM t"), ["2|2"]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_join_rows() {
+    let text = |text: &str| Value::Text(text.to_string());
+    let (database, schema_tables) = test_tables("join-rows", &[
+        ("CREATE TABLE a (id INTEGER PRIMARY KEY, name TEXT)", ["x", "y", "z"].iter().map(|name| vec![Value::Null, text(name)]).collect()),
+        ("CREATE TABLE b (id INTEGER PRIMARY KEY, a_id INTEGER, v TEXT)", [(1, "p"), (1, "q"), (3, "r")].iter().map(|(a_id, v)| vec![Value::Null, Value::Integer(*a_id), text(v)]).collect()),
+        ("CREATE TABLE c (k, v)", vec![vec![Value::Integer(1), text("c1")], vec![Value::Integer(2), text("c2")]]),
+        ("CREATE TABLE d (k, w)", vec![vec![Value::Integer(1), text("d1")], vec![Value::Integer(3), text("d3")]]),
+    ]);
+    let query = |sql: &str| test_query(&database, &schema_tables, sql);
+    //a row on the left that nothing matches is kept once, with NULLs for the right table's columns
+    assert_eq!(query("SELECT a.name, b.v FROM a LEFT JOIN b ON b.a_id = a.id ORDER BY a.name, b.v"), ["x|p", "x|q", "y|null", "z|r"]);
+    assert_eq!(query("SELECT a.name FROM a LEFT JOIN b ON b.a_id = a.id WHERE b.v IS NULL"), ["y"]);
+    assert_eq!(query("SELECT name, v FROM a JOIN b ON b.a_id = a.id AND b.v > 'p'"), ["x|q", "z|r"]);
+    //a column joined with USING appears once in *, as the left table's
+    assert_eq!(query("SELECT * FROM c JOIN d USING (k)"), ["1|c1|d1"]);
+    assert_eq!(query("SELECT * FROM c LEFT JOIN d USING (k)"), ["1|c1|d1", "2|c2|null"]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_subquery_results() {
+    let text = |text: &str| Value::Text(text.to_string());
+    let (database, schema_tables) = test_tables("subquery-results", &[
+        ("CREATE TABLE a (id INTEGER PRIMARY KEY, name TEXT)", ["x", "y", "z"].iter().map(|name| vec![Value::Null, text(name)]).collect()),
+        ("CREATE TABLE b (id INTEGER PRIMARY KEY, a_id INTEGER, v TEXT)", [(1, "p"), (1, "q"), (3, "r")].iter().map(|(a_id, v)| vec![Value::Null, Value::Integer(*a_id), text(v)]).collect()),
+    ]);
+    let query = |sql: &str| test_query(&database, &schema_tables, sql);
+    //correlated subqueries run again for each row of the query around them
+    assert_eq!(query("SELECT name, (SELECT count(*) FROM b WHERE b.a_id = a.id) FROM a"), ["x|2", "y|0", "z|1"]);
+    assert_eq!(query("SELECT name FROM a WHERE EXISTS (SELECT 1 FROM b WHERE b.a_id = a.id)"), ["x", "z"]);
+    assert_eq!(query("SELECT name FROM a WHERE NOT EXISTS (SELECT 1 FROM b WHERE b.a_id = a.id)"), ["y"]);
+    //a subquery prepared once is given each row in turn, and so are the subqueries inside it and the SELECTs of its compound
+    assert_eq!(query("SELECT name, (SELECT count(*) FROM b WHERE b.a_id = (SELECT a.id)) FROM a"), ["x|2", "y|0", "z|1"]);
+    assert_eq!(query("SELECT name FROM a WHERE EXISTS (SELECT 1 FROM b WHERE b.a_id = a.id UNION ALL SELECT 1 FROM b WHERE a.id = 2)"), ["x", "y", "z"]);
+    //a subquery in FROM that reads the row is prepared again for each one
+    assert_eq!(query("SELECT name, (SELECT count(*) FROM (SELECT * FROM b WHERE b.a_id = a.id)) FROM a"), ["x|2", "y|0", "z|1"]);
+    //the same subquery in each SELECT of a compound reads that SELECT's tables
+    assert_eq!(query("SELECT (SELECT name) FROM a UNION ALL SELECT (SELECT name) FROM (SELECT v name FROM b)"), ["x", "y", "z", "p", "q", "r"]);
+    //a scalar subquery is NULL without rows, and its first row's value otherwise
+    assert_eq!(query("SELECT (SELECT v FROM b WHERE a_id = 2), (SELECT v FROM b ORDER BY v DESC)"), ["null|r"]);
+    assert_eq!(query("SELECT name FROM a WHERE id = (SELECT max(a_id) FROM b WHERE v < 'r')"), ["x"]);
+    assert_eq!(query("SELECT max(n) FROM (SELECT a_id, count(*) n FROM b GROUP BY a_id)"), ["2"]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_with_rows() {
+    let text = |text: &str| Value::Text(text.to_string());
+    let (database, schema_tables) = test_tables("with-rows", &[
+        ("CREATE TABLE a (id INTEGER PRIMARY KEY, name TEXT)", ["x", "y", "z"].iter().map(|name| vec![Value::Null, text(name)]).collect()),
+        ("CREATE TABLE b (id INTEGER PRIMARY KEY, a_id INTEGER, v TEXT)", [(1, "p"), (1, "q"), (3, "r")].iter().map(|(a_id, v)| vec![Value::Null, Value::Integer(*a_id), text(v)]).collect()),
+    ]);
+    let query = |sql: &str| test_query(&database, &schema_tables, sql);
+    assert_eq!(query("WITH later AS (SELECT * FROM a WHERE id > 1) SELECT name FROM later"), ["y", "z"]);
+    assert_eq!(query("WITH counts AS (SELECT a_id, count(*) c FROM b GROUP BY a_id) SELECT name, (SELECT c FROM counts WHERE counts.a_id = a.id) FROM a"), ["x|2", "y|null", "z|1"]);
+    //UNION drops the rows the recursion has already had, which ends it once it comes back round to 1
+    assert_eq!(query("WITH RECURSIVE c(x) AS (SELECT 1 UNION SELECT x % 3 + 1 FROM c) SELECT x FROM c"), ["1", "2", "3"]);
+    assert_eq!(query("WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 4) SELECT sum(x) FROM c"), ["10"]);
+    //a query that reads the rows as they come stops the recursion once it has reached its LIMIT
+    assert_eq!(query("WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT x FROM c LIMIT 3"), ["1", "2", "3"]);
+    assert_eq!(query("WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT x FROM c LIMIT 2 OFFSET 3"), ["4", "5"]);
+    //the row taken out of the queue is the first in the ORDER BY order, and of those that sort the same the first
+    //that went in: by depth, every word of each length in turn, the queue holding the 81 of the last; by depth
+    //descending, each word followed by those it starts
+    let words = |depth: usize| -> Vec<String> {
+        (0..=depth).flat_map(|length| (0..3usize.pow(length as u32)).map(move |n| (0..length).rev().map(|place| ["x", "y", "z"][n / 3usize.pow(place as u32) % 3]).collect())).collect()
+    };
+    assert_eq!(query("WITH RECURSIVE w(d, s) AS (SELECT 0, '' UNION ALL SELECT d + 1, s || name FROM w, a WHERE d < 4 ORDER BY 1) SELECT s FROM w"), words(4));
+    assert_eq!(query("WITH RECURSIVE w(d, s) AS (SELECT 0, '' UNION ALL SELECT d + 1, s || name FROM w, a WHERE d < 4 ORDER BY 1 DESC LIMIT 7) SELECT s FROM w"),
+        ["", "x", "xx", "xxx", "xxxx", "xxxy", "xxxz"]);
+    //rows that read no row of the queries around them are kept for the next time the table is read,
+    //so b (one page) is read once however many times c is
+    let pages_read = |sql: &str| {
+        database.pages_read.set(0);
+        (query(sql), database.pages_read.get())
+    };
+    assert_eq!(pages_read("WITH c AS (SELECT a_id FROM b) SELECT count(*) FROM c x, c y"), (vec!["9".to_string()], 1));
+    assert_eq!(pages_read("WITH c AS (SELECT a_id FROM b) SELECT name, (SELECT count(*) FROM (SELECT * FROM c WHERE c.a_id = a.id)) FROM a"),
+        (vec!["x|2".to_string(), "y|0".to_string(), "z|1".to_string()], 2));
+}
+
+#[cfg(test)]
+#[test]
+fn test_compound_rows() {
+    let integers = |values: &[i64]| values.iter().map(|n| vec![Value::Integer(*n)]).collect();
+    let (database, schema_tables) = test_tables("compound-rows", &[("CREATE TABLE n (x)", integers(&[3, 1, 2, 1, 3])), ("CREATE TABLE m (y)", integers(&[2, 4, 2]))]);
+    let query = |sql: &str| test_query(&database, &schema_tables, sql);
+    //the rows of UNION, INTERSECT and EXCEPT are distinct, and ORDER BY and LIMIT apply to the whole compound
+    assert_eq!(query("SELECT x FROM n UNION SELECT x FROM n"), ["1", "2", "3"]);
+    assert_eq!(query("SELECT x FROM n UNION SELECT y FROM m ORDER BY 1 DESC LIMIT 3"), ["4", "3", "2"]);
+    assert_eq!(query("SELECT x FROM n UNION SELECT y FROM m LIMIT 2 OFFSET 1"), ["2", "3"]);
+    assert_eq!(query("SELECT x FROM n INTERSECT SELECT y FROM m"), ["2"]);
+    assert_eq!(query("SELECT x FROM n EXCEPT SELECT y FROM m ORDER BY x"), ["1", "3"]);
+    assert_eq!(query("SELECT x FROM n UNION ALL SELECT y FROM m LIMIT 2 OFFSET 4"), ["3", "2"]);
+}
+
+//the affinity an expression gives its value when compared: a column's declared affinity or the type of a CAST;
+//anything else has none
+fn expression_affinity(expr: &Expr, sources: &[Source]) -> Option<Affinity> {
+    match expr {
+        Expr::Column { table, name } => match resolve_source_column(sources, table.as_deref(), name) {
+            std::result::Result::Ok((source_index, column)) => Some(sources[source_index].affinity(column)),
+            Err(_) => None,
+        },
+        Expr::Cast { type_name, .. } => Some(Affinity::from_type_name(Some(type_name))),
+        Expr::Collate { expr, .. } => expression_affinity(expr, sources),
+        _ => None,
+    }
+}
+
+//the collating sequence a comparison uses: an explicit COLLATE wins over a column's collation (which is BINARY
+//unless it declares another), and the left side wins over the right
+fn comparison_collation(left: &Expr, right: &Expr, sources: &[Source]) -> Result<Collation> {
+    let collation = explicit_collation(left)
+        .or(explicit_collation(right))
+        .or(column_collation(left, sources))
+        .or(column_collation(right, sources));
+    collation.map_or(Ok(Collation::Binary), Collation::from_name)
+}
+
+//the collating sequence an expression sorts with: its COLLATE clause, else its column's, else BINARY
+fn expression_collation(expr: &Expr, sources: &[Source]) -> Result<Collation> {
+    explicit_collation(expr).or(column_collation(expr, sources)).map_or(Ok(Collation::Binary), Collation::from_name)
+}
+
+fn explicit_collation(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Collate { collation, .. } => Some(collation),
+        _ => None,
+    }
+}
+
+//the collating sequence of a column: the one it declares, else BINARY
+fn column_collation<'a>(expr: &Expr, sources: &'a [Source]) -> Option<&'a str> {
+    match expr {
+        Expr::Column { table, name } => {
+            let (source_index, column) = resolve_source_column(sources, table.as_deref(), name).ok()?;
+            Some(sources[source_index].collation(column).unwrap_or("BINARY"))
+        },
+        _ => None,
+    }
+}
+
+//report errors as a message and a non-zero exit code rather than a panic
+fn main() {
+    // Parse arguments
+    let args = std::env::args().collect::<Vec<_>>();
+    // debug args
+    // let args = vec!["".to_string(),"sample.db".to_string(), ".dbinfo".to_string()];
+    // let args = vec!["".to_string(),"sample.db".to_string(), ".tables".to_string()];
+    // let args = vec!["".to_string(),"sample.db".to_string(), "SELECT COUNT(*) FROM apples".to_string()];
+    // let args = vec!["".to_string(),"sample.db".to_string(), "SELECT name FROM apples".to_string()];
+    // let args = vec!["".to_string(),"sample.db".to_string(), "SELECT name,color FROM apples".to_string()];
+    if let Err(err) = run(&args) {
+        eprintln!("Error: {:#}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    match args.len() {
+        0 | 1 => bail!("Missing <database path> and <command>"),
+        2 => bail!("Missing <command>"),
+        _ => {}
+    }
+
+    // Parse command and act accordingly
+    let command = &args[2];
+    match command.as_str() {
+        ".dbinfo" => {
+            // dbinfo(args);
+            let database = Database::new(&args[1])?;
             println!("database page size: {}", database.page_size);
-            let schema_tables = database.get_schema_table().unwrap();
-            println!("number of tables: {}",schema_tables.len());
+            let schema_tables = database.get_schema_table()?;
+            let num_tables = schema_tables.iter().filter(|table| table.schema_type == "table").count();
+            println!("number of tables: {}",num_tables);
         },
         ".tables" => {
             // tables(args);
-            let mut database = Database::new(&args[1])?;
-            let schema_tables = database.get_schema_table().unwrap();
+            let database = Database::new(&args[1])?;
+            let schema_tables = database.get_schema_table()?;
             let mut table_names:Vec<String> = Vec::new();
-            for table in schema_tables {
+            for table in schema_tables.into_iter().filter(|table| table.schema_type == "table") {
                 table_names.push(table.tbl_name);
             }
             println!("{}",table_names.join(" "));
         }
         // _ => bail!("Missing or invalid command passed: {}", command),
-        _ => {
-            parse_sql(args);
-
-        }
+        _ => parse_sql(args)?,
     }
 
     Ok(())
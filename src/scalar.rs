@@ -0,0 +1,784 @@
+use anyhow::{bail, Result};
+use regex::Regex;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::ops::RangeInclusive;
+use std::str::Chars;
+
+use crate::ast::FunctionArgs;
+use crate::datetime;
+use crate::value::{self, Value};
+
+//the built-in scalar functions, which compute a value from the arguments of a single row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScalarFunction {
+    Length,
+    Lower,
+    Upper,
+    //substr(x, start[, length]), also spelled substring
+    Substr,
+    Trim,
+    LTrim,
+    RTrim,
+    Replace,
+    Instr,
+    Abs,
+    Round,
+    Coalesce,
+    IfNull,
+    NullIf,
+    TypeOf,
+    Hex,
+    Quote,
+    //printf(format, ...), also spelled format
+    Printf,
+    Iif,
+    //min and max with more than one argument; with one they're the aggregates
+    Min,
+    Max,
+    //like(pattern, x[, escape]), glob(pattern, x) and regexp(pattern, x), which the LIKE, GLOB and REGEXP operators call
+    Like,
+    Glob,
+    Regexp,
+    //the date and time functions, which take a time value and modifiers
+    Date,
+    Time,
+    DateTime,
+    JulianDay,
+    UnixEpoch,
+    StrFTime,
+}
+
+impl ScalarFunction {
+    //the scalar function a call names, if it names one
+    pub fn from_call(name: &str, args: &FunctionArgs) -> Result<Option<Self>> {
+        use ScalarFunction::*;
+        //DISTINCT makes no difference to a scalar function
+        let arg_count = match args {
+            FunctionArgs::Star => None,
+            FunctionArgs::List { args, .. } => Some(args.len()),
+        };
+        let name = name.to_ascii_lowercase();
+        let any = usize::MAX;
+        let (function, arity): (Self, RangeInclusive<usize>) = match name.as_str() {
+            "length" => (Length, 1..=1),
+            "lower" => (Lower, 1..=1),
+            "upper" => (Upper, 1..=1),
+            "substr" | "substring" => (Substr, 2..=3),
+            "trim" => (Trim, 1..=2),
+            "ltrim" => (LTrim, 1..=2),
+            "rtrim" => (RTrim, 1..=2),
+            "replace" => (Replace, 3..=3),
+            "instr" => (Instr, 2..=2),
+            "abs" => (Abs, 1..=1),
+            "round" => (Round, 1..=2),
+            "coalesce" => (Coalesce, 2..=any),
+            "ifnull" => (IfNull, 2..=2),
+            "nullif" => (NullIf, 2..=2),
+            "typeof" => (TypeOf, 1..=1),
+            "hex" => (Hex, 1..=1),
+            "quote" => (Quote, 1..=1),
+            "printf" | "format" => (Printf, 1..=any),
+            "iif" => (Iif, 2..=3),
+            "min" => (Min, 2..=any),
+            "max" => (Max, 2..=any),
+            "like" => (Like, 2..=3),
+            "glob" => (Glob, 2..=2),
+            "regexp" => (Regexp, 2..=2),
+            "date" => (Date, 0..=any),
+            "time" => (Time, 0..=any),
+            "datetime" => (DateTime, 0..=any),
+            "julianday" => (JulianDay, 0..=any),
+            "unixepoch" => (UnixEpoch, 0..=any),
+            "strftime" => (StrFTime, 1..=any),
+            _ => return Ok(None),
+        };
+        if !arg_count.is_some_and(|count| arity.contains(&count)) {
+            bail!("wrong number of arguments to function {}()", name);
+        }
+        Ok(Some(function))
+    }
+
+    pub fn call(self, args: &[Value]) -> Result<Value> {
+        use ScalarFunction::*;
+        Ok(match self {
+            Coalesce | IfNull => args.iter().find(|arg| !arg.is_null()).cloned().unwrap_or(Value::Null),
+            NullIf if args[0].compare(&args[1]) == Ordering::Equal => Value::Null,
+            NullIf => args[0].clone(),
+            Iif if args[0].truth() == Some(true) => args[1].clone(),
+            Iif => args.get(2).cloned().unwrap_or(Value::Null),
+            TypeOf => Value::Text(match &args[0] {
+                Value::Null => "null",
+                Value::Integer(_) => "integer",
+                Value::Real(_) => "real",
+                Value::Text(_) => "text",
+                Value::Blob(_) => "blob",
+            }.to_string()),
+            Quote => Value::Text(quote(&args[0])),
+            Hex => Value::Text(hex(&bytes(&args[0]))),
+            Printf => match &args[0] {
+                Value::Null => Value::Null,
+                format => Value::Text(printf(&format.to_text(), &args[1..])),
+            },
+            Date => datetime::date(args),
+            Time => datetime::time(args),
+            DateTime => datetime::datetime(args),
+            JulianDay => datetime::julian_day(args),
+            UnixEpoch => datetime::unix_epoch(args),
+            StrFTime => datetime::strftime(args),
+            //the rest are NULL when any argument is
+            _ if args.iter().any(Value::is_null) => Value::Null,
+            //ties keep the earlier argument
+            Min => args.iter().reduce(|min, arg| if arg.compare(min) == Ordering::Less { arg } else { min }).cloned().unwrap_or(Value::Null),
+            Max => args.iter().reduce(|max, arg| if arg.compare(max) == Ordering::Greater { arg } else { max }).cloned().unwrap_or(Value::Null),
+            //text is measured in characters, up to the first NUL, and blobs in bytes
+            Length => Value::Integer(match &args[0] {
+                Value::Blob(bytes) => bytes.len(),
+                value => value.to_text().chars().take_while(|c| *c != '\0').count(),
+            } as i64),
+            //only ASCII letters change case, as in sqlite without ICU
+            Lower => Value::Text(args[0].to_text().to_ascii_lowercase()),
+            Upper => Value::Text(args[0].to_text().to_ascii_uppercase()),
+            Substr => {
+                let start = args[1].to_number().as_integer();
+                let length = args.get(2).map(|length| length.to_number().as_integer());
+                match &args[0] {
+                    Value::Blob(bytes) => {
+                        let (from, to) = substr_range(bytes.len(), start, length);
+                        Value::Blob(bytes[from..to].to_vec())
+                    },
+                    value => {
+                        let chars: Vec<char> = value.to_text().chars().collect();
+                        let (from, to) = substr_range(chars.len(), start, length);
+                        Value::Text(chars[from..to].iter().collect())
+                    },
+                }
+            },
+            Trim | LTrim | RTrim => {
+                let text = args[0].to_text();
+                let trimmed: Vec<char> = args.get(1).map_or(vec![' '], |chars| chars.to_text().chars().collect());
+                let text = match self {
+                    LTrim => text.trim_start_matches(trimmed.as_slice()),
+                    RTrim => text.trim_end_matches(trimmed.as_slice()),
+                    _ => text.trim_matches(trimmed.as_slice()),
+                };
+                Value::Text(text.to_string())
+            },
+            Replace => {
+                let pattern = args[1].to_text();
+                if pattern.is_empty() {
+                    args[0].clone()
+                } else {
+                    Value::Text(args[0].to_text().replace(&pattern, &args[2].to_text()))
+                }
+            },
+            //the 1-based position of the first occurrence, in bytes between blobs and characters otherwise, or 0
+            Instr => Value::Integer(match (&args[0], &args[1]) {
+                (Value::Blob(haystack), Value::Blob(needle)) => match needle.is_empty() {
+                    true => 1,
+                    false => haystack.windows(needle.len()).position(|window| window == needle.as_slice()).map_or(0, |position| position + 1),
+                },
+                (haystack, needle) => {
+                    let haystack = haystack.to_text();
+                    haystack.find(&needle.to_text()).map_or(0, |position| haystack[..position].chars().count() + 1)
+                },
+            } as i64),
+            Abs => match &args[0] {
+                Value::Integer(n) => match n.checked_abs() {
+                    Some(n) => Value::Integer(n),
+                    None => bail!("integer overflow"),
+                },
+                //anything that isn't a number already becomes a real
+                value => Value::Real(value.to_number().as_real().abs()),
+            },
+            Like => {
+                let escape = match args.get(2).map(|escape| escape.to_text()) {
+                    Some(escape) if escape.chars().count() != 1 => bail!("ESCAPE expression must be a single character"),
+                    escape => escape.and_then(|escape| escape.chars().next()),
+                };
+                let pattern: Vec<char> = args[0].to_text().chars().collect();
+                let text: Vec<char> = args[1].to_text().chars().collect();
+                Value::from_bool(Some(matches_pattern(&pattern, &text, &PatternRules::like(escape))))
+            },
+            Glob => {
+                let pattern: Vec<char> = args[0].to_text().chars().collect();
+                let text: Vec<char> = args[1].to_text().chars().collect();
+                Value::from_bool(Some(matches_pattern(&pattern, &text, &PatternRules::GLOB)))
+            },
+            Regexp => Value::from_bool(Some(regexp(&args[0].to_text(), &args[1].to_text())?)),
+            Round => {
+                let digits = args.get(1).map_or(0, |digits| digits.to_number().as_integer()).clamp(0, 30) as usize;
+                Value::Real(round(args[0].to_number().as_real(), digits))
+            },
+        })
+    }
+}
+
+//the characters (or bytes) substr(x, start, length) picks out of `len` of them: positions count from 1,
+//a negative start counts back from the end, and a negative length takes the characters before the start
+fn substr_range(len: usize, start: i64, length: Option<i64>) -> (usize, usize) {
+    let len = len as i128;
+    let mut start = start as i128;
+    let negative = length.is_some_and(|length| length < 0);
+    //with no length, everything from the start on
+    let given = length.is_some();
+    let mut length = length.map_or(i64::MAX as i128, |length| (length as i128).abs());
+    if start < 0 {
+        start += len;
+        if start < 0 {
+            length = (length + start).max(0);
+            start = 0;
+        }
+    } else if start > 0 {
+        start -= 1;
+    } else if given && length > 0 {
+        //position 0 is just before the first character
+        length -= 1;
+    }
+    if negative {
+        start -= length;
+        if start < 0 {
+            length += start;
+            start = 0;
+        }
+    }
+    let from = start.min(len);
+    (from as usize, (from + length.max(0)).min(len) as usize)
+}
+
+//round to a number of decimal places: to a whole number, halves round away from zero,
+//and otherwise the digits printf's %!f gives decide; reals too big to have a fraction stay as they are
+fn round(n: f64, digits: usize) -> f64 {
+    if n.abs() > 4503599627370496.0 {
+        n
+    } else if digits == 0 {
+        let rounded = (n.abs() + 0.5).trunc();
+        if n < 0.0 { -rounded } else { rounded }
+    } else {
+        printf(&format!("%!.{}f", digits), &[Value::Real(n)]).parse().unwrap_or(n)
+    }
+}
+
+//the wildcards of LIKE or GLOB patterns
+struct PatternRules {
+    //matches any run of characters: % or *
+    any_run: Option<char>,
+    //matches a single character: _ or ?
+    any_char: Option<char>,
+    //the character that makes the next one match itself, for LIKE ... ESCAPE
+    escape: Option<char>,
+    //GLOB's [...] character sets
+    sets: bool,
+    //LIKE ignores the case of ASCII letters
+    ignore_case: bool,
+}
+
+impl PatternRules {
+    const GLOB: PatternRules = PatternRules { any_run: Some('*'), any_char: Some('?'), escape: None, sets: true, ignore_case: false };
+
+    //an escape character that is also a wildcard is only an escape character
+    fn like(escape: Option<char>) -> Self {
+        PatternRules {
+            any_run: Some('%').filter(|c| escape != Some(*c)),
+            any_char: Some('_').filter(|c| escape != Some(*c)),
+            escape,
+            sets: false,
+            ignore_case: true,
+        }
+    }
+}
+
+//how matching a pattern against some text came out
+#[derive(PartialEq)]
+enum PatternMatch {
+    Match,
+    NoMatch,
+    //the text ran out inside a run wildcard, so no later start for an enclosing run can match either
+    NoWildcardMatch,
+}
+
+//whether all of `text` matches `pattern`, the way sqlite's LIKE and GLOB match it
+fn matches_pattern(pattern: &[char], text: &[char], rules: &PatternRules) -> bool {
+    compare_pattern(pattern, text, rules) == PatternMatch::Match
+}
+
+fn compare_pattern(pattern: &[char], text: &[char], rules: &PatternRules) -> PatternMatch {
+    let (mut p, mut t) = (0, 0);
+    while let Some(&c) = pattern.get(p) {
+        if Some(c) == rules.escape {
+            //an escape character at the end of the pattern matches nothing
+            let Some(&literal) = pattern.get(p + 1) else { return PatternMatch::NoMatch };
+            if !text.get(t).is_some_and(|&next| same_char(literal, next, rules)) {
+                return PatternMatch::NoMatch;
+            }
+            p += 2;
+            t += 1;
+            continue;
+        }
+        if Some(c) == rules.any_run {
+            //a run of wildcards: the single-character ones among them each take a character, and then
+            //the rest of the pattern has to match from some point on
+            let mut single = 0;
+            while let Some(&c) = pattern.get(p) {
+                match Some(c) {
+                    run if run == rules.any_run => {},
+                    one if one == rules.any_char => single += 1,
+                    _ => break,
+                }
+                p += 1;
+            }
+            if t + single > text.len() {
+                return PatternMatch::NoWildcardMatch;
+            }
+            t += single;
+            if p == pattern.len() {
+                return PatternMatch::Match;
+            }
+            //like sqlite, stop at the first start that matches or that shows no start can, which keeps
+            //patterns with many runs from trying every way of splitting the text between them
+            for start in t..text.len() {
+                match compare_pattern(&pattern[p..], &text[start..], rules) {
+                    PatternMatch::NoMatch => {},
+                    result => return result,
+                }
+            }
+            return PatternMatch::NoWildcardMatch;
+        }
+        let Some(&next) = text.get(t) else { return PatternMatch::NoMatch };
+        if Some(c) == rules.any_char {
+            p += 1;
+        } else if c == '[' && rules.sets {
+            match char_set(&pattern[p + 1..], next) {
+                Some((true, length)) => p += length + 1,
+                _ => return PatternMatch::NoMatch,
+            }
+        } else if same_char(c, next, rules) {
+            p += 1;
+        } else {
+            return PatternMatch::NoMatch;
+        }
+        t += 1;
+    }
+    match t == text.len() {
+        true => PatternMatch::Match,
+        false => PatternMatch::NoMatch,
+    }
+}
+
+fn same_char(a: char, b: char, rules: &PatternRules) -> bool {
+    a == b || rules.ignore_case && a.eq_ignore_ascii_case(&b)
+}
+
+//whether a GLOB set like [a-z] or [^0-9] (given from after its [) contains `c`, and how much of the pattern
+//it takes up through its ]; a ] straight after the [ or ^ is one of its characters. None when it isn't closed
+fn char_set(set: &[char], c: char) -> Option<(bool, usize)> {
+    let mut position = 0;
+    let invert = set.first() == Some(&'^');
+    if invert {
+        position += 1;
+    }
+    let mut seen = false;
+    if set.get(position) == Some(&']') {
+        seen = c == ']';
+        position += 1;
+    }
+    let mut prior = None;
+    loop {
+        match *set.get(position)? {
+            ']' => return Some((seen != invert, position + 1)),
+            '-' if prior.is_some() && set.get(position + 1).is_some_and(|next| *next != ']') => {
+                let (low, high) = (prior.unwrap_or(c), set[position + 1]);
+                seen |= low <= c && c <= high;
+                prior = None;
+                position += 2;
+            },
+            member => {
+                seen |= member == c;
+                prior = Some(member);
+                position += 1;
+            },
+        }
+    }
+}
+
+//REGEXP: whether the regular expression matches anywhere in the text. The last pattern stays compiled,
+//since a query usually matches every row against the same one
+fn regexp(pattern: &str, text: &str) -> Result<bool> {
+    thread_local! {
+        static COMPILED: RefCell<Option<Regex>> = const { RefCell::new(None) };
+    }
+    COMPILED.with(|compiled| {
+        let mut compiled = compiled.borrow_mut();
+        let regex = match compiled.take() {
+            Some(regex) if regex.as_str() == pattern => regex,
+            _ => Regex::new(pattern)?,
+        };
+        let is_match = regex.is_match(text);
+        *compiled = Some(regex);
+        Ok(is_match)
+    })
+}
+
+//the bytes of a value: a blob's own, or those of its text
+fn bytes(value: &Value) -> Vec<u8> {
+    match value {
+        Value::Null => Vec::new(),
+        Value::Blob(bytes) => bytes.clone(),
+        value => value.to_text().into_bytes(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+//the value as an SQL literal that reads back as the same value
+fn quote(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(n) => n.to_string(),
+        Value::Real(n) if n.is_infinite() => if *n > 0.0 { "9.0e+999" } else { "-9.0e+999" }.to_string(),
+        Value::Real(n) => {
+            //15 significant digits when they're enough to read back as the same real, and 20 otherwise
+            let text = value.to_string();
+            if text.parse::<f64>().is_ok_and(|parsed| parsed == *n) {
+                text
+            } else {
+                printf("%!.20e", &[Value::Real(*n)])
+            }
+        },
+        Value::Text(text) => format!("'{}'", text.replace('\'', "''")),
+        Value::Blob(bytes) => format!("X'{}'", hex(bytes)),
+    }
+}
+
+// ***PRINTF***
+
+//the significant digits of a real that printf works with, or with the ! flag; any it's asked for
+//past these are zeros
+const PRINTF_DIGITS: usize = 16;
+const PRINTF_MORE_DIGITS: usize = 26;
+
+//%f, %e or %g of a positive real, with its digits rounded the way sqlite rounds them
+fn real_conversion(kind: char, n: f64, precision: usize, conversion: &Conversion) -> String {
+    let limit = if conversion.more_digits { PRINTF_MORE_DIGITS } else { PRINTF_DIGITS };
+    //the digits rounded to `significant` of them, and how many of them go before the point
+    let digits = |significant: i64| -> (Vec<u8>, i64) {
+        if n == 0.0 {
+            return (Vec::new(), 1);
+        }
+        let (digits, point) = value::decimal_digits(n, significant.clamp(0, limit as i64) as usize);
+        (digits, point as i64)
+    };
+    let (text, trim_zeros) = match kind {
+        'f' | 'F' => {
+            let (_, point) = digits(limit as i64);
+            let (digits, point) = digits(point + precision as i64);
+            (fixed_notation(&digits, point, precision), conversion.more_digits)
+        },
+        'e' | 'E' => {
+            let (digits, point) = digits(precision as i64 + 1);
+            (exponent_notation(&digits, point, precision, kind == 'E'), conversion.more_digits)
+        },
+        _ => {
+            //exponent notation for very small or large numbers and fixed notation otherwise
+            let precision = precision.max(1);
+            let (digits, point) = digits(precision as i64);
+            let exponent = if digits.is_empty() { 0 } else { point - 1 };
+            let text = if exponent < -4 || exponent >= precision as i64 {
+                exponent_notation(&digits, point, precision - 1, kind == 'G')
+            } else {
+                fixed_notation(&digits, point, (precision as i64 - 1 - exponent) as usize)
+            };
+            (text, !conversion.alternate)
+        },
+    };
+    if !trim_zeros {
+        return text;
+    }
+    //drop the zeros at the end of the digits after the point, and the point too unless the ! flag keeps one zero
+    let (mantissa, exponent) = text.split_at(text.find(['e', 'E']).unwrap_or(text.len()));
+    if !mantissa.contains('.') {
+        return text;
+    }
+    let mantissa = mantissa.trim_end_matches('0');
+    let mantissa = match mantissa.strip_suffix('.') {
+        Some(whole) if conversion.more_digits => format!("{}.0", whole),
+        Some(whole) => whole.to_string(),
+        None => mantissa.to_string(),
+    };
+    mantissa + exponent
+}
+
+//the digit at a position counted from the first of `digits`; there are zeros on either side of them
+fn digit_at(digits: &[u8], position: i64) -> char {
+    usize::try_from(position).ok().and_then(|position| digits.get(position)).map_or('0', |digit| *digit as char)
+}
+
+//digits with `point` of them before the decimal point and `precision` after it
+fn fixed_notation(digits: &[u8], point: i64, precision: usize) -> String {
+    let mut text: String = match point > 0 {
+        true => (0..point).map(|position| digit_at(digits, position)).collect(),
+        false => "0".to_string(),
+    };
+    if precision > 0 {
+        text.push('.');
+        text.extend((point..point + precision as i64).map(|position| digit_at(digits, position)));
+    }
+    text
+}
+
+//digits in exponent notation with `precision` of them after the point and at least two exponent digits, like 1.5e+03
+fn exponent_notation(digits: &[u8], point: i64, precision: usize, upper: bool) -> String {
+    let mut text = digit_at(digits, 0).to_string();
+    if precision > 0 {
+        text.push('.');
+        text.extend((1..=precision as i64).map(|position| digit_at(digits, position)));
+    }
+    let exponent = if digits.is_empty() { 0 } else { point - 1 };
+    let exponent_sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}{}{}{:02}", text, if upper { 'E' } else { 'e' }, exponent_sign, exponent.abs())
+}
+
+//the flags, width and precision of a %-conversion
+#[derive(Default)]
+struct Conversion {
+    left_align: bool,
+    plus_sign: bool,
+    space_sign: bool,
+    zero_pad: bool,
+    alternate: bool,
+    thousands: bool,
+    more_digits: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+//widths and precisions past this would only make absurdly long strings
+const PRINTF_MAX_WIDTH: i64 = 100_000;
+
+//printf(format, ...): the format with each %-conversion replaced by the next argument, formatted the way
+//sqlite's printf formats it; missing arguments count as NULL, which is 0 or an empty string
+pub fn printf(format: &str, args: &[Value]) -> String {
+    let mut args = args.iter();
+    let mut next_arg = || args.next().cloned().unwrap_or(Value::Null);
+    let mut output = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        let mut conversion = Conversion::default();
+        while let Some(flag) = chars.next_if(|c| "-+ 0#,!".contains(*c)) {
+            match flag {
+                '-' => conversion.left_align = true,
+                '+' => conversion.plus_sign = true,
+                ' ' => conversion.space_sign = true,
+                '0' => conversion.zero_pad = true,
+                '#' => conversion.alternate = true,
+                ',' => conversion.thousands = true,
+                _ => conversion.more_digits = true,
+            }
+        }
+        if chars.next_if_eq(&'*').is_some() {
+            let width = next_arg().to_number().as_integer();
+            conversion.left_align |= width < 0;
+            conversion.width = width.unsigned_abs().min(PRINTF_MAX_WIDTH as u64) as usize;
+        } else {
+            conversion.width = printf_number(&mut chars);
+        }
+        if chars.next_if_eq(&'.').is_some() {
+            conversion.precision = Some(if chars.next_if_eq(&'*').is_some() {
+                next_arg().to_number().as_integer().clamp(0, PRINTF_MAX_WIDTH) as usize
+            } else {
+                printf_number(&mut chars)
+            });
+        }
+        //the size modifiers of C's printf change nothing here
+        while chars.next_if(|c| *c == 'l').is_some() {}
+        let Some(kind) = chars.next() else { break };
+
+        let text = match kind {
+            '%' => "%".to_string(),
+            'd' | 'i' => {
+                let n = next_arg().to_number().as_integer();
+                let mut digits = n.unsigned_abs().to_string();
+                if let Some(precision) = conversion.precision {
+                    digits = format!("{:0>width$}", digits, width = precision);
+                }
+                if conversion.thousands {
+                    digits = thousands(&digits);
+                }
+                pad_number(&conversion, sign(&conversion, n < 0), "", &digits)
+            },
+            'u' | 'x' | 'X' | 'o' => {
+                //negative numbers are read as their two's complement
+                let n = next_arg().to_number().as_integer() as u64;
+                let (digits, prefix) = match kind {
+                    'x' => (format!("{:x}", n), "0x"),
+                    'X' => (format!("{:X}", n), "0X"),
+                    'o' => (format!("{:o}", n), "0"),
+                    _ => (n.to_string(), ""),
+                };
+                let digits = match conversion.precision {
+                    Some(precision) => format!("{:0>width$}", digits, width = precision),
+                    None => digits,
+                };
+                let prefix = if conversion.alternate && n != 0 { prefix } else { "" };
+                pad_number(&conversion, "", prefix, &digits)
+            },
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let n = next_arg().to_number().as_real();
+                let precision = conversion.precision.unwrap_or(6);
+                let digits = if n.is_nan() {
+                    "NaN".to_string()
+                } else if n.is_infinite() {
+                    "Inf".to_string()
+                } else {
+                    real_conversion(kind, n.abs(), precision, &conversion)
+                };
+                let negative = n.is_sign_negative() && (n != 0.0 || digits.bytes().any(|b| (b'1'..=b'9').contains(&b)));
+                pad_number(&conversion, sign(&conversion, negative), "", &digits)
+            },
+            's' | 'z' | 'q' | 'Q' | 'w' => {
+                let arg = next_arg();
+                let text = match (kind, &arg) {
+                    ('Q', Value::Null) => "NULL".to_string(),
+                    (_, Value::Null) => String::new(),
+                    ('q', arg) => arg.to_text().replace('\'', "''"),
+                    ('Q', arg) => format!("'{}'", arg.to_text().replace('\'', "''")),
+                    ('w', arg) => arg.to_text().replace('"', "\"\""),
+                    (_, arg) => arg.to_text(),
+                };
+                let text = match conversion.precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text,
+                };
+                pad(&conversion, text)
+            },
+            'c' => {
+                let c = next_arg().to_text().chars().next().map_or(String::new(), String::from);
+                pad(&conversion, c.repeat(conversion.precision.unwrap_or(1).max(1)))
+            },
+            'n' => String::new(),
+            //an unknown conversion ends the output, as it does in sqlite
+            _ => break,
+        };
+        output.push_str(&text);
+    }
+    output
+}
+
+//a width or precision written out in digits
+fn printf_number(chars: &mut Peekable<Chars>) -> usize {
+    let mut n: i64 = 0;
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        n = (n * 10 + digit.to_digit(10).unwrap_or(0) as i64).min(PRINTF_MAX_WIDTH);
+    }
+    n as usize
+}
+
+fn sign(conversion: &Conversion, negative: bool) -> &'static str {
+    match negative {
+        true => "-",
+        false if conversion.plus_sign => "+",
+        false if conversion.space_sign => " ",
+        false => "",
+    }
+}
+
+//group the digits of a whole number in threes with commas
+fn thousands(digits: &str) -> String {
+    let mut grouped = String::new();
+    for (position, digit) in digits.chars().enumerate() {
+        if position > 0 && (digits.len() - position) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+//pad a number to the width, with zeros between its sign and its digits when the 0 flag asks for them
+fn pad_number(conversion: &Conversion, sign: &str, prefix: &str, digits: &str) -> String {
+    let length = sign.len() + prefix.len() + digits.chars().count();
+    if conversion.zero_pad && !conversion.left_align && length < conversion.width {
+        return format!("{}{}{}{}", sign, prefix, "0".repeat(conversion.width - length), digits);
+    }
+    pad(conversion, format!("{}{}{}", sign, prefix, digits))
+}
+
+fn pad(conversion: &Conversion, text: String) -> String {
+    let length = text.chars().count();
+    if length >= conversion.width {
+        return text;
+    }
+    let padding = " ".repeat(conversion.width - length);
+    match conversion.left_align {
+        true => text + &padding,
+        false => padding + &text,
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_scalar_functions() {
+    let call = |name: &str, args: Vec<Value>| {
+        let arg_list = FunctionArgs::List { distinct: false, args: args.iter().map(|_| crate::ast::Expr::column("x")).collect() };
+        let function = ScalarFunction::from_call(name, &arg_list).unwrap().unwrap();
+        function.call(&args).unwrap().to_string()
+    };
+    let text = |text: &str| Value::Text(text.to_string());
+    assert_eq!(call("length", vec![Value::Real(12.0)]), "4");
+    assert_eq!(call("upper", vec![text("abé")]), "ABé");
+    assert_eq!(call("substr", vec![text("hello"), Value::Integer(-3), Value::Integer(2)]), "ll");
+    assert_eq!(call("substr", vec![text("hello"), Value::Integer(0), Value::Integer(2)]), "h");
+    assert_eq!(call("substr", vec![text("hello"), Value::Integer(3), Value::Integer(-2)]), "he");
+    assert_eq!(call("substr", vec![text("hello"), Value::Integer(2)]), "ello");
+    assert_eq!(call("substr", vec![text("hello"), Value::Integer(0)]), "hello");
+    assert_eq!(call("substr", vec![text("hello"), Value::Integer(-10)]), "hello");
+    assert_eq!(call("substr", vec![text("hello"), Value::Integer(-10), Value::Integer(7)]), "he");
+    assert_eq!(call("trim", vec![text("xxhixx"), text("x")]), "hi");
+    assert_eq!(call("rtrim", vec![text("  hi  ")]), "  hi");
+    assert_eq!(call("replace", vec![text("banana"), text("an"), text("AN")]), "bANANa");
+    assert_eq!(call("instr", vec![text("héllo"), text("l")]), "3");
+    assert_eq!(call("abs", vec![text("-5")]), "5.0");
+    assert_eq!(call("round", vec![Value::Real(-2.5)]), "-3.0");
+    assert_eq!(call("round", vec![Value::Real(1.005), Value::Integer(2)]), "1.0");
+    assert_eq!(call("coalesce", vec![Value::Null, Value::Integer(2), Value::Integer(3)]), "2");
+    assert_eq!(call("nullif", vec![Value::Integer(1), Value::Real(1.0)]), "null");
+    assert_eq!(call("typeof", vec![Value::Blob(vec![1])]), "blob");
+    assert_eq!(call("hex", vec![Value::Real(1.5)]), "312E35");
+    assert_eq!(call("quote", vec![text("it's")]), "'it''s'");
+    assert_eq!(call("quote", vec![Value::Real(0.1 + 0.2)]), "3.000000000000000445e-01");
+    assert_eq!(call("iif", vec![Value::Integer(0), text("yes"), text("no")]), "no");
+    assert_eq!(call("max", vec![text("a"), text("B")]), "a");
+    assert_eq!(call("min", vec![Value::Integer(1), Value::Null]), "null");
+    assert_eq!(call("like", vec![text("a_c%"), text("ABCdef")]), "1");
+    assert_eq!(call("like", vec![text("10!%"), text("10%"), text("!")]), "1");
+    assert_eq!(call("like", vec![text("10!%"), text("100"), text("!")]), "0");
+    assert_eq!(call("like", vec![text("é"), text("É")]), "0");
+    assert_eq!(call("glob", vec![text("[a-c]*[^0-9]"), text("b12x")]), "1");
+    assert_eq!(call("glob", vec![text("[]x]?"), text("]y")]), "1");
+    assert_eq!(call("glob", vec![text("*.TXT"), text("a.txt")]), "0");
+    assert_eq!(call("regexp", vec![text("^[0-9]+$"), text("123")]), "1");
+    //runs that can't match have to give up without trying every split of the text between them
+    let many_as = format!("{}b", "a".repeat(200));
+    assert_eq!(call("like", vec![text(&format!("{}c", "%a".repeat(12))), text(&many_as)]), "0");
+    assert_eq!(call("glob", vec![text(&format!("{}c", "*a".repeat(12))), text(&many_as)]), "0");
+    assert_eq!(call("like", vec![text(&format!("{}b", "%a".repeat(12))), text(&many_as)]), "1");
+    assert!(ScalarFunction::call(ScalarFunction::Like, &[text("a"), text("a"), text("ab")]).is_err());
+    assert!(ScalarFunction::from_call("abs", &FunctionArgs::Star).is_err());
+    assert!(ScalarFunction::call(ScalarFunction::Abs, &[Value::Integer(i64::MIN)]).is_err());
+
+    let printf = |format: &str, args: Vec<Value>| printf(format, &args);
+    assert_eq!(printf("%5.2f|%-5d|%05d|%x|%q|%Q|%s", vec![Value::Real(1.23456), Value::Integer(42), Value::Integer(42), Value::Integer(255), text("it's"), Value::Null, text("str")]),
+        " 1.23|42   |00042|ff|it''s|NULL|str");
+    assert_eq!(printf("%e|%g|%g|%g", vec![Value::Real(1234.5), Value::Real(0.0001), Value::Real(1e6), Value::Real(100000.0)]), "1.234500e+03|0.0001|1e+06|100000");
+    assert_eq!(printf("%,d|%.3s|%d|%10.4g|%#x|%+d|% d", vec![Value::Integer(1234567), text("abcdef"), text("12abc"), Value::Real(1.23456), Value::Integer(255), Value::Integer(5), Value::Integer(5)]),
+        "1,234,567|abc|12|     1.235|0xff|+5| 5");
+    assert_eq!(printf("%.20f", vec![Value::Real(0.1)]), "0.10000000000000000000");
+    assert_eq!(printf("%d%%", vec![]), "0%");
+}
@@ -0,0 +1,491 @@
+use anyhow::{bail, Result};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use crate::value::{allocation_size, Collation, Value};
+
+//how much row data a sort keeps in memory before writing a sorted run out to a temporary file
+pub const SORT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+//the most runs merged at once, which keeps the number of open files down
+const MAX_MERGE_WIDTH: usize = 64;
+
+//how to order the rows by one of their sort keys
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub descending: bool,
+    pub nulls_first: bool,
+    pub collation: Collation,
+}
+
+//a row waiting to be sorted: the values of the ORDER BY terms, then the values to output
+#[derive(Debug, Clone)]
+pub struct SortRow {
+    pub keys: Vec<Value>,
+    pub values: Vec<Value>,
+}
+
+impl SortRow {
+    //rough size in memory, used to decide when to spill: the row, the allocations of its lists of values at their
+    //capacity, and what the values hold outside them
+    fn size(&self) -> usize {
+        let lists = [self.keys.capacity(), self.values.capacity()].map(|capacity| allocation_size(capacity * std::mem::size_of::<Value>()));
+        std::mem::size_of::<SortRow>() + lists.iter().sum::<usize>() + self.keys.iter().chain(&self.values).map(Value::heap_size).sum::<usize>()
+    }
+}
+
+pub fn compare_rows(keys: &[SortKey], a: &SortRow, b: &SortRow) -> Ordering {
+    compare_keys(keys, &a.keys, &b.keys)
+}
+
+//how two lists of sort key values compare, key by key
+pub fn compare_keys(keys: &[SortKey], a: &[Value], b: &[Value]) -> Ordering {
+    for (key, (a, b)) in keys.iter().zip(a.iter().zip(b)) {
+        let ordering = match (a.is_null(), b.is_null()) {
+            (true, true) => Ordering::Equal,
+            //NULLS FIRST and NULLS LAST hold whichever direction the rest sorts in
+            (true, false) => return if key.nulls_first { Ordering::Less } else { Ordering::Greater },
+            (false, true) => return if key.nulls_first { Ordering::Greater } else { Ordering::Less },
+            (false, false) if key.descending => a.compare_collated(b, key.collation).reverse(),
+            (false, false) => a.compare_collated(b, key.collation),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+//a row in a heap, ordered by its sort keys and then by when it went in
+struct HeapRow {
+    keys: Rc<[SortKey]>,
+    sequence: u64,
+    row: SortRow,
+}
+
+impl Ord for HeapRow {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_rows(&self.keys, &self.row, &other.row).then(self.sequence.cmp(&other.sequence))
+    }
+}
+
+impl PartialOrd for HeapRow {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapRow {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapRow {}
+
+//rows taken out in the order of their sort keys, and those that sort the same in the order they went in
+//(with no keys, first in first out)
+pub struct SortQueue {
+    keys: Rc<[SortKey]>,
+    heap: BinaryHeap<Reverse<HeapRow>>,
+    pushed: u64,
+}
+
+impl SortQueue {
+    pub fn new(keys: Vec<SortKey>) -> Self {
+        SortQueue { keys: keys.into(), heap: BinaryHeap::new(), pushed: 0 }
+    }
+
+    pub fn push(&mut self, row: SortRow) {
+        self.heap.push(Reverse(HeapRow { keys: self.keys.clone(), sequence: self.pushed, row }));
+        self.pushed += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<SortRow> {
+        self.heap.pop().map(|Reverse(row)| row.row)
+    }
+}
+
+//an external merge sort: rows are sorted in memory until they outgrow the budget, at which point
+//they're written out as a sorted run, and the runs are merged back together at the end.
+//Rows that compare equal come out in the order they went in
+pub struct Sorter {
+    keys: Rc<[SortKey]>,
+    memory_budget: usize,
+    rows: Vec<SortRow>,
+    memory_used: usize,
+    runs: Vec<Run>,
+    //when only the first rows are wanted, how many, and the first of the rows so far in a heap whose largest row
+    //makes way for a smaller one; should they outgrow the budget, the sort goes on as a full one
+    first: Option<(usize, BinaryHeap<HeapRow>)>,
+    pushed: u64,
+}
+
+impl Sorter {
+    pub fn new(keys: Vec<SortKey>, memory_budget: usize) -> Self {
+        Sorter { keys: keys.into(), memory_budget, rows: Vec::new(), memory_used: 0, runs: Vec::new(), first: None, pushed: 0 }
+    }
+
+    //keep only the first `count` rows, when that's known, for an ORDER BY with a LIMIT
+    pub fn keep_first(mut self, count: Option<u64>) -> Self {
+        self.first = count.map(|count| (usize::try_from(count).unwrap_or(usize::MAX), BinaryHeap::new()));
+        self
+    }
+
+    pub fn push(&mut self, row: SortRow) -> Result<()> {
+        let size = row.size();
+        if let Some((count, heap)) = &mut self.first {
+            let row = HeapRow { keys: self.keys.clone(), sequence: self.pushed, row };
+            self.pushed += 1;
+            if heap.len() < *count {
+                self.memory_used += size;
+                heap.push(row);
+            } else if let Some(mut largest) = heap.peek_mut() {
+                if row < *largest {
+                    self.memory_used = self.memory_used + size - largest.row.size();
+                    *largest = row;
+                }
+            }
+            if self.memory_used > self.memory_budget {
+                self.rows = std::mem::take(heap).into_sorted_vec().into_iter().map(|row| row.row).collect();
+                self.first = None;
+                self.spill()?;
+            }
+            return Ok(());
+        }
+        self.memory_used += size;
+        self.rows.push(row);
+        if self.memory_used > self.memory_budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    //sort the rows in memory and write them to a new run
+    fn spill(&mut self) -> Result<()> {
+        let keys = &self.keys;
+        self.rows.sort_by(|a, b| compare_rows(keys, a, b));
+        let mut run = Run::create()?;
+        {
+            let mut writer = BufWriter::new(&mut run.file);
+            for row in self.rows.drain(..) {
+                write_row(&mut writer, &row)?;
+            }
+            writer.flush()?;
+        }
+        self.runs.push(run);
+        self.memory_used = 0;
+        Ok(())
+    }
+
+    //the rows in sorted order
+    pub fn finish(mut self) -> Result<SortedRows> {
+        if let Some((_, heap)) = self.first {
+            return Ok(SortedRows::Memory(heap.into_sorted_vec().into_iter().map(|row| row.row).collect::<Vec<_>>().into_iter()));
+        }
+        if self.runs.is_empty() {
+            let keys = &self.keys;
+            self.rows.sort_by(|a, b| compare_rows(keys, a, b));
+            return Ok(SortedRows::Memory(self.rows.into_iter()));
+        }
+        if !self.rows.is_empty() {
+            self.spill()?;
+        }
+        //merge groups of runs into longer runs until they can all be merged at once
+        let mut runs = self.runs;
+        while runs.len() > MAX_MERGE_WIDTH {
+            let mut merged_runs = Vec::new();
+            let mut remaining = runs.into_iter();
+            loop {
+                let group: Vec<Run> = remaining.by_ref().take(MAX_MERGE_WIDTH).collect();
+                if group.is_empty() {
+                    break;
+                }
+                let mut merge = Merge::new(self.keys.clone(), group)?;
+                let mut run = Run::create()?;
+                {
+                    let mut writer = BufWriter::new(&mut run.file);
+                    while let Some(row) = merge.next_row()? {
+                        write_row(&mut writer, &row)?;
+                    }
+                    writer.flush()?;
+                }
+                merged_runs.push(run);
+            }
+            runs = merged_runs;
+        }
+        Ok(SortedRows::Merge(Merge::new(self.keys, runs)?))
+    }
+}
+
+pub enum SortedRows {
+    Memory(std::vec::IntoIter<SortRow>),
+    Merge(Merge),
+}
+
+impl Iterator for SortedRows {
+    type Item = Result<SortRow>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SortedRows::Memory(rows) => rows.next().map(Ok),
+            SortedRows::Merge(merge) => merge.next_row().transpose(),
+        }
+    }
+}
+
+//a sorted run in a temporary file, which is deleted when the run is dropped
+struct Run {
+    path: PathBuf,
+    file: File,
+}
+
+impl Run {
+    fn create() -> Result<Self> {
+        static NEXT_RUN: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("sqlite-rust-sort-{}-{}", std::process::id(), NEXT_RUN.fetch_add(1, AtomicOrdering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        let file = File::options().read(true).write(true).create_new(true).open(&path)?;
+        Ok(Run { path, file })
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+//reads the runs back from the start and repeatedly takes the smallest of their first rows;
+//ties go to the earlier run, which keeps the sort stable
+pub struct Merge {
+    keys: Rc<[SortKey]>,
+    readers: Vec<BufReader<File>>,
+    heads: Vec<Option<SortRow>>,
+    //kept so the files are deleted once the merge is done with them
+    #[allow(dead_code)]
+    runs: Vec<Run>,
+}
+
+impl Merge {
+    fn new(keys: Rc<[SortKey]>, runs: Vec<Run>) -> Result<Self> {
+        let mut readers = Vec::with_capacity(runs.len());
+        let mut heads = Vec::with_capacity(runs.len());
+        for run in &runs {
+            let mut file = run.file.try_clone()?;
+            std::io::Seek::rewind(&mut file)?;
+            let mut reader = BufReader::new(file);
+            heads.push(read_row(&mut reader)?);
+            readers.push(reader);
+        }
+        Ok(Merge { keys, readers, heads, runs })
+    }
+
+    fn next_row(&mut self) -> Result<Option<SortRow>> {
+        let mut smallest: Option<usize> = None;
+        for (index, head) in self.heads.iter().enumerate() {
+            let Some(row) = head else { continue };
+            let smaller = match smallest.and_then(|smallest| self.heads[smallest].as_ref()) {
+                Some(smallest_row) => compare_rows(&self.keys, row, smallest_row) == Ordering::Less,
+                None => true,
+            };
+            if smaller {
+                smallest = Some(index);
+            }
+        }
+        let Some(index) = smallest else { return Ok(None) };
+        let next = read_row(&mut self.readers[index])?;
+        Ok(std::mem::replace(&mut self.heads[index], next))
+    }
+}
+
+// ***SPILL FILE FORMAT***
+//a row is the number of keys and of values (u32 each) followed by the values, each a tag byte
+//and its contents: nothing for NULL, 8 big-endian bytes for numbers, and a u32 length and the bytes for text and blobs
+
+fn write_row(writer: &mut impl Write, row: &SortRow) -> Result<()> {
+    writer.write_all(&(row.keys.len() as u32).to_be_bytes())?;
+    writer.write_all(&(row.values.len() as u32).to_be_bytes())?;
+    for value in row.keys.iter().chain(&row.values) {
+        match value {
+            Value::Null => writer.write_all(&[0])?,
+            Value::Integer(n) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&n.to_be_bytes())?;
+            },
+            Value::Real(n) => {
+                writer.write_all(&[2])?;
+                writer.write_all(&n.to_be_bytes())?;
+            },
+            Value::Text(text) => {
+                writer.write_all(&[3])?;
+                writer.write_all(&(text.len() as u32).to_be_bytes())?;
+                writer.write_all(text.as_bytes())?;
+            },
+            Value::Blob(bytes) => {
+                writer.write_all(&[4])?;
+                writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+                writer.write_all(bytes)?;
+            },
+        }
+    }
+    Ok(())
+}
+
+//the next row of a run, or None at the end of it
+fn read_row(reader: &mut impl Read) -> Result<Option<SortRow>> {
+    let mut count_bytes = [0u8; 4];
+    match reader.read_exact(&mut count_bytes) {
+        Ok(()) => {},
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let key_count = u32::from_be_bytes(count_bytes) as usize;
+    reader.read_exact(&mut count_bytes)?;
+    let value_count = u32::from_be_bytes(count_bytes) as usize;
+
+    let keys = (0..key_count).map(|_| read_value(reader)).collect::<Result<Vec<_>>>()?;
+    let values = (0..value_count).map(|_| read_value(reader)).collect::<Result<Vec<_>>>()?;
+    Ok(Some(SortRow { keys, values }))
+}
+
+fn read_value(reader: &mut impl Read) -> Result<Value> {
+    let mut tag = [0u8; 1];
+    reader.read_exact(&mut tag)?;
+    let mut eight_bytes = [0u8; 8];
+    Ok(match tag[0] {
+        0 => Value::Null,
+        1 => {
+            reader.read_exact(&mut eight_bytes)?;
+            Value::Integer(i64::from_be_bytes(eight_bytes))
+        },
+        2 => {
+            reader.read_exact(&mut eight_bytes)?;
+            Value::Real(f64::from_be_bytes(eight_bytes))
+        },
+        3 => Value::Text(String::from_utf8(read_bytes(reader)?)?),
+        4 => Value::Blob(read_bytes(reader)?),
+        tag => bail!("corrupt sort run: unknown value tag {}", tag),
+    })
+}
+
+fn read_bytes(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length)?;
+    let mut bytes = vec![0u8; u32::from_be_bytes(length) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+#[test]
+fn test_sort_spills_and_merges() {
+    let keys = vec![
+        SortKey { descending: false, nulls_first: false, collation: Collation::Binary },
+        SortKey { descending: true, nulls_first: false, collation: Collation::Binary },
+    ];
+    //a budget this small makes every few rows a run of their own
+    let mut sorter = Sorter::new(keys, 512);
+    let mut expected = Vec::new();
+    for n in 0..500i64 {
+        let first = if n % 7 == 0 { Value::Null } else { Value::Integer(n % 10) };
+        let second = Value::Text(format!("row{:03}", n));
+        expected.push((first.clone(), n));
+        sorter.push(SortRow { keys: vec![first, second], values: vec![Value::Integer(n)] }).unwrap();
+    }
+    assert!(sorter.runs.len() > MAX_MERGE_WIDTH);
+
+    //ascending by the first key with NULLs last, then by the second key descending, which is the row number
+    expected.sort_by(|(a, a_n), (b, b_n)| match (a.is_null(), b.is_null()) {
+        (true, true) => b_n.cmp(a_n),
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.compare(b).then(b_n.cmp(a_n)),
+    });
+    let sorted: Vec<i64> = sorter.finish().unwrap().map(|row| row.unwrap().values[0].as_integer().unwrap()).collect();
+    assert_eq!(sorted, expected.into_iter().map(|(_, n)| n).collect::<Vec<_>>());
+}
+
+#[cfg(test)]
+#[test]
+fn test_sort_keeps_first() {
+    let keys = vec![SortKey { descending: true, nulls_first: false, collation: Collation::Binary }];
+    let row = |n: i64| SortRow { keys: vec![if n % 11 == 0 { Value::Null } else { Value::Integer(n % 13) }], values: vec![Value::Integer(n)] };
+    let sorted = |sorter: Sorter| sorter.finish().unwrap().map(|row| row.unwrap().values[0].as_integer().unwrap()).collect::<Vec<_>>();
+    let mut all = Sorter::new(keys.clone(), usize::MAX);
+    for n in 0..1000 {
+        all.push(row(n)).unwrap();
+    }
+    let all = sorted(all);
+    //the first rows, with those that sort the same in the order they went in, whether they fit the budget or not
+    for (count, budget) in [(0, usize::MAX), (25, usize::MAX), (2000, usize::MAX), (25, 1024)] {
+        let mut first = Sorter::new(keys.clone(), budget).keep_first(Some(count as u64));
+        for n in 0..1000 {
+            first.push(row(n)).unwrap();
+        }
+        assert_eq!(first.first.is_some(), budget == usize::MAX);
+        assert_eq!(sorted(first)[..count.min(1000)], all[..count.min(1000)]);
+    }
+}
+
+//counts the memory the allocations of each thread take up, as allocation_size() reckons it, so a test can see what
+//the rows it builds take up while other tests allocate on their own threads
+#[cfg(test)]
+struct CountingAllocator;
+
+#[cfg(test)]
+thread_local! {
+    static ALLOCATED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get().wrapping_add(allocation_size(layout.size()))));
+        unsafe { std::alloc::System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get().wrapping_sub(allocation_size(layout.size()))));
+        unsafe { std::alloc::System.dealloc(ptr, layout) }
+    }
+}
+
+#[cfg(test)]
+#[global_allocator]
+static COUNTING_ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[cfg(test)]
+#[test]
+fn test_sort_memory_budget() {
+    let keys = vec![SortKey { descending: false, nulls_first: true, collation: Collation::Binary }];
+    let allocated = || ALLOCATED.with(|allocated| allocated.get());
+    //rows of short text, longer text with room to spare, blobs and numbers are reckoned to take up what they allocate
+    let row = |n: usize| {
+        let mut text = String::with_capacity(40 + n % 50);
+        text.push_str(&format!("row {}", n));
+        let values = vec![Value::Text(format!("{}", n % 7)), Value::Text(text), Value::Blob(vec![0; n % 300]), Value::Integer(n as i64), Value::Null];
+        SortRow { keys: vec![Value::Text(format!("key {}", n))], values }
+    };
+    let mut sizes = 0;
+    for n in 0..2000 {
+        let before = allocated();
+        let row = row(n);
+        let bytes = allocated().wrapping_sub(before);
+        assert_eq!(row.size(), bytes + std::mem::size_of::<SortRow>());
+        sizes += row.size();
+    }
+
+    //so a sorted run is written out each time the rows held outgrow the budget
+    let budget = 64 * 1024;
+    let mut sorter = Sorter::new(keys, budget);
+    for n in 0..2000 {
+        sorter.push(row(n)).unwrap();
+    }
+    assert_eq!(sorter.runs.len(), sizes / budget);
+}
@@ -4,58 +4,1184 @@
 
 use nom::{
     branch::alt,
-    bytes::complete::{tag, tag_no_case},
-    character::complete::{alpha1, alphanumeric1, multispace0, multispace1},
-    combinator::recognize,
-    multi::{many0_count, separated_list0, separated_list1},
-    sequence::{delimited, pair},
-    IResult, Parser,
+    bytes::complete::{is_not, tag_no_case, take_until, take_while},
+    character::complete::{alpha1, alphanumeric1, char, digit1, multispace0, multispace1, satisfy},
+    combinator::{cut, map, map_res, not, opt, recognize, value, verify},
+    multi::{many0, many0_count, many1, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated},
+    Parser,
 };
 
+use crate::ast::*;
+
+// ***ERRORS***
+
+//what the parser was looking for when it failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expected {
+    Keyword(&'static str),
+    Token(&'static str),
+    //anything else, like "expression" or "end of input"
+    Description(&'static str),
+}
+
+impl std::fmt::Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expected::Keyword(word) => write!(f, "{}", word.to_ascii_uppercase()),
+            Expected::Token(token) => write!(f, "'{}'", token),
+            Expected::Description(description) => write!(f, "{}", description),
+        }
+    }
+}
+
+//the error type of the parsers below: where parsing stopped and what could have come next there
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError<I> {
+    input: I,
+    expected: Vec<Expected>,
+    //a malformed token starting at `input`, reported instead of what was expected
+    unrecognized: Option<I>,
+}
+
+impl<I> SyntaxError<I> {
+    fn expected(input: I, expected: Expected) -> Self {
+        SyntaxError { input, expected: vec![expected], unrecognized: None }
+    }
+
+    fn unrecognized(input: I, token: I) -> Self {
+        SyntaxError { input, expected: Vec::new(), unrecognized: Some(token) }
+    }
+}
+
+impl<'a> nom::error::ParseError<&'a str> for SyntaxError<&'a str> {
+    fn from_error_kind(input: &'a str, _kind: nom::error::ErrorKind) -> Self {
+        SyntaxError { input, expected: Vec::new(), unrecognized: None }
+    }
+
+    fn append(_input: &'a str, _kind: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    //of two failed alternatives, report the one that got furthest, or both if they failed at the same place
+    fn or(mut self, other: Self) -> Self {
+        match self.input.len().cmp(&other.input.len()) {
+            std::cmp::Ordering::Less => self,
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Equal => {
+                self.expected.extend(other.expected);
+                self
+            },
+        }
+    }
+}
+
+impl<'a, E> nom::error::FromExternalError<&'a str, E> for SyntaxError<&'a str> {
+    fn from_external_error(input: &'a str, kind: nom::error::ErrorKind, _error: E) -> Self {
+        nom::error::ParseError::from_error_kind(input, kind)
+    }
+}
+
+type IResult<I, O> = nom::IResult<I, O, SyntaxError<I>>;
+
+//a syntax error in a whole statement, ready to be shown to the user
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("syntax error at offset {offset}: {}\n{snippet}", self.problem())]
+pub struct ParseError {
+    //byte offset into the statement
+    pub offset: usize,
+    pub expected: String,
+    //the text of a malformed token at the offset, like x'abc'
+    pub unrecognized: Option<String>,
+    //the line the error is on, with a caret under the offending position
+    pub snippet: String,
+}
+
+impl ParseError {
+    fn new(sql: &str, error: SyntaxError<&str>) -> Self {
+        let offset = sql.len() - error.input.len();
+
+        let mut expected: Vec<String> = Vec::new();
+        for item in &error.expected {
+            let item = item.to_string();
+            if !expected.contains(&item) {
+                expected.push(item);
+            }
+        }
+        let expected = match expected.split_last() {
+            None => "valid SQL".to_string(),
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        };
+
+        let line_start = sql[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        let line_end = sql[offset..].find('\n').map_or(sql.len(), |newline| offset + newline);
+        //keep tabs so the caret lines up with the text above it
+        let padding: String = sql[line_start..offset].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let snippet = format!("{}\n{}^", &sql[line_start..line_end], padding);
+
+        let unrecognized = error.unrecognized.map(str::to_string);
+        ParseError { offset, expected, unrecognized, snippet }
+    }
+
+    fn problem(&self) -> String {
+        match &self.unrecognized {
+            Some(token) => format!("unrecognized token: \"{}\"", token),
+            None => format!("expected {}", self.expected),
+        }
+    }
+}
+
+//run `parser` over the whole of `sql`, which must leave nothing unparsed
+fn parse_complete<'a, O>(sql: &'a str, mut parser: impl Parser<&'a str, Output = O, Error = SyntaxError<&'a str>>) -> Result<O, ParseError> {
+    let error = match parser.parse(sql) {
+        Ok(("", output)) => return Ok(output),
+        Ok((remaining, _)) => SyntaxError::expected(remaining, Expected::Description("end of input")),
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => error,
+        Err(nom::Err::Incomplete(_)) => unreachable!("the parsers only work on complete input"),
+    };
+    Err(ParseError::new(sql, error))
+}
+
+//replace the error of a parser that failed without consuming anything with `label`
+fn expecting<'a, O>(
+    label: Expected,
+    mut parser: impl Parser<&'a str, Output = O, Error = SyntaxError<&'a str>>,
+) -> impl Parser<&'a str, Output = O, Error = SyntaxError<&'a str>> {
+    move |i: &'a str| match parser.parse(i) {
+        Err(nom::Err::Error(error)) if error.input.len() == i.len() => Err(nom::Err::Error(SyntaxError::expected(i, label))),
+        result => result,
+    }
+}
+
+// ***WHITESPACE AND KEYWORDS***
+
+//whitespace and comments, which can appear between any two tokens
+fn space0(i: &str) -> IResult<&str, ()> {
+    let line_comment = (tag("--"), opt(is_not("\n")));
+    let block_comment = (tag("/*"), take_until("*/"), tag("*/"));
+    value((), many0_count(alt((
+        value((), multispace1),
+        value((), line_comment),
+        value((), block_comment),
+    )))).parse(i)
+}
+
+//a token matched exactly, reported by name when it's missing
+fn tag<'a>(token: &'static str) -> impl Parser<&'a str, Output = &'a str, Error = SyntaxError<&'a str>> {
+    expecting(Expected::Token(token), nom::bytes::complete::tag(token))
+}
+
+//a keyword, which unlike tag_no_case mustn't run on into an identifier ("or" doesn't match "order")
+fn keyword<'a>(word: &'static str) -> impl Parser<&'a str, Output = &'a str, Error = SyntaxError<&'a str>> {
+    expecting(Expected::Keyword(word), terminated(tag_no_case(word), not(satisfy(|c: char| c.is_alphanumeric() || c == '_'))))
+}
+
+//several keywords in a row, e.g. ["primary", "key"]
+fn keywords<'a>(words: &'static [&'static str]) -> impl Parser<&'a str, Output = (), Error = SyntaxError<&'a str>> {
+    move |mut i: &'a str| {
+        for (position, word) in words.iter().enumerate() {
+            if position > 0 {
+                (i, _) = space0(i)?;
+            }
+            (i, _) = keyword(word).parse(i)?;
+        }
+        Ok((i, ()))
+    }
+}
+
+//a token surrounded by optional whitespace
+fn symbol<'a>(token: &'static str) -> impl Parser<&'a str, Output = &'a str, Error = SyntaxError<&'a str>> {
+    delimited(space0, tag(token), space0)
+}
+
+//get the space comma combination that appears between list items
+fn space_comma(i: &str) -> IResult<&str, &str> {
+    symbol(",").parse(i)
+}
+
+#[cfg(test)]
+#[test]
+fn test_space_comma() {
+    let input = ", ";
+    let (remaining, result) = space_comma(input).unwrap();
+    assert_eq!(remaining, "");
+    assert_eq!(result, ",");
+}
+
+// ***IDENTIFIERS AND LITERALS***
+
+//words that can't be used as bare identifiers because they'd make the grammar ambiguous
+const RESERVED: &[&str] = &[
+    "all", "and", "as", "asc", "between", "by", "case", "cast", "check", "collate", "constraint", "cross",
+    "default", "desc", "distinct", "else", "end", "escape", "except", "exists", "foreign", "from", "glob",
+    "group", "having", "in", "inner", "intersect", "is", "isnull", "join", "left", "like", "limit", "natural",
+    "not", "notnull", "null", "offset", "on", "or", "order", "outer", "primary", "references", "regexp",
+    "select", "then", "union", "unique", "using", "when", "where", "window", "with",
+];
+
+//a bare identifier (letters, digits and underscores, not starting with a digit) or a quoted one
+pub fn identifier(i: &str) -> IResult<&str, &str> {
+    let bare = verify(
+        recognize(pair(
+            alt((alpha1, tag("_"))),
+            many0_count(alt((alphanumeric1, tag("_"), tag("$")))),
+        )),
+        |word: &str| !RESERVED.iter().any(|reserved| reserved.eq_ignore_ascii_case(word)),
+    );
+    //sqlite accepts MySQL's backticks and SQL Server's brackets as well as double quotes
+    let double_quoted = delimited(tag("\""), is_not("\""), tag("\""));
+    let backticked = delimited(tag("`"), is_not("`"), tag("`"));
+    let bracketed = delimited(tag("["), is_not("]"), tag("]"));
+    expecting(Expected::Description("identifier"), alt((double_quoted, backticked, bracketed, bare))).parse(i)
+}
+
+#[cfg(test)]
+#[test]
+fn test_identifier() {
+    assert_eq!(identifier("eye_color text"), Ok((" text", "eye_color")));
+    assert_eq!(identifier("\"superheroes\" (id"), Ok((" (id", "superheroes")));
+    assert_eq!(identifier("[first name]"), Ok(("", "first name")));
+    assert!(identifier("1abc").is_err());
+    assert!(identifier("FROM apples").is_err());
+}
+
+//a single-quoted string (with '' for a quote inside it), NULL or a (possibly negative) number
+fn literal(i: &str) -> IResult<&str, Literal> {
+    let text = map(string_literal, Literal::Text);
+    let null = value(Literal::Null, keyword("null"));
+    alt((blob, text, null, number)).parse(i)
+}
+
+//x'...': a blob written as an even number of hex digits
+fn blob(i: &str) -> IResult<&str, Literal> {
+    let (rest, _) = (alt((tag("x"), tag("X"))), tag("'")).parse(i)?;
+    //past x' this can only be a blob, so anything else is a malformed token rather than a column named x
+    cut(blob_digits).parse(rest).map_err(|error| error.map(|_| {
+        //the token runs to its closing quote, or to the end of the line without one
+        let length = match rest.find(['\'', '\n']) {
+            Some(position) if rest[position..].starts_with('\'') => position + 1,
+            Some(position) => position,
+            None => rest.len(),
+        };
+        SyntaxError::unrecognized(i, &i[..i.len() - rest.len() + length])
+    }))
+}
+
+fn blob_digits(i: &str) -> IResult<&str, Literal> {
+    let digits = terminated(take_while(|c: char| c.is_ascii_hexdigit()), tag("'"));
+    map(verify(digits, |digits: &str| digits.len() % 2 == 0), |digits: &str| {
+        Literal::Blob((0..digits.len()).step_by(2).map(|position| u8::from_str_radix(&digits[position..position + 2], 16).unwrap_or(0)).collect())
+    }).parse(i)
+}
+
+fn string_literal(i: &str) -> IResult<&str, String> {
+    map(
+        delimited(tag("'"), many0(alt((is_not("'"), value("'", tag("''"))))), tag("'")),
+        |parts: Vec<&str>| parts.concat(),
+    ).parse(i)
+}
+
+fn number(i: &str) -> IResult<&str, Literal> {
+    //opt(digit1) rather than digit0, which loses the digits it matched when used inside recognize
+    let mantissa = alt((recognize((digit1, opt((tag("."), opt(digit1))))), recognize((tag("."), digit1))));
+    let exponent = (alt((tag("e"), tag("E"))), opt(alt((tag("+"), tag("-")))), digit1);
+    let hex = map_res(
+        preceded((opt(tag("-")), alt((tag("0x"), tag("0X")))), recognize(many1(satisfy(|c: char| c.is_ascii_hexdigit())))),
+        |digits: &str| u64::from_str_radix(digits, 16).map(|n| Literal::Integer(n as i64)),
+    );
+    let decimal = map(
+        terminated(recognize((opt(tag("-")), mantissa, opt(exponent))), not(satisfy(|c: char| c.is_alphanumeric() || c == '_'))),
+        |number: &str| {
+            //integers too big for an i64 are read as reals, like sqlite does
+            let is_integer = !number.contains(['.', 'e', 'E']);
+            match number.parse() {
+                std::result::Result::Ok(n) if is_integer => Literal::Integer(n),
+                _ => Literal::Real(number.parse().unwrap_or(f64::INFINITY)),
+            }
+        },
+    );
+    alt((hex, decimal)).parse(i)
+}
+
+#[cfg(test)]
+#[test]
+fn test_literal() {
+    assert_eq!(literal("'Yellow'"), Ok(("", Literal::Text("Yellow".into()))));
+    assert_eq!(literal("''"), Ok(("", Literal::Text("".into()))));
+    assert_eq!(literal("'O''Brien'"), Ok(("", Literal::Text("O'Brien".into()))));
+    assert_eq!(literal("-42"), Ok(("", Literal::Integer(-42))));
+    assert_eq!(literal("1.5"), Ok(("", Literal::Real(1.5))));
+    assert_eq!(literal("2e3"), Ok(("", Literal::Real(2000.0))));
+    assert_eq!(literal("0x1F"), Ok(("", Literal::Integer(31))));
+    assert_eq!(literal("NULL"), Ok(("", Literal::Null)));
+    assert_eq!(literal("x'0aFf'"), Ok(("", Literal::Blob(vec![0x0a, 0xff]))));
+    assert_eq!(literal("X''"), Ok(("", Literal::Blob(vec![]))));
+    assert!(matches!(literal("x'abc'"), Err(nom::Err::Failure(SyntaxError { unrecognized: Some("x'abc'"), .. }))));
+    assert!(matches!(literal("x'0g'"), Err(nom::Err::Failure(SyntaxError { unrecognized: Some("x'0g'"), .. }))));
+    assert!(literal("nullable").is_err());
+}
+
+// ***EXPRESSIONS***
+
+//precedence from loosest to tightest: OR, AND, NOT, equality (and IS), relational comparisons,
+//bitwise operators, + and -, * / and %, ||, COLLATE, unary operators, operands
+pub fn expr(i: &str) -> IResult<&str, Expr> {
+    expecting(Expected::Description("expression"), or_expr).parse(i)
+}
+
+fn binary(left: Expr, operator: BinaryOperator, right: Expr) -> Expr {
+    Expr::Binary(Box::new(left), operator, Box::new(right))
+}
+
+//a left-associative chain of `operand`s joined by `operator`s; an operator must be followed by an operand
+fn left_assoc<'a>(
+    i: &'a str,
+    operand: fn(&'a str) -> IResult<&'a str, Expr>,
+    operator: impl Parser<&'a str, Output = BinaryOperator, Error = SyntaxError<&'a str>>,
+) -> IResult<&'a str, Expr> {
+    let (remaining, (first, rest)) = (
+        operand,
+        many0((delimited(space0, operator, space0), cut(operand))),
+    ).parse(i)?;
+    Ok((remaining, rest.into_iter().fold(first, |left, (operator, right)| binary(left, operator, right))))
+}
+
+fn or_expr(i: &str) -> IResult<&str, Expr> {
+    left_assoc(i, and_expr, value(BinaryOperator::Or, keyword("or")))
+}
+
+fn and_expr(i: &str) -> IResult<&str, Expr> {
+    left_assoc(i, not_expr, value(BinaryOperator::And, keyword("and")))
+}
+
+fn not_expr(i: &str) -> IResult<&str, Expr> {
+    alt((
+        map(preceded((keyword("not"), space0), not_expr), |inner| Expr::Unary(UnaryOperator::Not, Box::new(inner))),
+        equality,
+    )).parse(i)
+}
+
+//=, !=, IS [NOT], the NULL tests, [NOT] LIKE, GLOB and REGEXP, [NOT] BETWEEN and [NOT] IN, which all share a level
+fn equality(i: &str) -> IResult<&str, Expr> {
+    enum Suffix {
+        Compare(BinaryOperator, Expr),
+        IsNull(bool),
+        Pattern { negated: bool, function: &'static str, pattern: Expr, escape: Option<Expr> },
+        Between { negated: bool, low: Expr, high: Expr },
+        In { negated: bool, list: InList },
+    }
+    let operator = alt((
+        value(BinaryOperator::NotEqual, tag("<>")),
+        value(BinaryOperator::NotEqual, tag("!=")),
+        value(BinaryOperator::Equal, tag("==")),
+        value(BinaryOperator::Equal, tag("=")),
+        value(BinaryOperator::IsNot, keywords(&["is", "not"])),
+        value(BinaryOperator::Is, keyword("is")),
+    ));
+    let null_test = alt((
+        value(false, keyword("isnull")),
+        value(true, keyword("notnull")),
+        value(true, keywords(&["not", "null"])),
+    ));
+    let negated = || map(opt(terminated(keyword("not"), space0)), |not| not.is_some());
+    //x LIKE y is the function call like(y, x), and the same goes for GLOB and REGEXP
+    let pattern_function = alt((value("like", keyword("like")), value("glob", keyword("glob")), value("regexp", keyword("regexp"))));
+    let pattern = map(
+        (negated(), pattern_function, space0, cut(relational), opt(preceded((space0, keyword("escape"), space0), cut(relational)))),
+        |(negated, function, _, pattern, escape)| Suffix::Pattern { negated, function, pattern, escape },
+    );
+    let between = map(
+        (negated(), keyword("between"), space0, cut((relational, space0, keyword("and"), space0, relational))),
+        |(negated, _, _, (low, _, _, _, high))| Suffix::Between { negated, low, high },
+    );
+    let in_list = alt((
+        map(select, |select| InList::Select(Box::new(select))),
+        map(separated_list0(space_comma, expr), InList::Values),
+    ));
+    let in_test = map(
+        (negated(), keyword("in"), space0, cut(delimited((tag("("), space0), in_list, (space0, tag(")"))))),
+        |(negated, _, _, list)| Suffix::In { negated, list },
+    );
+    let (remaining, (first, suffixes)) = (
+        relational,
+        many0(preceded(space0, alt((
+            map(null_test, Suffix::IsNull),
+            map((operator, space0, cut(relational)), |(operator, _, right)| Suffix::Compare(operator, right)),
+            pattern,
+            between,
+            in_test,
+        )))),
+    ).parse(i)?;
+    let not = |negated: bool, expr: Expr| if negated { Expr::Unary(UnaryOperator::Not, Box::new(expr)) } else { expr };
+    let equality = suffixes.into_iter().fold(first, |left, suffix| match suffix {
+        //x IS NULL is the same as x ISNULL
+        Suffix::Compare(BinaryOperator::Is, Expr::Literal(Literal::Null)) => Expr::IsNull { expr: Box::new(left), negated: false },
+        Suffix::Compare(BinaryOperator::IsNot, Expr::Literal(Literal::Null)) => Expr::IsNull { expr: Box::new(left), negated: true },
+        Suffix::Compare(operator, right) => binary(left, operator, right),
+        Suffix::IsNull(negated) => Expr::IsNull { expr: Box::new(left), negated },
+        Suffix::Pattern { negated, function, pattern, escape } => {
+            let args = [pattern, left].into_iter().chain(escape).collect();
+            not(negated, Expr::Function { name: function.to_string(), args: FunctionArgs::List { distinct: false, args } })
+        },
+        //x BETWEEN y AND z is x >= y AND x <= z
+        Suffix::Between { negated, low, high } => {
+            let between = binary(binary(left.clone(), BinaryOperator::GreaterEqual, low), BinaryOperator::And, binary(left, BinaryOperator::LessEqual, high));
+            not(negated, between)
+        },
+        Suffix::In { negated, list } => Expr::In { expr: Box::new(left), list, negated },
+    });
+    Ok((remaining, equality))
+}
+
+fn relational(i: &str) -> IResult<&str, Expr> {
+    //longer operators first so "<=" isn't read as "<", and "<<" is left to the bitwise level
+    left_assoc(i, bitwise, alt((
+        value(BinaryOperator::LessEqual, tag("<=")),
+        value(BinaryOperator::GreaterEqual, tag(">=")),
+        value(BinaryOperator::Less, terminated(tag("<"), not(alt((tag("<"), tag(">")))))),
+        value(BinaryOperator::Greater, terminated(tag(">"), not(tag(">")))),
+    )))
+}
+
+fn bitwise(i: &str) -> IResult<&str, Expr> {
+    left_assoc(i, additive, alt((
+        value(BinaryOperator::ShiftLeft, tag("<<")),
+        value(BinaryOperator::ShiftRight, tag(">>")),
+        value(BinaryOperator::BitAnd, tag("&")),
+        value(BinaryOperator::BitOr, terminated(tag("|"), not(tag("|")))),
+    )))
+}
+
+fn additive(i: &str) -> IResult<&str, Expr> {
+    left_assoc(i, multiplicative, alt((
+        value(BinaryOperator::Add, tag("+")),
+        value(BinaryOperator::Subtract, tag("-")),
+    )))
+}
+
+fn multiplicative(i: &str) -> IResult<&str, Expr> {
+    left_assoc(i, concatenation, alt((
+        value(BinaryOperator::Multiply, tag("*")),
+        value(BinaryOperator::Divide, tag("/")),
+        value(BinaryOperator::Modulo, tag("%")),
+    )))
+}
+
+fn concatenation(i: &str) -> IResult<&str, Expr> {
+    left_assoc(i, collate, value(BinaryOperator::Concat, tag("||")))
+}
+
+//expr COLLATE name, which can be repeated (the last one wins)
+fn collate(i: &str) -> IResult<&str, Expr> {
+    let (remaining, (first, collations)) = (
+        unary,
+        many0(preceded((space0, keyword("collate"), space0), cut(identifier))),
+    ).parse(i)?;
+    let collate = collations.into_iter().fold(first, |expr, collation| Expr::Collate { expr: Box::new(expr), collation: collation.to_string() });
+    Ok((remaining, collate))
+}
+
+fn unary(i: &str) -> IResult<&str, Expr> {
+    let operator = alt((
+        value(UnaryOperator::Negate, tag("-")),
+        value(UnaryOperator::Plus, tag("+")),
+        value(UnaryOperator::BitNot, tag("~")),
+    ));
+    expecting(Expected::Description("expression"), alt((
+        //negative numbers are literals rather than negated ones
+        map(literal, Expr::Literal),
+        map((operator, space0, unary), |(operator, _, operand)| Expr::Unary(operator, Box::new(operand))),
+        operand,
+    ))).parse(i)
+}
+
+//a subquery, a parenthesised expression, CASE, CAST, a function call or a (possibly qualified) column name
+fn operand(i: &str) -> IResult<&str, Expr> {
+    let subquery = || delimited((tag("("), space0), select, (space0, tag(")")));
+    alt((
+        map(subquery(), |select| Expr::Subquery(Box::new(select))),
+        map(preceded((keyword("exists"), space0), cut(subquery())), |select| Expr::Exists(Box::new(select))),
+        delimited((tag("("), space0), expr, (space0, tag(")"))),
+        case_expr,
+        cast_expr,
+        function_call,
+        column_ref,
+    )).parse(i)
+}
+
+fn column_ref(i: &str) -> IResult<&str, Expr> {
+    let (remaining, (first, second)) = (identifier, opt(preceded(symbol("."), identifier))).parse(i)?;
+    let column = match second {
+        Some(name) => Expr::Column { table: Some(first.to_string()), name: name.to_string() },
+        None => Expr::column(first),
+    };
+    Ok((remaining, column))
+}
+
+fn function_call(i: &str) -> IResult<&str, Expr> {
+    let star = value(FunctionArgs::Star, symbol("*"));
+    let list = map(
+        (opt(terminated(keyword("distinct"), space0)), separated_list0(space_comma, expr)),
+        |(distinct, args)| FunctionArgs::List { distinct: distinct.is_some(), args },
+    );
+    //the functions behind LIKE, GLOB and REGEXP can be called by name too
+    let name = alt((identifier, keyword("like"), keyword("glob"), keyword("regexp")));
+    let over = alt((
+        map(delimited(tag("("), window_definition, tag(")")), Over::Window),
+        map(identifier, |name| Over::Name(name.to_string())),
+    ));
+    let (remaining, (name, _, args, _, over)) = (
+        name,
+        (space0, tag("("), space0),
+        alt((star, list)),
+        (space0, tag(")")),
+        opt(preceded((space0, keyword("over"), space0), cut(over))),
+    ).parse(i)?;
+    let name = name.to_ascii_lowercase();
+    let call = match over {
+        Some(over) => Expr::WindowFunction { name, args, over: Box::new(over) },
+        None => Expr::Function { name, args },
+    };
+    Ok((remaining, call))
+}
+
+//the inside of the parentheses after OVER or WINDOW name AS
+fn window_definition(i: &str) -> IResult<&str, WindowDefinition> {
+    //the name of the window it extends, which can't be one of the keywords that may come first instead
+    let base = verify(identifier, |name: &str| !["partition", "rows", "range", "groups"].iter().any(|word| word.eq_ignore_ascii_case(name)));
+    let (remaining, (_, base, partition_by, order_by, frame, _)) = (
+        space0,
+        opt(terminated(base, space0)),
+        opt(terminated(preceded((keywords(&["partition", "by"]), space0), cut(separated_list1(space_comma, expr))), space0)),
+        opt(terminated(preceded((keywords(&["order", "by"]), space0), cut(separated_list1(space_comma, ordering_term))), space0)),
+        opt(terminated(frame, space0)),
+        space0,
+    ).parse(i)?;
+    Ok((remaining, WindowDefinition {
+        base: base.map(str::to_string),
+        partition_by: partition_by.unwrap_or_default(),
+        order_by: order_by.unwrap_or_default(),
+        frame,
+    }))
+}
+
+//ROWS, RANGE or GROUPS with BETWEEN start AND end, or just the start, in which case the frame ends at the current row
+fn frame(i: &str) -> IResult<&str, Frame> {
+    let units = alt((
+        value(FrameUnits::Rows, keyword("rows")),
+        value(FrameUnits::Range, keyword("range")),
+        value(FrameUnits::Groups, keyword("groups")),
+    ));
+    //UNBOUNDED would otherwise be read as a column name
+    let offset_bound = || map(
+        (preceded(not(keyword("unbounded")), expr), space0, alt((value(true, keyword("preceding")), value(false, keyword("following"))))),
+        |(offset, _, preceding)| if preceding { FrameBound::Preceding(offset) } else { FrameBound::Following(offset) },
+    );
+    //a frame can't start at UNBOUNDED FOLLOWING or end at UNBOUNDED PRECEDING
+    let start = || alt((
+        value(FrameBound::UnboundedPreceding, keywords(&["unbounded", "preceding"])),
+        value(FrameBound::CurrentRow, keywords(&["current", "row"])),
+        offset_bound(),
+    ));
+    let end = alt((
+        value(FrameBound::UnboundedFollowing, keywords(&["unbounded", "following"])),
+        value(FrameBound::CurrentRow, keywords(&["current", "row"])),
+        offset_bound(),
+    ));
+    let bounds = alt((
+        map(
+            preceded((keyword("between"), space0), cut((start(), space0, keyword("and"), space0, end))),
+            |(start, _, _, _, end)| (start, end),
+        ),
+        map(start(), |start| (start, FrameBound::CurrentRow)),
+    ));
+    map((units, space0, cut(bounds)), |(units, _, (start, end))| Frame { units, start, end }).parse(i)
+}
+
+fn case_expr(i: &str) -> IResult<&str, Expr> {
+    let when_then = (
+        preceded((space0, keyword("when"), space0), expr),
+        preceded((space0, keyword("then"), space0), expr),
+    );
+    let (remaining, (_, operand, when_then, else_expr, _)) = (
+        keyword("case"),
+        opt(preceded(space0, expr)),
+        many1(when_then),
+        opt(preceded((space0, keyword("else"), space0), expr)),
+        (space0, keyword("end")),
+    ).parse(i)?;
+    Ok((remaining, Expr::Case { operand: operand.map(Box::new), when_then, else_expr: else_expr.map(Box::new) }))
+}
+
+fn cast_expr(i: &str) -> IResult<&str, Expr> {
+    let (remaining, (_, _, expr, _, type_name, _)) = (
+        keyword("cast"),
+        symbol("("),
+        expr,
+        (space0, keyword("as"), space0),
+        type_name,
+        symbol(")"),
+    ).parse(i)?;
+    Ok((remaining, Expr::Cast { expr: Box::new(expr), type_name: type_name.to_string() }))
+}
+
+#[cfg(test)]
+#[test]
+fn test_expr() {
+    let column = |name| Box::new(Expr::column(name));
+    let literal = |value| Box::new(Expr::Literal(value));
+    let (remaining, result) = expr("color = 'Red' OR NOT (size >= 3 AND name IS NOT NULL) ORDER BY x").unwrap();
+    assert_eq!(remaining, " ORDER BY x");
+    assert_eq!(result, Expr::Binary(
+        Box::new(Expr::Binary(column("color"), BinaryOperator::Equal, literal(Literal::Text("Red".into())))),
+        BinaryOperator::Or,
+        Box::new(Expr::Unary(UnaryOperator::Not, Box::new(Expr::Binary(
+            Box::new(Expr::Binary(column("size"), BinaryOperator::GreaterEqual, literal(Literal::Integer(3)))),
+            BinaryOperator::And,
+            Box::new(Expr::IsNull { expr: column("name"), negated: true }),
+        )))),
+    ));
+    //AND binds tighter than OR
+    let (_, result) = expr("a<>1 or b<2 and c>3").unwrap();
+    assert!(matches!(result, Expr::Binary(_, BinaryOperator::Or, _)));
+    //* binds tighter than +, and || tighter than both
+    let (_, result) = expr("1 + t.x * 2 || 'a'").unwrap();
+    assert_eq!(result, Expr::Binary(
+        literal(Literal::Integer(1)),
+        BinaryOperator::Add,
+        Box::new(Expr::Binary(
+            Box::new(Expr::Column { table: Some("t".into()), name: "x".into() }),
+            BinaryOperator::Multiply,
+            Box::new(Expr::Binary(literal(Literal::Integer(2)), BinaryOperator::Concat, literal(Literal::Text("a".into())))),
+        )),
+    ));
+    let (_, result) = expr("a - -1").unwrap();
+    assert_eq!(result, Expr::Binary(column("a"), BinaryOperator::Subtract, literal(Literal::Integer(-1))));
+}
+
+#[cfg(test)]
+#[test]
+fn test_expr_functions_case_cast() {
+    let (remaining, result) = expr("count(DISTINCT name)").unwrap();
+    assert_eq!(remaining, "");
+    assert_eq!(result, Expr::Function { name: "count".into(), args: FunctionArgs::List { distinct: true, args: vec![Expr::column("name")] } });
+    let (_, result) = expr("COUNT(*)").unwrap();
+    assert_eq!(result, Expr::Function { name: "count".into(), args: FunctionArgs::Star });
+    let (_, result) = expr("CASE WHEN x > 1 THEN 'big' ELSE 'small' END").unwrap();
+    assert_eq!(result, Expr::Case {
+        operand: None,
+        when_then: vec![(
+            Expr::Binary(Box::new(Expr::column("x")), BinaryOperator::Greater, Box::new(Expr::Literal(Literal::Integer(1)))),
+            Expr::Literal(Literal::Text("big".into())),
+        )],
+        else_expr: Some(Box::new(Expr::Literal(Literal::Text("small".into())))),
+    });
+    let (_, result) = expr("x NOT LIKE 'a%' ESCAPE '!'").unwrap();
+    assert_eq!(result, Expr::Unary(UnaryOperator::Not, Box::new(Expr::Function {
+        name: "like".into(),
+        args: FunctionArgs::List { distinct: false, args: vec![
+            Expr::Literal(Literal::Text("a%".into())),
+            Expr::column("x"),
+            Expr::Literal(Literal::Text("!".into())),
+        ] },
+    })));
+    let (_, result) = expr("x BETWEEN 1 AND 2 AND y IN (3) AND z NOT IN (SELECT a FROM t)").unwrap();
+    let Expr::Binary(left, BinaryOperator::And, z_in) = result else { panic!("expected AND") };
+    assert!(matches!(*z_in, Expr::In { list: InList::Select(_), negated: true, .. }));
+    let Expr::Binary(between, BinaryOperator::And, y_in) = *left else { panic!("expected AND") };
+    assert_eq!(*y_in, Expr::In { expr: Box::new(Expr::column("y")), list: InList::Values(vec![Expr::Literal(Literal::Integer(3))]), negated: false });
+    assert_eq!(*between, Expr::Binary(
+        Box::new(Expr::Binary(Box::new(Expr::column("x")), BinaryOperator::GreaterEqual, Box::new(Expr::Literal(Literal::Integer(1))))),
+        BinaryOperator::And,
+        Box::new(Expr::Binary(Box::new(Expr::column("x")), BinaryOperator::LessEqual, Box::new(Expr::Literal(Literal::Integer(2))))),
+    ));
+    assert!(expr("x IN ()").is_ok());
+    assert!(expr("x BETWEEN 1").is_err());
+    let (_, result) = expr("cast(x as varchar(10))").unwrap();
+    assert_eq!(result, Expr::Cast { expr: Box::new(Expr::column("x")), type_name: "varchar(10)".into() });
+}
+
 // ***SELECT***
 
     // "SELECT COUNT(*) FROM apples"
     // "SELECT name FROM apples"
     // "SELECT name, color FROM apples"
+    // "SELECT name, color FROM apples WHERE color = 'Yellow'"
+
+pub fn select(i:&str) -> IResult<&str, SelectStatement> {
+    let compound_operator = alt((
+        value(CompoundOperator::UnionAll, keywords(&["union", "all"])),
+        value(CompoundOperator::Union, keyword("union")),
+        value(CompoundOperator::Intersect, keyword("intersect")),
+        value(CompoundOperator::Except, keyword("except")),
+    ));
+    let compound_select = map(
+        (space0, compound_operator, space0, cut(select_core)),
+        |(_, operator, _, select)| CompoundSelect { operator, select },
+    );
+    let (remaining, (_, with, first, compound, order_by, limit, _)) = (
+        space0,
+        opt(with_clause),
+        select_core,
+        many0(compound_select),
+        opt(preceded((space0, keywords(&["order", "by"]), space0), cut(separated_list1(space_comma, ordering_term)))),
+        opt(preceded((space0, keyword("limit"), space0), cut(limit))),
+        (space0, opt(tag(";")), space0),
+    ).parse(i)?;
+
+    let select = SelectStatement {
+        with: with.unwrap_or_default(),
+        compound,
+        order_by: order_by.unwrap_or_default(),
+        limit,
+        ..first
+    };
+    Ok((remaining, select))
+}
+
+//a SELECT up to its ORDER BY, which is one of the SELECTs of a compound SELECT
+fn select_core(i: &str) -> IResult<&str, SelectStatement> {
+    //once SELECT has matched, a failure anywhere later is an error in this statement rather than a cue to backtrack
+    let window = map(
+        (identifier, space0, keyword("as"), space0, delimited(tag("("), window_definition, tag(")"))),
+        |(name, _, _, _, window)| (name.to_string(), window),
+    );
+    let (remaining, (_, (_, distinct, columns, from, where_clause, group_by, having, windows))) = (
+        keyword("select"),
+        cut((
+            space0,
+            //ALL is the default
+            opt(terminated(alt((value(true, keyword("distinct")), value(false, keyword("all")))), space0)),
+            selection,
+            opt(preceded((space0, keyword("from"), space0), cut(from_clause))),
+            opt(preceded((space0, keyword("where"), space0), cut(expr))),
+            opt(preceded((space0, keywords(&["group", "by"]), space0), cut(separated_list1(space_comma, expr)))),
+            opt(preceded((space0, keyword("having"), space0), cut(expr))),
+            opt(preceded((space0, keyword("window"), space0), cut(separated_list1(space_comma, window)))),
+        )),
+    ).parse(i)?;
 
-pub fn select(i:&str) -> nom::IResult<&str, (&str, Vec<&str>)> {
-    let (remaining, (_, _, selected, _, _, _, table_name)) = (
-        tag_no_case("select"), 
-        multispace1,
-        selection,
-        multispace1,
-        tag_no_case("from"),
-        multispace1,
-        alphanumeric1
-    ).parse(i).unwrap();
+    let select = SelectStatement {
+        with: Vec::new(),
+        distinct: distinct.unwrap_or(false),
+        columns,
+        from,
+        where_clause,
+        group_by: group_by.unwrap_or_default(),
+        having,
+        windows: windows.unwrap_or_default(),
+        compound: Vec::new(),
+        order_by: Vec::new(),
+        limit: None,
+    };
+    Ok((remaining, select))
+}
+
+//WITH [RECURSIVE] and the common table expressions before a SELECT; like sqlite, a table can read itself
+//without RECURSIVE
+fn with_clause(i: &str) -> IResult<&str, Vec<CommonTableExpression>> {
+    let column_names = delimited(symbol("("), separated_list1(space_comma, map(identifier, str::to_string)), symbol(")"));
+    let common_table_expression = map(
+        (
+            expecting(Expected::Description("table name"), identifier),
+            opt(preceded(space0, column_names)),
+            (space0, keyword("as"), space0),
+            //the hints on whether to materialize the table change nothing here
+            opt((opt(terminated(keyword("not"), space0)), keyword("materialized"), space0)),
+            delimited((tag("("), space0), select, (space0, tag(")"))),
+        ),
+        |(name, columns, _, _, select)| CommonTableExpression {
+            name: name.to_string(),
+            columns: columns.unwrap_or_default(),
+            select: Box::new(select),
+        },
+    );
+    delimited(
+        (keyword("with"), space0, opt(terminated(keyword("recursive"), space0))),
+        cut(separated_list1(space_comma, common_table_expression)),
+        space0,
+    ).parse(i)
+}
+
+//the tables after FROM and how they are joined
+fn from_clause(i: &str) -> IResult<&str, FromClause> {
+    let join_operator = alt((
+        value(JoinOperator::Inner, space_comma),
+        value(JoinOperator::Left, (space0, keyword("left"), space0, opt((keyword("outer"), space0)), keyword("join"))),
+        value(JoinOperator::Inner, (space0, alt((keyword("inner"), keyword("cross"))), space0, keyword("join"))),
+        value(JoinOperator::Inner, (space0, keyword("join"))),
+    ));
+    let join_constraint = alt((
+        map(preceded((space0, keyword("on"), space0), cut(expr)), JoinConstraint::On),
+        map(
+            preceded((space0, keyword("using"), space0), cut(delimited(symbol("("), separated_list1(space_comma, map(identifier, str::to_string)), symbol(")")))),
+            JoinConstraint::Using,
+        ),
+        value(JoinConstraint::None, space0),
+    ));
+    let join = map(
+        (join_operator, cut(preceded(space0, table_or_subquery)), join_constraint),
+        |(operator, table, constraint)| Join { operator, table, constraint },
+    );
+    map((table_or_subquery, many0(join)), |(table, joins)| FromClause { table, joins }).parse(i)
+}
+
+//a table name or a parenthesised SELECT, with an optional alias
+fn table_or_subquery(i: &str) -> IResult<&str, TableOrSubquery> {
+    let alias = preceded((space0, opt((keyword("as"), space0))), identifier);
+    alt((
+        map(
+            (delimited((tag("("), space0), select, (space0, tag(")"))), opt(alias)),
+            |(select, alias)| TableOrSubquery::Subquery { select: Box::new(select), alias: alias.map(str::to_string) },
+        ),
+        map(table_name, TableOrSubquery::Table),
+    )).parse(i)
+}
+
+//a table name with an optional alias
+fn table_name(i: &str) -> IResult<&str, TableName> {
+    let alias = preceded((space0, opt((keyword("as"), space0))), identifier);
+    map(
+        (expecting(Expected::Description("table name"), identifier), opt(alias)),
+        |(name, alias)| TableName { name: name.to_string(), alias: alias.map(str::to_string) },
+    ).parse(i)
+}
+
+//the part after LIMIT; in the comma form the offset comes first
+fn limit(i: &str) -> IResult<&str, Limit> {
+    let (remaining, (first, second)) = (
+        expr,
+        opt(alt((
+            preceded((space0, keyword("offset"), space0), cut(map(expr, |offset| (false, offset)))),
+            preceded(space_comma, cut(map(expr, |count| (true, count)))),
+        ))),
+    ).parse(i)?;
+    let limit = match second {
+        None => Limit { count: first, offset: None },
+        Some((false, offset)) => Limit { count: first, offset: Some(offset) },
+        Some((true, count)) => Limit { count, offset: Some(first) },
+    };
+    Ok((remaining, limit))
+}
+
+//expr [ASC|DESC] [NULLS FIRST|NULLS LAST]
+fn ordering_term(i: &str) -> IResult<&str, OrderingTerm> {
+    let (remaining, (expr, descending, nulls_first)) = (
+        expr,
+        opt(preceded(space0, alt((value(false, keyword("asc")), value(true, keyword("desc")))))),
+        opt(preceded(space0, alt((value(true, keywords(&["nulls", "first"])), value(false, keywords(&["nulls", "last"])))))),
+    ).parse(i)?;
+    let descending = descending.unwrap_or(false);
+    //NULL is the smallest value, so it comes first in ascending order unless asked otherwise
+    Ok((remaining, OrderingTerm { expr, descending, nulls_first: nulls_first.unwrap_or(!descending) }))
+}
+
+//a whole SELECT statement, with nothing left over
+pub fn parse_select(sql: &str) -> Result<SelectStatement, ParseError> {
+    parse_complete(sql, select)
+}
 
-    Ok((remaining,(table_name,selected)))
+//a SELECT, or EXPLAIN QUERY PLAN and a SELECT
+pub fn parse_statement(sql: &str) -> Result<Statement, ParseError> {
+    let explain = preceded((space0, keywords(&["explain", "query", "plan"])), cut(select));
+    parse_complete(sql, alt((map(explain, Statement::ExplainQueryPlan), map(select, Statement::Select))))
+}
+
+#[cfg(test)]
+#[test]
+fn test_explain_query_plan() {
+    let Ok(Statement::ExplainQueryPlan(select)) = parse_statement("EXPLAIN QUERY PLAN SELECT name FROM apples WHERE id = 1;") else {
+        panic!("expected EXPLAIN QUERY PLAN");
+    };
+    assert_eq!(select, parse_select("SELECT name FROM apples WHERE id = 1").unwrap());
+    assert!(matches!(parse_statement("select 1"), Ok(Statement::Select(_))));
+    assert!(parse_statement("explain query plan").is_err());
+    assert!(parse_statement("explain select 1").is_err());
 }
 
 //object of select statement
-fn selection(i:&str) -> nom::IResult<&str, Vec<&str>> {
-    //get count(*) or list of columns
-    //essential that count(*) is first because alt tries parsers in order, 
-    //and if alphanumeric1 were first, it would consume "count" and reject "(*)"
-    separated_list1(space_comma, alt((tag_no_case("count(*)"), alphanumeric1))).parse(i)
+fn selection(i:&str) -> IResult<&str, Vec<ResultColumn>> {
+    separated_list1(space_comma, cut(result_column)).parse(i)
+}
+
+//*, or an expression with an optional alias
+fn result_column(i: &str) -> IResult<&str, ResultColumn> {
+    let alias = preceded(
+        (space0, opt((keyword("as"), space0))),
+        alt((map(identifier, str::to_string), string_literal)),
+    );
+    alt((
+        value(ResultColumn::Star, tag("*")),
+        //table.* reads like the start of an expression, so it's reported as one when it doesn't parse
+        map(
+            expecting(Expected::Description("expression"), terminated(identifier, (symbol("."), tag("*")))),
+            |table| ResultColumn::TableStar(table.to_string()),
+        ),
+        map((expr, opt(alias)), |(expr, alias)| ResultColumn::Expr { expr, alias }),
+    )).parse(i)
 }
 
 #[cfg(test)]
 #[test]
-fn test_select() -> () {
+fn test_select() {
 let input = "SELECT name, color FROM apples";
 let (remaining, result) = select(input).unwrap();
 assert_eq!(remaining, "");
-assert_eq!(result, ("apples", vec!["name", "color"]));
+assert_eq!(result, SelectStatement {
+    with: vec![],
+    columns: vec![
+        ResultColumn::Expr { expr: Expr::column("name"), alias: None },
+        ResultColumn::Expr { expr: Expr::column("color"), alias: None },
+    ],
+    from: Some(FromClause { table: TableOrSubquery::Table(TableName::new("apples")), joins: vec![] }),
+    where_clause: None,
+    distinct: false,
+    group_by: vec![],
+    having: None,
+    windows: vec![],
+    compound: vec![],
+    order_by: vec![],
+    limit: None,
+});
+}
+
+#[cfg(test)]
+#[test]
+fn test_select_where() {
+let input = "SELECT name, color FROM apples WHERE color = 'Yellow'";
+let (remaining, result) = select(input).unwrap();
+assert_eq!(remaining, "");
+assert_eq!(result.where_clause, Some(Expr::Binary(
+    Box::new(Expr::column("color")),
+    BinaryOperator::Equal,
+    Box::new(Expr::Literal(Literal::Text("Yellow".into()))),
+)));
 }
 
 #[cfg(test)]
 #[test]
-fn test_selection() -> () {
-let input = "name, color";
+fn test_select_order_by() {
+let input = "SELECT name FROM apples ORDER BY color COLLATE NOCASE DESC, 2 NULLS LAST, id";
+let (remaining, result) = select(input).unwrap();
+assert_eq!(remaining, "");
+assert_eq!(result.order_by, vec![
+    OrderingTerm {
+        expr: Expr::Collate { expr: Box::new(Expr::column("color")), collation: "NOCASE".into() },
+        descending: true,
+        nulls_first: false,
+    },
+    OrderingTerm { expr: Expr::Literal(Literal::Integer(2)), descending: false, nulls_first: false },
+    OrderingTerm { expr: Expr::column("id"), descending: false, nulls_first: true },
+]);
+assert!(parse_select("SELECT name FROM apples ORDER BY").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_select_group_by() {
+let input = "SELECT color, count(*) FROM apples GROUP BY color, 2 HAVING count(*) > 1 ORDER BY color";
+let (remaining, result) = select(input).unwrap();
+assert_eq!(remaining, "");
+assert_eq!(result.group_by, vec![Expr::column("color"), Expr::Literal(Literal::Integer(2))]);
+assert_eq!(result.having, Some(Expr::Binary(
+    Box::new(Expr::Function { name: "count".into(), args: FunctionArgs::Star }),
+    BinaryOperator::Greater,
+    Box::new(Expr::Literal(Literal::Integer(1))),
+)));
+assert_eq!(result.order_by.len(), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn test_select_distinct() {
+assert!(select("SELECT DISTINCT color FROM apples").unwrap().1.distinct);
+assert!(!select("SELECT ALL color FROM apples").unwrap().1.distinct);
+//a column named like the keyword's prefix is still a column
+let (_, result) = select("SELECT distinctive FROM apples").unwrap();
+assert!(!result.distinct);
+assert_eq!(result.columns, vec![ResultColumn::Expr { expr: Expr::column("distinctive"), alias: None }]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_select_joins() {
+let input = "SELECT a.*, b.name FROM apples AS a JOIN baskets b ON a.basket_id = b.id, farms LEFT OUTER JOIN owners USING (farm_id, name)";
+let (remaining, result) = select(input).unwrap();
+assert_eq!(remaining, "");
+assert_eq!(result.columns[0], ResultColumn::TableStar("a".into()));
+assert_eq!(result.from, Some(FromClause {
+    table: TableOrSubquery::Table(TableName { name: "apples".into(), alias: Some("a".into()) }),
+    joins: vec![
+        Join {
+            operator: JoinOperator::Inner,
+            table: TableOrSubquery::Table(TableName { name: "baskets".into(), alias: Some("b".into()) }),
+            constraint: JoinConstraint::On(Expr::Binary(
+                Box::new(Expr::Column { table: Some("a".into()), name: "basket_id".into() }),
+                BinaryOperator::Equal,
+                Box::new(Expr::Column { table: Some("b".into()), name: "id".into() }),
+            )),
+        },
+        Join { operator: JoinOperator::Inner, table: TableOrSubquery::Table(TableName::new("farms")), constraint: JoinConstraint::None },
+        Join {
+            operator: JoinOperator::Left,
+            table: TableOrSubquery::Table(TableName::new("owners")),
+            constraint: JoinConstraint::Using(vec!["farm_id".into(), "name".into()]),
+        },
+    ],
+}));
+let (_, result) = select("SELECT * FROM apples CROSS JOIN baskets WHERE 1").unwrap();
+assert_eq!(result.from.unwrap().joins.len(), 1);
+assert!(result.where_clause.is_some());
+assert!(parse_select("SELECT * FROM apples JOIN").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_select_subqueries() {
+let input = "SELECT (SELECT max(x) FROM t), n FROM (SELECT count(*) AS n FROM u) AS s WHERE NOT EXISTS (SELECT 1)";
+let (remaining, result) = select(input).unwrap();
+assert_eq!(remaining, "");
+let ResultColumn::Expr { expr: Expr::Subquery(scalar), .. } = &result.columns[0] else { panic!("expected a scalar subquery") };
+assert_eq!(scalar.from, Some(FromClause { table: TableOrSubquery::Table(TableName::new("t")), joins: vec![] }));
+let Some(FromClause { table: TableOrSubquery::Subquery { select: derived, alias }, .. }) = &result.from else { panic!("expected a subquery in FROM") };
+assert_eq!(alias.as_deref(), Some("s"));
+assert_eq!(derived.columns, vec![ResultColumn::Expr { expr: Expr::Function { name: "count".into(), args: FunctionArgs::Star }, alias: Some("n".into()) }]);
+let Some(Expr::Unary(UnaryOperator::Not, exists)) = &result.where_clause else { panic!("expected NOT") };
+let Expr::Exists(exists) = exists.as_ref() else { panic!("expected EXISTS") };
+assert_eq!(exists.from, None);
+//a parenthesised expression that starts like SELECT is still an expression
+let (_, result) = expr("(selected + 1)").unwrap();
+assert!(matches!(result, Expr::Binary(_, BinaryOperator::Add, _)));
+assert!(parse_select("SELECT 1 FROM (SELECT 2").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_select_with() {
+let input = "WITH RECURSIVE tree(id, depth) AS (SELECT 1, 0 UNION ALL SELECT id + 1, depth + 1 FROM tree WHERE id < 5), \
+    big AS (SELECT * FROM t) SELECT * FROM tree JOIN big";
+let (remaining, result) = select(input).unwrap();
+assert_eq!(remaining, "");
+assert_eq!(result.with.len(), 2);
+let tree = &result.with[0];
+assert_eq!(tree.name, "tree");
+assert_eq!(tree.columns, vec!["id".to_string(), "depth".to_string()]);
+assert_eq!(tree.select.from, None);
+let [recursive] = tree.select.compound.as_slice() else { panic!("expected a compound SELECT") };
+assert_eq!(recursive.operator, CompoundOperator::UnionAll);
+assert_eq!(recursive.select.from, Some(FromClause { table: TableOrSubquery::Table(TableName::new("tree")), joins: vec![] }));
+assert_eq!(result.with[1].columns, Vec::<String>::new());
+assert_eq!(result.with[1].select.compound, vec![]);
+//a subquery can have its own WITH
+let (_, result) = expr("(WITH t AS (SELECT 1) SELECT * FROM t)").unwrap();
+assert!(matches!(result, Expr::Subquery(select) if select.with.len() == 1));
+assert!(parse_select("WITH t AS SELECT 1 SELECT * FROM t").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_select_compound() {
+let input = "SELECT a FROM t UNION ALL SELECT b FROM u WHERE b > 1 EXCEPT SELECT c FROM v ORDER BY 1 DESC LIMIT 3";
+let (remaining, result) = select(input).unwrap();
+assert_eq!(remaining, "");
+assert_eq!(result.columns, vec![ResultColumn::Expr { expr: Expr::column("a"), alias: None }]);
+let operators: Vec<CompoundOperator> = result.compound.iter().map(|compound| compound.operator).collect();
+assert_eq!(operators, vec![CompoundOperator::UnionAll, CompoundOperator::Except]);
+assert!(result.compound[0].select.where_clause.is_some());
+//ORDER BY and LIMIT belong to the whole compound SELECT
+assert_eq!(result.order_by.len(), 1);
+assert!(result.limit.is_some());
+assert!(result.compound.iter().all(|compound| compound.select.order_by.is_empty() && compound.select.limit.is_none()));
+let (_, result) = select("SELECT 1 INTERSECT SELECT 2 UNION SELECT 3").unwrap();
+assert_eq!(result.compound[0].operator, CompoundOperator::Intersect);
+assert_eq!(result.compound[1].operator, CompoundOperator::Union);
+assert!(parse_select("SELECT 1 ORDER BY 1 UNION SELECT 2").is_err());
+assert!(parse_select("SELECT 1 UNION").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_window_functions() {
+let (remaining, result) = expr("sum(x) OVER (PARTITION BY a, b ORDER BY c DESC ROWS BETWEEN 2 PRECEDING AND UNBOUNDED FOLLOWING)").unwrap();
+assert_eq!(remaining, "");
+assert_eq!(result, Expr::WindowFunction {
+    name: "sum".into(),
+    args: FunctionArgs::List { distinct: false, args: vec![Expr::column("x")] },
+    over: Box::new(Over::Window(WindowDefinition {
+        base: None,
+        partition_by: vec![Expr::column("a"), Expr::column("b")],
+        order_by: vec![OrderingTerm { expr: Expr::column("c"), descending: true, nulls_first: false }],
+        frame: Some(Frame { units: FrameUnits::Rows, start: FrameBound::Preceding(Expr::Literal(Literal::Integer(2))), end: FrameBound::UnboundedFollowing }),
+    })),
+});
+let (_, result) = expr("row_number() OVER ()").unwrap();
+assert!(matches!(result, Expr::WindowFunction { over, .. } if matches!(*over, Over::Window(WindowDefinition { base: None, frame: None, .. }))));
+//a frame with only a start ends at the current row
+let (_, result) = expr("count(*) OVER (w GROUPS CURRENT ROW)").unwrap();
+let Expr::WindowFunction { over, .. } = result else { panic!("expected a window function") };
+let Over::Window(window) = *over else { panic!("expected a window definition") };
+assert_eq!(window.base.as_deref(), Some("w"));
+assert_eq!(window.frame, Some(Frame { units: FrameUnits::Groups, start: FrameBound::CurrentRow, end: FrameBound::CurrentRow }));
+
+let (remaining, result) = select("SELECT rank() OVER w FROM t WINDOW w AS (ORDER BY x), v AS (w RANGE 1 PRECEDING)").unwrap();
+assert_eq!(remaining, "");
+assert!(matches!(&result.columns[0], ResultColumn::Expr { expr: Expr::WindowFunction { over, .. }, .. } if **over == Over::Name("w".into())));
+assert_eq!(result.windows.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["w", "v"]);
+assert!(parse_select("SELECT sum(x) OVER (ROWS BETWEEN UNBOUNDED FOLLOWING AND CURRENT ROW) FROM t").is_err());
+assert!(parse_select("SELECT sum(x) OVER (ROWS BETWEEN 1 PRECEDING AND UNBOUNDED PRECEDING) FROM t").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_select_limit() {
+let limit = |input| select(input).unwrap().1.limit;
+let integer = |n| Expr::Literal(Literal::Integer(n));
+assert_eq!(limit("SELECT name FROM apples LIMIT 20"), Some(Limit { count: integer(20), offset: None }));
+assert_eq!(limit("SELECT name FROM apples ORDER BY id LIMIT 20 OFFSET 5"), Some(Limit { count: integer(20), offset: Some(integer(5)) }));
+assert_eq!(limit("SELECT name FROM apples LIMIT 5, 20"), Some(Limit { count: integer(20), offset: Some(integer(5)) }));
+assert!(parse_select("SELECT name FROM apples LIMIT 5 OFFSET").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_selection() {
+let input = "*, name AS n, upper(color) c";
 let (remaining, result) = selection(input).unwrap();
 assert_eq!(remaining, "");
-assert_eq!(result, vec!["name", "color"]);
+assert_eq!(result, vec![
+    ResultColumn::Star,
+    ResultColumn::Expr { expr: Expr::column("name"), alias: Some("n".into()) },
+    ResultColumn::Expr {
+        expr: Expr::Function { name: "upper".into(), args: FunctionArgs::List { distinct: false, args: vec![Expr::column("color")] } },
+        alias: Some("c".into()),
+    },
+]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_errors() {
+    let error = parse_select("SELECT name, FROM apples").unwrap_err();
+    assert_eq!(error.offset, 13);
+    assert_eq!(error.expected, "'*' or expression");
+    assert_eq!(error.snippet, "SELECT name, FROM apples\n             ^");
+
+    let error = parse_select("SELECT name FROM apples\nWHERE color = ").unwrap_err();
+    assert_eq!(error.offset, 38);
+    assert_eq!(error.expected, "expression");
+    assert_eq!(error.snippet, "WHERE color = \n              ^");
+
+    let error = parse_select("SELECT 1, x'abc' AS data").unwrap_err();
+    assert_eq!(error.offset, 10);
+    assert_eq!(error.to_string(), "syntax error at offset 10: unrecognized token: \"x'abc'\"\nSELECT 1, x'abc' AS data\n          ^");
+
+    let error = parse_select("SELECT name FROM apples LIMIT").unwrap_err();
+    assert_eq!(error.expected, "expression");
+    let error = parse_select("SELECT name FROM apples LIMIT 1 2").unwrap_err();
+    assert_eq!(error.offset, 32);
+    assert_eq!(error.expected, "end of input");
+    assert!(parse_select("select name from apples;").is_ok());
 }
 
 // ***CREATE TABLE***
@@ -67,36 +1193,201 @@ assert_eq!(result, vec!["name", "color"]);
 // 	color text
 // )
 
-//get table name and columns from CREATE TABLE statement
-pub fn create_table(i: &str) -> nom::IResult<&str,(&str,Vec<Vec<&str>>)> {
-    let (remaining, (_,_,table_name,_,table_columns)) = (
-        tag_no_case("create table"), 
-        multispace1,
-        alphanumeric1,
-        multispace1,
-        columns
-    ).parse(i).unwrap();
+//get table name, columns and constraints from CREATE TABLE statement
+pub fn create_table(i: &str) -> IResult<&str, CreateTable> {
+    let (remaining, (_, _, _, _, _, name, _, (columns, constraints), without_rowid)) = (
+        keyword("create"),
+        space0,
+        opt(terminated(alt((keyword("temporary"), keyword("temp"))), space0)),
+        keyword("table"),
+        opt((space0, keywords(&["if", "not", "exists"]))),
+        preceded(space0, qualified_name),
+        space0,
+        columns,
+        opt((space0, keywords(&["without", "rowid"]))),
+    ).parse(i)?;
 
-    Ok((remaining,(table_name,table_columns)))
+    Ok((remaining, CreateTable { name: name.to_string(), columns, constraints, without_rowid: without_rowid.is_some() }))
 }
 
-// get the comma-separated string inside parens from the SQL query
-fn columns(i: &str) -> IResult<&str,Vec<Vec<&str>>> {
-    let list_items = separated_list0(space_comma, column_items);
-    let mut inside_parens = delimited((tag("("), multispace0), list_items, (multispace0, tag(")")));
+pub fn parse_create_table(sql: &str) -> Result<CreateTable, ParseError> {
+    parse_complete(sql, create_table)
+}
 
-    inside_parens.parse(i)
-    
+//a name that may be prefixed with a schema name, which is dropped
+fn qualified_name(i: &str) -> IResult<&str, &str> {
+    map((identifier, opt(preceded(symbol("."), identifier))), |(first, second)| second.unwrap_or(first)).parse(i)
 }
 
-//get the space comma combination that appears between the columns
-fn space_comma(i: &str) -> IResult<&str, &str> {
-    delimited(multispace0, tag(","), multispace0).parse(i)
+// get the column definitions and table constraints inside parens from the SQL query
+fn columns(i: &str) -> IResult<&str, (Vec<ColumnDef>, Vec<TableConstraint>)> {
+    enum Item {
+        Column(ColumnDef),
+        Constraint(TableConstraint),
+    }
+    //table constraints start with keywords that can't be column names, so try them first
+    let item = alt((map(table_constraint, Item::Constraint), map(column_def, Item::Column)));
+    let list_items = separated_list1(space_comma, item);
+    let (remaining, items) = delimited((tag("("), space0), list_items, (space0, tag(")"))).parse(i)?;
+
+    let mut columns = Vec::new();
+    let mut constraints = Vec::new();
+    for item in items {
+        match item {
+            Item::Column(column) => columns.push(column),
+            Item::Constraint(constraint) => constraints.push(constraint),
+        }
+    }
+    Ok((remaining, (columns, constraints)))
+}
+
+//words that end a type name because they start a constraint
+const CONSTRAINT_KEYWORDS: &[&str] = &["constraint", "primary", "not", "null", "unique", "check", "default", "collate", "references", "generated", "as"];
+
+//one or more words, optionally followed by one or two sizes: "text", "varchar(255)", "unsigned big int"
+fn type_name(i: &str) -> IResult<&str, &str> {
+    let word = verify(identifier, |word: &str| !CONSTRAINT_KEYWORDS.iter().any(|keyword| keyword.eq_ignore_ascii_case(word)));
+    let size = (symbol("("), number, opt((space_comma, number)), tag(")"));
+    recognize((separated_list1(multispace1, word), opt(preceded(space0, size)))).parse(i)
+}
+
+//get the column name, declared type and constraints of one column
+fn column_def(i: &str) -> IResult<&str, ColumnDef> {
+    let (remaining, (name, type_name, constraints)) = (
+        identifier,
+        opt(preceded(space0, type_name)),
+        many0(preceded(space0, column_constraint)),
+    ).parse(i)?;
+    Ok((remaining, ColumnDef {
+        name: name.to_string(),
+        type_name: type_name.map(str::to_string),
+        //a bare NULL is allowed as a constraint but doesn't constrain anything
+        constraints: constraints.into_iter().flatten().collect(),
+    }))
+}
+
+fn column_constraint(i: &str) -> IResult<&str, Option<ColumnConstraint>> {
+    let primary_key = map(
+        (
+            keywords(&["primary", "key"]),
+            opt(preceded(space0, alt((value(false, keyword("asc")), value(true, keyword("desc")))))),
+            opt(preceded(space0, conflict_clause)),
+            opt(preceded(space0, keyword("autoincrement"))),
+        ),
+        |(_, descending, _, autoincrement)| ColumnConstraint::PrimaryKey {
+            descending: descending.unwrap_or(false),
+            autoincrement: autoincrement.is_some(),
+        },
+    );
+    let not_null = value(ColumnConstraint::NotNull, (keywords(&["not", "null"]), opt(preceded(space0, conflict_clause))));
+    let unique = value(ColumnConstraint::Unique, (keyword("unique"), opt(preceded(space0, conflict_clause))));
+    let check = map(preceded(keyword("check"), parenthesised_expr), ColumnConstraint::Check);
+    let default = map(
+        preceded(
+            (keyword("default"), space0),
+            alt((
+                parenthesised_expr,
+                map(preceded(opt(tag("+")), literal), Expr::Literal),
+                //CURRENT_TIMESTAMP and friends, or a bare word sqlite reads as a string
+                map(identifier, |word| Expr::Literal(Literal::Text(word.to_string()))),
+            )),
+        ),
+        ColumnConstraint::Default,
+    );
+    let collate = map(preceded((keyword("collate"), space0), identifier), |name| ColumnConstraint::Collate(name.to_string()));
+    let references = map(foreign_key_clause, |(table, columns)| ColumnConstraint::References { table, columns });
+    let generated = map(
+        (
+            opt((keywords(&["generated", "always"]), space0)),
+            keyword("as"),
+            parenthesised_expr,
+            opt(preceded(space0, alt((value(true, keyword("stored")), value(false, keyword("virtual")))))),
+        ),
+        |(_, _, expr, stored)| ColumnConstraint::Generated { expr, stored: stored.unwrap_or(false) },
+    );
+    preceded(
+        opt((keyword("constraint"), space0, identifier, space0)),
+        alt((
+            map(alt((primary_key, not_null, unique, check, default, collate, references, generated)), Some),
+            value(None, keyword("null")),
+        )),
+    ).parse(i)
 }
 
-//get the individual items (column name, column data type, etc) from the comma-separated string
-fn column_items(i: &str) -> IResult<&str,Vec<&str>> {
-    separated_list0(multispace1, alphanumeric1).parse(i)
+fn table_constraint(i: &str) -> IResult<&str, TableConstraint> {
+    let column_list = |i| delimited(symbol("("), separated_list1(space_comma, indexed_column), symbol(")")).parse(i);
+    let primary_key = map(
+        (keywords(&["primary", "key"]), column_list, opt(conflict_clause)),
+        |(_, columns, _)| TableConstraint::PrimaryKey(columns),
+    );
+    let unique = map(
+        (keyword("unique"), column_list, opt(conflict_clause)),
+        |(_, columns, _)| TableConstraint::Unique(columns),
+    );
+    let check = map(preceded(keyword("check"), parenthesised_expr), TableConstraint::Check);
+    let foreign_key = map(
+        (keywords(&["foreign", "key"]), name_list, space0, foreign_key_clause),
+        |(_, columns, _, (table, foreign_columns))| TableConstraint::ForeignKey { columns, table, foreign_columns },
+    );
+    preceded(
+        opt((keyword("constraint"), space0, identifier, space0)),
+        alt((primary_key, unique, check, foreign_key)),
+    ).parse(i)
+}
+
+fn parenthesised_expr(i: &str) -> IResult<&str, Expr> {
+    delimited(symbol("("), expr, symbol(")")).parse(i)
+}
+
+//"(a, b, c)"
+fn name_list(i: &str) -> IResult<&str, Vec<String>> {
+    delimited(symbol("("), separated_list1(space_comma, map(identifier, str::to_string)), symbol(")")).parse(i)
+}
+
+//ON CONFLICT with its resolution, which only matters when writing
+fn conflict_clause(i: &str) -> IResult<&str, ()> {
+    let resolution = alt((keyword("rollback"), keyword("abort"), keyword("fail"), keyword("ignore"), keyword("replace")));
+    value((), (keywords(&["on", "conflict"]), space0, resolution)).parse(i)
+}
+
+//REFERENCES table [(columns)] followed by any ON DELETE/ON UPDATE/MATCH/DEFERRABLE clauses, which are ignored
+fn foreign_key_clause(i: &str) -> IResult<&str, (String, Vec<String>)> {
+    let action = alt((
+        keywords(&["set", "null"]),
+        keywords(&["set", "default"]),
+        keywords(&["no", "action"]),
+        value((), keyword("cascade")),
+        value((), keyword("restrict")),
+    ));
+    let on_action = value((), (keyword("on"), space0, alt((keyword("delete"), keyword("update"))), space0, action));
+    let match_name = value((), (keyword("match"), space0, identifier));
+    let deferrable = value((), (
+        opt((keyword("not"), space0)),
+        keyword("deferrable"),
+        opt((space0, keyword("initially"), space0, alt((keyword("deferred"), keyword("immediate"))))),
+    ));
+    let (remaining, (_, _, table, columns, _)) = (
+        keyword("references"),
+        space0,
+        identifier,
+        opt(preceded(space0, name_list)),
+        many0(preceded(space0, alt((on_action, match_name, deferrable)))),
+    ).parse(i)?;
+    Ok((remaining, (table.to_string(), columns.unwrap_or_default())))
+}
+
+#[cfg(test)]
+#[test]
+fn test_rowid_alias() {
+    let (_, table) = create_table("CREATE TABLE apples (name text, id integer primary key autoincrement)").unwrap();
+    assert_eq!(table.rowid_alias(), Some(1));
+    //only the exact type name INTEGER makes an alias
+    let (_, table) = create_table("CREATE TABLE t (id int primary key, name text)").unwrap();
+    assert_eq!(table.rowid_alias(), None);
+    let (_, table) = create_table("CREATE TABLE t (name text, id INTEGER, PRIMARY KEY (id))").unwrap();
+    assert_eq!(table.rowid_alias(), Some(1));
+    let (_, table) = create_table("CREATE TABLE t (id integer primary key desc)").unwrap();
+    assert_eq!(table.rowid_alias(), None);
 }
 
 #[cfg(test)]
@@ -110,32 +1401,115 @@ fn test_create_table() {
 )";
     let (remaining, result) = create_table(input).unwrap();
     assert_eq!(remaining, "");
-    assert_eq!(result, ("apples",vec![vec!["id", "integer", "primary", "key", "autoincrement"], vec!["name", "text"], vec!["color","text"]]));
+    let column = |name: &str, type_name: &str, constraints| ColumnDef { name: name.into(), type_name: Some(type_name.into()), constraints };
+    assert_eq!(result, CreateTable {
+        name: "apples".into(),
+        columns: vec![
+            column("id", "integer", vec![ColumnConstraint::PrimaryKey { descending: false, autoincrement: true }]),
+            column("name", "text", vec![]),
+            column("color", "text", vec![]),
+        ],
+        constraints: vec![],
+        without_rowid: false,
+    });
 }
 
 #[cfg(test)]
 #[test]
 fn test_columns() {
-    let input = "(id integer primary key autoincrement, name text, color text)";
-    let (remaining, result) = columns(input).unwrap();
+    let input = "(id integer primary key, \"full name\" varchar(255) not null default 'x', -- a comment
+        parent_id int references people(id) on delete cascade, flags, unique (parent_id, flags desc))";
+    let (remaining, (columns, constraints)) = columns(input).unwrap();
     assert_eq!(remaining, "");
-    assert_eq!(result, vec![vec!["id", "integer", "primary", "key", "autoincrement"], vec!["name", "text"], vec!["color","text"]]);
+    assert_eq!(columns.len(), 4);
+    assert_eq!(columns[1].name, "full name");
+    assert_eq!(columns[1].type_name.as_deref(), Some("varchar(255)"));
+    assert_eq!(columns[1].constraints, vec![ColumnConstraint::NotNull, ColumnConstraint::Default(Expr::Literal(Literal::Text("x".into())))]);
+    assert_eq!(columns[2].constraints, vec![ColumnConstraint::References { table: "people".into(), columns: vec!["id".into()] }]);
+    assert_eq!(columns[3].type_name, None);
+    let indexed = |name: &str, descending: bool| IndexedColumn { name: name.into(), collation: None, descending };
+    assert_eq!(constraints, vec![TableConstraint::Unique(vec![indexed("parent_id", false), indexed("flags", true)])]);
 }
 
 #[cfg(test)]
 #[test]
-fn test_space_comma() {
-    let input = ", ";
-    let (remaining, result) = space_comma(input).unwrap();
-    assert_eq!(remaining, "");
-    assert_eq!(result, ",");
+fn test_column_def() {
+    let input = "id integer primary key autoincrement,";
+    let (remaining, result) = column_def(input).unwrap();
+    assert_eq!(remaining, ",");
+    assert_eq!(result, ColumnDef {
+        name: "id".into(),
+        type_name: Some("integer".into()),
+        constraints: vec![ColumnConstraint::PrimaryKey { descending: false, autoincrement: true }],
+    });
+    let (_, result) = column_def("price unsigned big int null check (price > 0)").unwrap();
+    assert_eq!(result.type_name.as_deref(), Some("unsigned big int"));
+    assert_eq!(result.affinity(), Affinity::Integer);
+    assert!(matches!(result.constraints[..], [ColumnConstraint::Check(_)]));
+}
+
+// ***CREATE INDEX***
+
+// CREATE INDEX idx_companies_country on companies (country)
+
+//get index name, table name and indexed columns from CREATE INDEX statement
+pub fn create_index(i: &str) -> IResult<&str, CreateIndex> {
+    let (remaining, (_, unique, _, _, name, _, _, table, _, columns, where_clause)) = (
+        keyword("create"),
+        opt((space0, keyword("unique"))),
+        (space0, keyword("index")),
+        opt((space0, keywords(&["if", "not", "exists"]))),
+        preceded(space0, qualified_name),
+        space0,
+        keyword("on"),
+        preceded(space0, identifier),
+        space0,
+        delimited((tag("("), space0), separated_list1(space_comma, indexed_column), (space0, tag(")"))),
+        //partial indexes have a WHERE clause
+        opt(preceded((space0, keyword("where"), space0), expr)),
+    ).parse(i)?;
+
+    Ok((remaining, CreateIndex { name: name.to_string(), table: table.to_string(), columns, unique: unique.is_some(), where_clause }))
+}
+
+pub fn parse_create_index(sql: &str) -> Result<CreateIndex, ParseError> {
+    parse_complete(sql, create_index)
+}
+
+//an indexed column name, with its collation and sort order
+fn indexed_column(i: &str) -> IResult<&str, IndexedColumn> {
+    let (remaining, (name, collation, descending)) = (
+        identifier,
+        opt(preceded((space0, keyword("collate"), space0), identifier)),
+        opt(preceded(space0, alt((value(false, keyword("asc")), value(true, keyword("desc")))))),
+    ).parse(i)?;
+    Ok((remaining, IndexedColumn {
+        name: name.to_string(),
+        collation: collation.map(str::to_string),
+        descending: descending.unwrap_or(false),
+    }))
 }
 
 #[cfg(test)]
 #[test]
-fn test_column_items() {
-    let input = "id integer primary key autoincrement,";
-    let (remaining, result) = column_items(input).unwrap();
-    assert_eq!(remaining, ",");
-    assert_eq!(result, vec!["id", "integer", "primary", "key", "autoincrement"]);
-}
\ No newline at end of file
+fn test_create_index() {
+    let input = "CREATE INDEX idx_companies_country\n\ton companies (country)";
+    let (remaining, result) = create_index(input).unwrap();
+    assert_eq!(remaining, "");
+    assert_eq!(result, CreateIndex {
+        name: "idx_companies_country".into(),
+        table: "companies".into(),
+        columns: vec![IndexedColumn { name: "country".into(), collation: None, descending: false }],
+        unique: false,
+        where_clause: None,
+    });
+    let input = "create unique index if not exists \"by_name\" on people(last_name, first_name desc)";
+    let (_, result) = create_index(input).unwrap();
+    assert_eq!(result.name, "by_name");
+    assert!(result.unique);
+    assert_eq!(result.columns[1], IndexedColumn { name: "first_name".into(), collation: None, descending: true });
+    let input = "create index named on people(name collate nocase) where name is not null";
+    let (_, result) = create_index(input).unwrap();
+    assert_eq!(result.columns[0].collation.as_deref(), Some("nocase"));
+    assert!(result.where_clause.is_some());
+}
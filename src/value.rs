@@ -0,0 +1,598 @@
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+
+use crate::ast::{Affinity, BinaryOperator, Literal};
+
+//a value as sqlite sees it: one of its five storage classes, however it was stored on disk
+#[derive(Debug, Clone)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    pub fn from_literal(literal: &Literal) -> Self {
+        match literal {
+            Literal::Text(text) => Value::Text(text.to_string()),
+            Literal::Integer(n) => Value::Integer(*n),
+            Literal::Real(n) => Value::Real(*n),
+            Literal::Blob(bytes) => Value::Blob(bytes.clone()),
+            Literal::Null => Value::Null,
+        }
+    }
+
+    pub fn from_bool(truth: Option<bool>) -> Self {
+        match truth {
+            Some(truth) => Value::Integer(truth as i64),
+            None => Value::Null,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    //the value as an operand of arithmetic; text and blobs are read as the number they start with
+    pub fn to_number(&self) -> Number {
+        match self {
+            Value::Null => Number::Integer(0),
+            Value::Integer(n) => Number::Integer(*n),
+            Value::Real(n) => Number::Real(*n),
+            Value::Text(text) => leading_number(text).0,
+            Value::Blob(bytes) => leading_number(&String::from_utf8_lossy(bytes)).0,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        match self {
+            Value::Text(text) => text.clone(),
+            Value::Blob(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            _ => self.to_string(),
+        }
+    }
+
+    //truth value of a condition: numbers are true when non-zero, and text and blobs are
+    //read as the number they start with ('12abc' is true, 'abc' is false); NULL is unknown
+    pub fn truth(&self) -> Option<bool> {
+        match self {
+            Value::Null => None,
+            _ => Some(self.to_number().as_real() != 0.0),
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    //CAST(value AS type), converting to the storage class the affinity of the type asks for
+    pub fn cast(&self, affinity: Affinity) -> Self {
+        if self.is_null() {
+            return Value::Null;
+        }
+        match affinity {
+            //text is read only as far as the integer it starts with, so '1e5' is 1
+            Affinity::Integer => match self {
+                Value::Text(text) => Value::Integer(leading_integer(text)),
+                Value::Blob(bytes) => Value::Integer(leading_integer(&String::from_utf8_lossy(bytes))),
+                _ => Value::Integer(self.to_number().as_integer()),
+            },
+            Affinity::Real => Value::Real(self.to_number().as_real()),
+            Affinity::Text => Value::Text(self.to_text()),
+            Affinity::Blob => match self {
+                Value::Blob(_) => self.clone(),
+                _ => Value::Blob(self.to_text().into_bytes()),
+            },
+            //reals that hold a whole number become integers
+            Affinity::Numeric => match self.to_number() {
+                Number::Real(n) => Number::Real(n).integral().into_value(),
+                number => number.into_value(),
+            },
+        }
+    }
+
+    //the conversion a column with `affinity` makes to values stored in it, which is also what
+    //comparisons do to their operands; unlike CAST it only turns text into a number if all of it is one
+    pub fn apply_affinity(self, affinity: Affinity) -> Self {
+        match (affinity, self) {
+            (Affinity::Text, value @ (Value::Integer(_) | Value::Real(_))) => Value::Text(value.to_string()),
+            (Affinity::Integer | Affinity::Numeric, Value::Text(text)) => match numeric_text(&text) {
+                Some(number) => number.integral().into_value(),
+                None => Value::Text(text),
+            },
+            (Affinity::Integer | Affinity::Numeric, Value::Real(n)) => Number::Real(n).integral().into_value(),
+            (Affinity::Real, Value::Integer(n)) => Value::Real(n as f64),
+            (Affinity::Real, Value::Text(text)) => match numeric_text(&text) {
+                Some(number) => Value::Real(number.as_real()),
+                None => Value::Text(text),
+            },
+            (_, value) => value,
+        }
+    }
+
+    //sort order between storage classes: NULL, then numbers, then text, then blobs
+    fn class_rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Integer(_) | Value::Real(_) => 1,
+            Value::Text(_) => 2,
+            Value::Blob(_) => 3,
+        }
+    }
+
+    //order two values the way sqlite orders them in an index or ORDER BY (binary collation);
+    //integers and reals compare by numeric value, and NULL sorts before everything
+    pub fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Real(b)) => compare_integer_real(*a, *b),
+            (Value::Real(a), Value::Integer(b)) => compare_integer_real(*b, *a).reverse(),
+            //values never hold NaN, and -0.0 equals 0.0 like it does in sqlite
+            (Value::Real(a), Value::Real(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Value::Text(a), Value::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+            _ => self.class_rank().cmp(&other.class_rank()),
+        }
+    }
+
+    //the value with its text rewritten so values equal under the collation are equal under binary
+    //comparison too, for DISTINCT aggregates and grouping by a NOCASE or RTRIM expression
+    pub fn collation_key(&self, collation: Collation) -> Value {
+        match (self, collation) {
+            (Value::Text(text), Collation::NoCase) => Value::Text(text.to_ascii_lowercase()),
+            (Value::Text(text), Collation::RTrim) => Value::Text(text.trim_end_matches(' ').to_string()),
+            _ => self.clone(),
+        }
+    }
+
+    //rough size in memory, for the operators that spill to disk past a budget
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Value>() + self.heap_size()
+    }
+
+    //what the value holds outside itself: the allocation of its text or blob, at its capacity
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Value::Text(text) => allocation_size(text.capacity()),
+            Value::Blob(bytes) => allocation_size(bytes.capacity()),
+            _ => 0,
+        }
+    }
+
+    //order two values with a collating sequence, which only changes how text compares with text
+    pub fn compare_collated(&self, other: &Self, collation: Collation) -> Ordering {
+        match (self, other, collation) {
+            (Value::Text(a), Value::Text(b), Collation::NoCase) => {
+                a.bytes().map(|byte| byte.to_ascii_lowercase()).cmp(b.bytes().map(|byte| byte.to_ascii_lowercase()))
+            },
+            (Value::Text(a), Value::Text(b), Collation::RTrim) => a.trim_end_matches(' ').as_bytes().cmp(b.trim_end_matches(' ').as_bytes()),
+            _ => self.compare(other),
+        }
+    }
+}
+
+//how much memory an allocation of `bytes` bytes takes up: the allocator keeps a header beside each one and rounds it up
+//to 16 bytes, 32 at least (and nothing is allocated for no bytes)
+pub fn allocation_size(bytes: usize) -> usize {
+    match bytes {
+        0 => 0,
+        bytes => (bytes + 8).max(32).next_multiple_of(16),
+    }
+}
+
+//values are equal, ordered and hashed the way compare() sees them, so 1 and 1.0 are the same key
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.compare(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.compare(other)
+    }
+}
+
+impl std::hash::Hash for Value {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.class_rank().hash(state);
+        match self {
+            Value::Null => {},
+            Value::Integer(n) => n.hash(state),
+            //a whole real has to hash like the integer it equals
+            Value::Real(n) if n.fract() == 0.0 && (-9223372036854775808.0..9223372036854775808.0).contains(n) => (*n as i64).hash(state),
+            Value::Real(n) => n.to_bits().hash(state),
+            Value::Text(text) => text.hash(state),
+            Value::Blob(bytes) => bytes.hash(state),
+        }
+    }
+}
+
+//the built-in collating sequences
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collation {
+    //compare the bytes of the text
+    Binary,
+    //fold ASCII letters to lower case first
+    NoCase,
+    //ignore trailing spaces
+    RTrim,
+}
+
+impl Collation {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "binary" => Ok(Collation::Binary),
+            "nocase" => Ok(Collation::NoCase),
+            "rtrim" => Ok(Collation::RTrim),
+            _ => bail!("no such collation sequence: {}", name),
+        }
+    }
+}
+
+//compare without going through f64, which can't hold every i64
+fn compare_integer_real(integer: i64, real: f64) -> Ordering {
+    if real.is_nan() || real >= 9223372036854775808.0 {
+        return Ordering::Less;
+    }
+    if real < -9223372036854775808.0 {
+        return Ordering::Greater;
+    }
+    let floor = real.floor();
+    match integer.cmp(&(floor as i64)) {
+        Ordering::Equal if real > floor => Ordering::Less,
+        ordering => ordering,
+    }
+}
+
+//the affinities of the two sides of a comparison decide which side gets converted before comparing:
+//a numeric side makes the other side numeric, and a text side makes a side without affinity text
+//(an expression that isn't a column or a CAST has none, while a BLOB column has BLOB affinity and keeps its values)
+pub fn comparison_affinities(left: Option<Affinity>, right: Option<Affinity>) -> (Affinity, Affinity) {
+    let numeric = |affinity| matches!(affinity, Some(Affinity::Integer | Affinity::Real | Affinity::Numeric));
+    if numeric(left) && !numeric(right) {
+        (Affinity::Blob, Affinity::Numeric)
+    } else if numeric(right) && !numeric(left) {
+        (Affinity::Numeric, Affinity::Blob)
+    } else if left == Some(Affinity::Text) && right.is_none() {
+        (Affinity::Blob, Affinity::Text)
+    } else if right == Some(Affinity::Text) && left.is_none() {
+        (Affinity::Text, Affinity::Blob)
+    } else {
+        (Affinity::Blob, Affinity::Blob)
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Real(n) => write!(f, "{}", format_real(*n)),
+            Value::Blob(bytes) => write!(f, "{:?}", bytes),
+            Value::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+//sqlite prints reals with up to 15 significant digits (printf's %!.15g), but always with a
+//decimal point so they can't be mistaken for integers: 1.0, 0.3, 1.0e+20
+fn format_real(n: f64) -> String {
+    if n.is_infinite() {
+        return if n > 0.0 { "Inf".to_string() } else { "-Inf".to_string() };
+    }
+    if n == 0.0 {
+        return "0.0".to_string();
+    }
+    let sign = if n < 0.0 { "-" } else { "" };
+    let (digits, point) = decimal_digits(n.abs(), 15);
+    let digits = String::from_utf8(digits).unwrap_or_default();
+    let digits = digits.trim_end_matches('0');
+    let exponent = point - 1;
+    if !(-4..15).contains(&exponent) {
+        let (first, rest) = digits.split_at(1);
+        let rest = if rest.is_empty() { "0" } else { rest };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}{}.{}e{}{:02}", sign, first, rest, exponent_sign, exponent.abs())
+    } else if exponent < 0 {
+        format!("{}0.{}{}", sign, "0".repeat((-exponent - 1) as usize), digits)
+    } else {
+        let point = point as usize;
+        if digits.len() <= point {
+            format!("{}{}{}.0", sign, digits, "0".repeat(point - digits.len()))
+        } else {
+            format!("{}{}.{}", sign, &digits[..point], &digits[point..])
+        }
+    }
+}
+
+//the first `significant` digits of a positive real and where the decimal point goes among them,
+//worked out the way sqlite does: scale the real into the u64 range with double-double arithmetic,
+//then round the digits of that integer half up. There are never more than 20 of them, and rounding
+//to none leaves either nothing or a 1 in the place before the first. This differs from rounding the exact value
+//in the last digit now and then (94.89911547561195 prints as 94.899115475612), and matching
+//sqlite's output matters more here
+//(the constants are copied from sqlite as written there)
+#[allow(clippy::excessive_precision)]
+pub fn decimal_digits(real: f64, significant: usize) -> (Vec<u8>, i32) {
+    let mut rr = [real, 0.0];
+    let mut exponent = 0;
+    if rr[0] > 9.223372036854774784e18 {
+        while rr[0] > 9.223372036854774784e118 {
+            exponent += 100;
+            dekker_multiply(&mut rr, 1.0e-100, -1.99918998026028836196e-117);
+        }
+        while rr[0] > 9.223372036854774784e28 {
+            exponent += 10;
+            dekker_multiply(&mut rr, 1.0e-10, -3.6432197315497741579e-27);
+        }
+        while rr[0] > 9.223372036854774784e18 {
+            exponent += 1;
+            dekker_multiply(&mut rr, 1.0e-01, -5.5511151231257827021e-18);
+        }
+    } else {
+        while rr[0] < 9.223372036854774784e-83 {
+            exponent -= 100;
+            dekker_multiply(&mut rr, 1.0e100, -1.5902891109759918046e83);
+        }
+        while rr[0] < 9.223372036854774784e7 {
+            exponent -= 10;
+            dekker_multiply(&mut rr, 1.0e10, 0.0);
+        }
+        while rr[0] < 9.22337203685477478e17 {
+            exponent -= 1;
+            dekker_multiply(&mut rr, 1.0e1, 0.0);
+        }
+    }
+    let scaled = if rr[1] < 0.0 { (rr[0] as u64).wrapping_sub((-rr[1]) as u64) } else { (rr[0] as u64).wrapping_add(rr[1] as u64) };
+
+    let mut digits = scaled.to_string().into_bytes();
+    let mut point = digits.len() as i32 + exponent;
+    if digits.len() > significant {
+        let round_up = digits[significant] >= b'5';
+        digits.truncate(significant);
+        if round_up {
+            match digits.iter().rposition(|digit| *digit != b'9') {
+                Some(position) => {
+                    digits[position] += 1;
+                    digits[position + 1..].fill(b'0');
+                },
+                //every digit was a 9
+                None => {
+                    digits.fill(b'0');
+                    digits.insert(0, b'1');
+                    point += 1;
+                },
+            }
+        }
+    }
+    (digits, point)
+}
+
+//multiply the double-double `x` by y + yy (Dekker's algorithm, splitting each double into two halves
+//whose products are exact)
+fn dekker_multiply(x: &mut [f64; 2], y: f64, yy: f64) {
+    let split = |value: f64| f64::from_bits(value.to_bits() & 0xfffffffffc000000);
+    let hx = split(x[0]);
+    let tx = x[0] - hx;
+    let hy = split(y);
+    let ty = y - hy;
+    let p = hx * hy;
+    let q = hx * ty + tx * hy;
+    let c = p + q;
+    let cc = x[0] * yy + x[1] * y + (p - c + q + tx * ty);
+    x[0] = c + cc;
+    x[1] = c - x[0];
+    x[1] += cc;
+}
+
+//a value taking part in arithmetic
+#[derive(Debug, Clone, Copy)]
+pub enum Number {
+    Integer(i64),
+    Real(f64),
+}
+
+impl Number {
+    pub fn as_integer(self) -> i64 {
+        match self {
+            Number::Integer(n) => n,
+            //`as` saturates, like sqlite does when it casts an out of range real
+            Number::Real(n) => n as i64,
+        }
+    }
+
+    pub fn as_real(self) -> f64 {
+        match self {
+            Number::Integer(n) => n as f64,
+            Number::Real(n) => n,
+        }
+    }
+
+    //a real holding a whole number that fits in an i64 as that integer
+    fn integral(self) -> Self {
+        match self {
+            Number::Real(n) if n.fract() == 0.0 && (-9223372036854775808.0..9223372036854775808.0).contains(&n) => Number::Integer(n as i64),
+            number => number,
+        }
+    }
+
+    pub fn into_value(self) -> Value {
+        match self {
+            Number::Integer(n) => Value::Integer(n),
+            //NaN is what dividing by zero gives, and sqlite makes that NULL
+            Number::Real(n) if n.is_nan() => Value::Null,
+            Number::Real(n) => Value::Real(n),
+        }
+    }
+}
+
+//the number at the start of `text` and how many bytes of it make up the number, read the way
+//sqlite reads text used as a number: '12abc' is 12, '1.5e3x' is 1500.0 and 'abc' is 0
+fn leading_number(text: &str) -> (Number, usize) {
+    let start = text.len() - text.trim_start().len();
+    let bytes = text.as_bytes();
+    let digits_from = |start: usize| start + bytes[start..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+    let mut end = if matches!(bytes.get(start), Some(b'+' | b'-')) { start + 1 } else { start };
+    let integer_end = digits_from(end);
+    let mut digits = integer_end - end;
+    end = integer_end;
+    if bytes.get(end) == Some(&b'.') {
+        let fraction_end = digits_from(end + 1);
+        digits += fraction_end - end - 1;
+        end = fraction_end;
+    }
+    if digits == 0 {
+        return (Number::Integer(0), 0);
+    }
+    //an exponent only counts if it has digits
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = if matches!(bytes.get(end + 1), Some(b'+' | b'-')) { 1 } else { 0 };
+        let exponent_end = digits_from(end + 1 + sign);
+        if exponent_end > end + 1 + sign {
+            end = exponent_end;
+        }
+    }
+    if end == integer_end {
+        if let Ok(n) = text[start..end].parse::<i64>() {
+            return (Number::Integer(n), end);
+        }
+    }
+    (Number::Real(text[start..end].parse::<f64>().unwrap_or_default()), end)
+}
+
+//the integer at the start of `text`, ignoring anything after its digits: ' -3e2x' is -3;
+//integers too big for an i64 saturate, as they do in sqlite
+fn leading_integer(text: &str) -> i64 {
+    let text = text.trim_start();
+    let (negative, digits) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let mut n: i64 = 0;
+    for digit in digits.bytes().take_while(|byte| byte.is_ascii_digit()) {
+        let digit = (digit - b'0') as i64;
+        n = n.saturating_mul(10);
+        n = if negative { n.saturating_sub(digit) } else { n.saturating_add(digit) };
+    }
+    n
+}
+
+//the number `text` spells out, if all of it (ignoring surrounding spaces) is a number
+pub fn numeric_text(text: &str) -> Option<Number> {
+    let (number, end) = leading_number(text);
+    (end > 0 && text[end..].trim().is_empty()).then_some(number)
+}
+
+//the arithmetic and bitwise operators; integer results that overflow turn into reals,
+//and dividing by zero gives NaN, which becomes NULL
+pub fn arithmetic(left: Number, operator: BinaryOperator, right: Number) -> Number {
+    use BinaryOperator::*;
+    match (left, operator, right) {
+        (Number::Integer(a), Add | Subtract | Multiply, Number::Integer(b)) => {
+            let result = match operator {
+                Add => a.checked_add(b),
+                Subtract => a.checked_sub(b),
+                _ => a.checked_mul(b),
+            };
+            result.map(Number::Integer).unwrap_or_else(|| arithmetic(Number::Real(a as f64), operator, Number::Real(b as f64)))
+        },
+        (Number::Integer(a), Divide, Number::Integer(b)) => match a.checked_div(b) {
+            Some(n) => Number::Integer(n),
+            None if b == 0 => Number::Real(f64::NAN),
+            None => Number::Real(a as f64 / b as f64),
+        },
+        (_, Add, _) => Number::Real(left.as_real() + right.as_real()),
+        (_, Subtract, _) => Number::Real(left.as_real() - right.as_real()),
+        (_, Multiply, _) => Number::Real(left.as_real() * right.as_real()),
+        (_, Divide, _) if right.as_real() == 0.0 => Number::Real(f64::NAN),
+        (_, Divide, _) => Number::Real(left.as_real() / right.as_real()),
+        //the remainder is taken of the integer parts, and is real if either side was
+        (_, Modulo, _) => {
+            let (a, b) = (left.as_integer(), right.as_integer());
+            if b == 0 {
+                return Number::Real(f64::NAN);
+            }
+            let remainder = a.checked_rem(b).unwrap_or(0);
+            match (left, right) {
+                (Number::Integer(_), Number::Integer(_)) => Number::Integer(remainder),
+                _ => Number::Real(remainder as f64),
+            }
+        },
+        (_, BitAnd, _) => Number::Integer(left.as_integer() & right.as_integer()),
+        (_, BitOr, _) => Number::Integer(left.as_integer() | right.as_integer()),
+        (_, ShiftLeft | ShiftRight, _) => {
+            let (value, mut shift) = (left.as_integer(), right.as_integer());
+            //a negative shift goes the other way
+            let mut leftwards = operator == ShiftLeft;
+            if shift < 0 {
+                leftwards = !leftwards;
+                shift = shift.saturating_neg();
+            }
+            Number::Integer(match (leftwards, shift >= 64) {
+                (true, true) => 0,
+                (true, false) => value << shift,
+                (false, true) => if value < 0 { -1 } else { 0 },
+                (false, false) => value >> shift,
+            })
+        },
+        _ => unreachable!("{:?} is not an arithmetic operator", operator),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_format_real() {
+    assert_eq!(format_real(1.0), "1.0");
+    assert_eq!(format_real(0.1 + 0.2), "0.3");
+    assert_eq!(format_real(-2.5), "-2.5");
+    assert_eq!(format_real(1e20), "1.0e+20");
+    assert_eq!(format_real(1.5e-7), "1.5e-07");
+    assert_eq!(format_real(123456789012345.0), "123456789012345.0");
+    assert_eq!(format_real(1234567890123456.0), "1.23456789012346e+15");
+    assert_eq!(format_real(94.89911547561195), "94.899115475612");
+    assert_eq!(format_real(57.19682275786375), "57.1968227578637");
+    assert_eq!(format_real(9.999999999999999), "10.0");
+}
+
+#[cfg(test)]
+#[test]
+fn test_affinity_and_compare() {
+    assert!(matches!(Value::Text(" 12 ".into()).apply_affinity(Affinity::Integer), Value::Integer(12)));
+    assert!(matches!(Value::Text("3.0".into()).apply_affinity(Affinity::Numeric), Value::Integer(3)));
+    assert!(matches!(Value::Text("12abc".into()).apply_affinity(Affinity::Numeric), Value::Text(_)));
+    assert!(matches!(Value::Integer(5).apply_affinity(Affinity::Text), Value::Text(text) if text == "5"));
+
+    assert!(matches!(Value::Text("1e5".into()).cast(Affinity::Integer), Value::Integer(1)));
+    assert!(matches!(Value::Text("2.5e1".into()).cast(Affinity::Integer), Value::Integer(2)));
+    assert!(matches!(Value::Text(" -3e2x".into()).cast(Affinity::Integer), Value::Integer(-3)));
+    assert!(matches!(Value::Blob(b"42abc".to_vec()).cast(Affinity::Integer), Value::Integer(42)));
+    assert!(matches!(Value::Text("99999999999999999999".into()).cast(Affinity::Integer), Value::Integer(i64::MAX)));
+    assert!(matches!(Value::Real(-2.7).cast(Affinity::Integer), Value::Integer(-2)));
+
+    assert_eq!(Value::Null.compare(&Value::Integer(i64::MIN)), Ordering::Less);
+    assert_eq!(Value::Integer(2).compare(&Value::Real(2.5)), Ordering::Less);
+    assert_eq!(Value::Real(3.0).compare(&Value::Integer(3)), Ordering::Equal);
+    assert_eq!(Value::Integer(i64::MAX).compare(&Value::Real(9.3e18)), Ordering::Less);
+    assert_eq!(Value::Real(1e300).compare(&Value::Text("1".into())), Ordering::Less);
+    assert_eq!(Value::Text("b".into()).compare(&Value::Blob(vec![b'a'])), Ordering::Less);
+
+    assert_eq!(Value::Text("B".into()).compare(&Value::Text("a".into())), Ordering::Less);
+    assert_eq!(Value::Text("B".into()).compare_collated(&Value::Text("a".into()), Collation::NoCase), Ordering::Greater);
+    assert_eq!(Value::Text("a  ".into()).compare_collated(&Value::Text("a".into()), Collation::RTrim), Ordering::Equal);
+    assert!(Collation::from_name("NOCASE").is_ok() && Collation::from_name("french").is_err());
+}
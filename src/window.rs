@@ -0,0 +1,336 @@
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+
+use crate::aggregate::Accumulator;
+use crate::ast::{Affinity, BinaryOperator, FrameUnits, FunctionArgs};
+use crate::sort::{self, SortKey};
+use crate::value::{self, Value};
+
+//the functions that can only be called with OVER, and the aggregate functions when they're called with it
+#[derive(Debug, Clone)]
+pub enum WindowFunction {
+    RowNumber,
+    //the row number of the first of the row's peers, the rows with the same ORDER BY values
+    Rank,
+    //the number of the row's group of peers
+    DenseRank,
+    //(rank - 1) / (rows - 1)
+    PercentRank,
+    //the number of rows up to the row's last peer / rows
+    CumeDist,
+    //ntile(n), the number of the row's group when the rows are split into n groups of about the same size
+    Ntile,
+    //lag(x[, offset[, default]]), x on the row `offset` rows before, or the default when there's none
+    Lag,
+    //lead(x[, offset[, default]]), x on the row `offset` rows after
+    Lead,
+    FirstValue,
+    LastValue,
+    //nth_value(x, n), x on the nth row of the frame
+    NthValue,
+    //an aggregate of the rows of the frame, which starts from this accumulator
+    Aggregate(Accumulator),
+}
+
+impl WindowFunction {
+    //the function that can only be used as a window function a call names, if it names one
+    pub fn from_call(name: &str, args: &FunctionArgs) -> Result<Option<Self>> {
+        let arg_count = match args {
+            FunctionArgs::Star => 0,
+            FunctionArgs::List { args, .. } => args.len(),
+        };
+        let name = name.to_ascii_lowercase();
+        let (function, arity_ok) = match name.as_str() {
+            "row_number" => (WindowFunction::RowNumber, arg_count == 0),
+            "rank" => (WindowFunction::Rank, arg_count == 0),
+            "dense_rank" => (WindowFunction::DenseRank, arg_count == 0),
+            "percent_rank" => (WindowFunction::PercentRank, arg_count == 0),
+            "cume_dist" => (WindowFunction::CumeDist, arg_count == 0),
+            "ntile" => (WindowFunction::Ntile, arg_count == 1),
+            "lag" => (WindowFunction::Lag, (1..=3).contains(&arg_count)),
+            "lead" => (WindowFunction::Lead, (1..=3).contains(&arg_count)),
+            "first_value" => (WindowFunction::FirstValue, arg_count == 1),
+            "last_value" => (WindowFunction::LastValue, arg_count == 1),
+            "nth_value" => (WindowFunction::NthValue, arg_count == 2),
+            _ => return Ok(None),
+        };
+        if !arity_ok {
+            bail!("wrong number of arguments to function {}()", name);
+        }
+        Ok(Some(function))
+    }
+
+    //the function's value on each row of a partition, whose rows are in the window's order:
+    //`order_keys` are the rows' ORDER BY values and `args` the function's arguments on them
+    pub fn partition_values(&self, window: &Window, order_keys: &[Vec<Value>], args: &[Vec<Value>]) -> Result<Vec<Value>> {
+        let row_count = order_keys.len();
+        let peers = Peers::new(&window.sort_keys, order_keys);
+        let frame = |row: usize| window.frame(row, order_keys, &peers);
+        let arg = |row: usize, index: usize| args[row].get(index).cloned().unwrap_or(Value::Null);
+        let mut values = Vec::with_capacity(row_count);
+        match self {
+            WindowFunction::Aggregate(empty) => {
+                //frames that start at the partition's first row only grow, so one accumulator is kept for them
+                let mut running = empty.clone();
+                let mut added = 0;
+                let mut previous: Option<((usize, usize), Value)> = None;
+                for row in 0..row_count {
+                    let (start, end) = frame(row);
+                    if let Some(((previous_start, previous_end), value)) = &previous {
+                        if (*previous_start, *previous_end) == (start, end) {
+                            values.push(value.clone());
+                            continue;
+                        }
+                    }
+                    let value = if start == 0 && end >= added {
+                        for args in &args[added..end] {
+                            running.step(args);
+                        }
+                        added = end;
+                        running.finish()?
+                    } else {
+                        let mut accumulator = empty.clone();
+                        for args in args.get(start..end).unwrap_or_default() {
+                            accumulator.step(args);
+                        }
+                        accumulator.finish()?
+                    };
+                    previous = Some(((start, end), value.clone()));
+                    values.push(value);
+                }
+            },
+            function => for row in 0..row_count {
+                let group = peers.group[row];
+                let value = match function {
+                    WindowFunction::RowNumber => Value::Integer(row as i64 + 1),
+                    WindowFunction::Rank => Value::Integer(peers.starts[group] as i64 + 1),
+                    WindowFunction::DenseRank => Value::Integer(group as i64 + 1),
+                    WindowFunction::PercentRank if row_count > 1 => Value::Real(peers.starts[group] as f64 / (row_count - 1) as f64),
+                    WindowFunction::PercentRank => Value::Real(0.0),
+                    WindowFunction::CumeDist => Value::Real(peers.end(group) as f64 / row_count as f64),
+                    WindowFunction::Ntile => {
+                        let buckets = match arg(row, 0) {
+                            Value::Integer(buckets) if buckets > 0 => buckets as usize,
+                            _ => bail!("argument of ntile must be a positive integer"),
+                        };
+                        //the first `row_count % buckets` groups have a row more than the others
+                        let size = row_count / buckets;
+                        let larger = row_count % buckets;
+                        let bucket = match row < larger * (size + 1) {
+                            true => row / (size + 1),
+                            false => larger + (row - larger * (size + 1)) / size.max(1),
+                        };
+                        Value::Integer(bucket as i64 + 1)
+                    },
+                    WindowFunction::Lag | WindowFunction::Lead => {
+                        let offset = match args[row].get(1) {
+                            Some(offset) => offset.cast(Affinity::Integer).as_integer().unwrap_or(0),
+                            None => 1,
+                        };
+                        let offset = if matches!(function, WindowFunction::Lag) { offset.saturating_neg() } else { offset };
+                        match (row as i64).checked_add(offset).and_then(|other| usize::try_from(other).ok()) {
+                            Some(other) if other < row_count => arg(other, 0),
+                            _ => arg(row, 2),
+                        }
+                    },
+                    WindowFunction::FirstValue => match frame(row) {
+                        (start, end) if start < end => arg(start, 0),
+                        _ => Value::Null,
+                    },
+                    WindowFunction::LastValue => match frame(row) {
+                        (start, end) if start < end => arg(end - 1, 0),
+                        _ => Value::Null,
+                    },
+                    WindowFunction::NthValue => {
+                        let n = match arg(row, 1) {
+                            Value::Integer(n) if n > 0 => n as usize,
+                            _ => bail!("second argument to nth_value must be a positive integer"),
+                        };
+                        match frame(row) {
+                            (start, end) if start.saturating_add(n - 1) < end => arg(start + n - 1, 0),
+                            _ => Value::Null,
+                        }
+                    },
+                    WindowFunction::Aggregate(_) => unreachable!("aggregates are computed over their frames above"),
+                };
+                values.push(value);
+            },
+        }
+        Ok(values)
+    }
+}
+
+//a frame bound with its offset evaluated
+#[derive(Debug, Clone)]
+pub enum Bound {
+    UnboundedPreceding,
+    Preceding(Value),
+    CurrentRow,
+    Following(Value),
+    UnboundedFollowing,
+}
+
+//how a window function sees the rows of its partition: their order, and the frame of each row
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub sort_keys: Vec<SortKey>,
+    pub units: FrameUnits,
+    pub start: Bound,
+    pub end: Bound,
+}
+
+impl Window {
+    //a window whose frame offsets have been evaluated, which have to be non-negative integers
+    //(or numbers, for RANGE)
+    pub fn new(sort_keys: Vec<SortKey>, units: FrameUnits, start: Bound, end: Bound) -> Result<Self> {
+        for (bound, which) in [(&start, "starting"), (&end, "ending")] {
+            let (Bound::Preceding(offset) | Bound::Following(offset)) = bound else {
+                continue;
+            };
+            match (units, offset) {
+                (_, Value::Integer(offset)) if *offset >= 0 => {},
+                (FrameUnits::Range, Value::Real(offset)) if *offset >= 0.0 => {},
+                (FrameUnits::Range, _) => bail!("frame {} offset must be a non-negative number", which),
+                _ => bail!("frame {} offset must be a non-negative integer", which),
+            }
+        }
+        Ok(Window { sort_keys, units, start, end })
+    }
+
+    //the rows of the frame of `row`, from the first to one past the last; it's empty when the start is past the end
+    fn frame(&self, row: usize, order_keys: &[Vec<Value>], peers: &Peers) -> (usize, usize) {
+        let row_count = order_keys.len();
+        let group = peers.group[row];
+        let start = match (&self.start, self.units) {
+            (Bound::UnboundedPreceding, _) => 0,
+            (Bound::UnboundedFollowing, _) => row_count,
+            (Bound::CurrentRow, FrameUnits::Rows) => row,
+            (Bound::CurrentRow, _) => peers.starts[group],
+            (Bound::Preceding(offset), FrameUnits::Rows) => row.saturating_sub(count(offset)),
+            (Bound::Following(offset), FrameUnits::Rows) => row.saturating_add(count(offset)),
+            (Bound::Preceding(offset), FrameUnits::Groups) => peers.starts[group.saturating_sub(count(offset))],
+            (Bound::Following(offset), FrameUnits::Groups) => peers.starts.get(group.saturating_add(count(offset))).copied().unwrap_or(row_count),
+            //the first row that doesn't sort before the row's value minus the offset
+            (Bound::Preceding(offset) | Bound::Following(offset), FrameUnits::Range) => {
+                let bound = self.range_bound(&order_keys[row], offset, matches!(self.start, Bound::Preceding(_)));
+                order_keys.partition_point(|keys| sort::compare_keys(&self.sort_keys, keys, &bound) == Ordering::Less)
+            },
+        };
+        let end = match (&self.end, self.units) {
+            (Bound::UnboundedPreceding, _) => 0,
+            (Bound::UnboundedFollowing, _) => row_count,
+            (Bound::CurrentRow, FrameUnits::Rows) => row + 1,
+            (Bound::CurrentRow, _) => peers.end(group),
+            (Bound::Preceding(offset), FrameUnits::Rows) => (row + 1).saturating_sub(count(offset)),
+            (Bound::Following(offset), FrameUnits::Rows) => (row + 1).saturating_add(count(offset)),
+            (Bound::Preceding(offset), FrameUnits::Groups) => match group.checked_sub(count(offset)) {
+                Some(group) => peers.end(group),
+                None => 0,
+            },
+            (Bound::Following(offset), FrameUnits::Groups) => peers.end(group.saturating_add(count(offset)).min(peers.starts.len() - 1)),
+            //one past the last row that doesn't sort after the row's value plus the offset
+            (Bound::Preceding(offset) | Bound::Following(offset), FrameUnits::Range) => {
+                let bound = self.range_bound(&order_keys[row], offset, matches!(self.end, Bound::Preceding(_)));
+                order_keys.partition_point(|keys| sort::compare_keys(&self.sort_keys, keys, &bound) != Ordering::Greater)
+            },
+        };
+        (start.min(row_count), end.min(row_count))
+    }
+
+    //the ORDER BY value `offset` before or after a row's in the window's order (NULL stays NULL, and text
+    //and blobs, which have no values before or after them, stay the same)
+    fn range_bound(&self, keys: &[Value], offset: &Value, preceding: bool) -> Vec<Value> {
+        let descending = self.sort_keys.first().is_some_and(|key| key.descending);
+        let operator = if preceding != descending { BinaryOperator::Subtract } else { BinaryOperator::Add };
+        let bound = match &keys[0] {
+            key @ (Value::Integer(_) | Value::Real(_)) => value::arithmetic(key.to_number(), operator, offset.to_number()).into_value(),
+            key => key.clone(),
+        };
+        vec![bound]
+    }
+}
+
+//an offset of ROWS or GROUPS, which has been checked to be a non-negative integer
+fn count(offset: &Value) -> usize {
+    offset.as_integer().map_or(0, |offset| offset as usize)
+}
+
+//the groups of peers of a partition: consecutive rows with the same ORDER BY values, which are all
+//the rows when there's no ORDER BY
+struct Peers {
+    //the first row of each group
+    starts: Vec<usize>,
+    //the group of each row
+    group: Vec<usize>,
+    row_count: usize,
+}
+
+impl Peers {
+    fn new(sort_keys: &[SortKey], order_keys: &[Vec<Value>]) -> Self {
+        let mut starts = Vec::new();
+        let mut group = Vec::with_capacity(order_keys.len());
+        for (row, keys) in order_keys.iter().enumerate() {
+            if row == 0 || sort::compare_keys(sort_keys, &order_keys[row - 1], keys) != Ordering::Equal {
+                starts.push(row);
+            }
+            group.push(starts.len() - 1);
+        }
+        Peers { starts, group, row_count: order_keys.len() }
+    }
+
+    //one past the last row of a group
+    fn end(&self, group: usize) -> usize {
+        self.starts.get(group + 1).copied().unwrap_or(self.row_count)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_window_functions() {
+    use crate::aggregate::AggregateFunction;
+    use crate::value::Collation;
+
+    let integers = |values: &[i64]| values.iter().map(|value| vec![Value::Integer(*value)]).collect::<Vec<_>>();
+    let ascending = vec![SortKey { descending: false, nulls_first: true, collation: Collation::Binary }];
+    let window = |units, start, end| Window::new(ascending.clone(), units, start, end).unwrap();
+    let default = window(FrameUnits::Range, Bound::UnboundedPreceding, Bound::CurrentRow);
+    let order_keys = integers(&[1, 2, 2, 3, 5]);
+    let run = |function: WindowFunction, window: &Window, args: Vec<Vec<Value>>| {
+        function.partition_values(window, &order_keys, &args).unwrap().into_iter().map(|value| value.to_string()).collect::<Vec<_>>().join(",")
+    };
+    let no_args = || vec![vec![]; 5];
+
+    assert_eq!(run(WindowFunction::RowNumber, &default, no_args()), "1,2,3,4,5");
+    assert_eq!(run(WindowFunction::Rank, &default, no_args()), "1,2,2,4,5");
+    assert_eq!(run(WindowFunction::DenseRank, &default, no_args()), "1,2,2,3,4");
+    assert_eq!(run(WindowFunction::PercentRank, &default, no_args()), "0.0,0.25,0.25,0.75,1.0");
+    assert_eq!(run(WindowFunction::CumeDist, &default, no_args()), "0.2,0.6,0.6,0.8,1.0");
+    assert_eq!(run(WindowFunction::Ntile, &default, integers(&[2; 5])), "1,1,1,2,2");
+    assert_eq!(run(WindowFunction::Ntile, &default, integers(&[7; 5])), "1,2,3,4,5");
+    let with_offset = |offset| order_keys.iter().map(|keys| vec![keys[0].clone(), Value::Integer(offset), Value::Integer(0)]).collect();
+    assert_eq!(run(WindowFunction::Lag, &default, integers(&[1, 2, 2, 3, 5])), "null,1,2,2,3");
+    assert_eq!(run(WindowFunction::Lead, &default, with_offset(2)), "2,3,5,0,0");
+    assert_eq!(run(WindowFunction::Lag, &default, with_offset(-1)), "2,2,3,5,0");
+
+    //the default frame ends at the last peer, while ROWS frames count rows
+    let sum = || WindowFunction::Aggregate(Accumulator::new(AggregateFunction::Sum, false, Collation::Binary));
+    assert_eq!(run(sum(), &default, order_keys.clone()), "1,5,5,8,13");
+    let rows = window(FrameUnits::Rows, Bound::Preceding(Value::Integer(1)), Bound::Following(Value::Integer(1)));
+    assert_eq!(run(sum(), &rows, order_keys.clone()), "3,5,7,10,8");
+    let groups = window(FrameUnits::Groups, Bound::CurrentRow, Bound::Following(Value::Integer(1)));
+    assert_eq!(run(sum(), &groups, order_keys.clone()), "5,7,7,8,5");
+    let range = window(FrameUnits::Range, Bound::Preceding(Value::Integer(1)), Bound::CurrentRow);
+    assert_eq!(run(sum(), &range, order_keys.clone()), "1,5,5,7,5");
+    let empty = window(FrameUnits::Rows, Bound::Following(Value::Integer(1)), Bound::Following(Value::Integer(1)));
+    assert_eq!(run(sum(), &empty, order_keys.clone()), "2,2,3,5,null");
+    let whole = window(FrameUnits::Rows, Bound::UnboundedPreceding, Bound::UnboundedFollowing);
+    assert_eq!(run(WindowFunction::LastValue, &whole, order_keys.clone()), "5,5,5,5,5");
+    assert_eq!(run(WindowFunction::FirstValue, &range, order_keys.clone()), "1,1,1,2,5");
+    let nth = order_keys.iter().map(|keys| vec![keys[0].clone(), Value::Integer(2)]).collect();
+    assert_eq!(run(WindowFunction::NthValue, &default, nth), "null,2,2,2,2");
+
+    assert!(Window::new(ascending.clone(), FrameUnits::Rows, Bound::Preceding(Value::Real(1.5)), Bound::CurrentRow).is_err());
+    assert!(Window::new(ascending.clone(), FrameUnits::Range, Bound::Preceding(Value::Real(1.5)), Bound::CurrentRow).is_ok());
+    assert!(WindowFunction::Ntile.partition_values(&default, &order_keys, &integers(&[0; 5])).is_err());
+    assert!(WindowFunction::from_call("lag", &FunctionArgs::List { distinct: false, args: vec![] }).is_err());
+}